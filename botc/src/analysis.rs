@@ -0,0 +1,373 @@
+use std::collections::VecDeque;
+
+use crate::{
+    code::{Command, CommandArg, CommandWord, Label, Mem, Opcode, Word},
+    isa::InstructionSet,
+};
+
+/// Depth of the call stack the analysis follows when the stack size is not known
+pub const DEFAULT_STACK_SIZE: usize = 64;
+
+/// Limit of the states and transitions of the control flow analysis, bigger genomes
+/// get `Issue::AnalysisIncomplete` instead of the flow issues, so that checking
+/// an untrusted genome stays cheap
+pub const MAX_ANALYSIS_STEPS: usize = 1 << 20;
+
+/// Control flow of one instruction
+pub(crate) enum Flow {
+    /// execution continues with the next instruction
+    Next,
+    /// unconditional jump
+    Jump(Label),
+    /// conditional jump
+    Branch(Label),
+    /// subroutine call, execution continues with the next instruction after the return
+    Call(Label),
+    /// return from subroutine
+    Ret,
+    /// the new bot starts execution from the label
+    Spawn(Label),
+}
+
+pub(crate) fn flow(cmd: &Command) -> Flow {
+    match cmd {
+        Command::Jmp(l) => Flow::Jump(*l),
+        Command::Jme(l)
+        | Command::Jne(l)
+        | Command::Jmg(l)
+        | Command::Jml(l)
+        | Command::Jle(l)
+        | Command::Jge(l)
+        | Command::Jmo(l)
+        | Command::Jno(l)
+        | Command::Jmb(l)
+        | Command::Jnb(l)
+        | Command::Jmc(l)
+        | Command::Jnc(l)
+        | Command::Jmf(l)
+        | Command::Jnf(l) => Flow::Branch(*l),
+        Command::Call(l) => Flow::Call(*l),
+        Command::Ret => Flow::Ret,
+        Command::Split(_, l) | Command::Fork(_, l) | Command::Mate(_, l) => Flow::Spawn(*l),
+        _ => Flow::Next,
+    }
+}
+
+/// Whether the builtin command ends the cycle of the bot, custom instructions
+/// declare it in their `InstructionSpec`
+pub fn is_action(cmd: &Command) -> bool {
+    match cmd.word() {
+        Word::Builtin(word) => is_action_word(word),
+        Word::Custom(_) => false,
+    }
+}
+
+pub(crate) fn is_action_word(word: CommandWord) -> bool {
+    matches!(
+        word,
+        CommandWord::Nop
+            | CommandWord::Mov
+            | CommandWord::Rot
+            | CommandWord::Chk
+            | CommandWord::Split
+            | CommandWord::Fork
+            | CommandWord::Mate
+            | CommandWord::Bite
+            | CommandWord::Eatsun
+            | CommandWord::Absorb
+    )
+}
+
+/// Sizes of the bot the genome is checked for, unknown ones are not checked
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    pub ram_size: Option<usize>,
+    /// `DEFAULT_STACK_SIZE` if not set, overflows are not reported then
+    pub stack_size: Option<usize>,
+}
+
+/// Problem found in the genome
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Issue {
+    /// the genome has no instructions
+    Empty,
+    /// the label is out of the genome
+    InvalidLabel { index: usize, label: Label },
+    /// the address is out of the RAM
+    MemOutOfRange { index: usize, addr: Mem },
+    /// the custom instruction is not in the instruction set
+    UnknownInstruction { index: usize, opcode: Opcode },
+    /// the arguments of the custom instruction do not match its spec
+    InvalidArguments { index: usize },
+    /// instructions `start..=end` are never executed
+    Unreachable { start: usize, end: usize },
+    /// once instructions `start..=end` are executed, no action command is ever reached,
+    /// so every cycle of the bot burns `max_commands_per_cycle`
+    IdleLoop { start: usize, end: usize },
+    /// `ret` may be executed with an empty stack, it sets `fo` and goes on
+    RetWithoutCall { index: usize },
+    /// `call` may overflow the stack, it sets `fo` and goes on
+    StackOverflow { index: usize },
+    /// the genome is too big for the control flow analysis, see `MAX_ANALYSIS_STEPS`
+    AnalysisIncomplete,
+}
+
+impl Issue {
+    /// Errors make the bot VM fail, the rest are likely mistakes in the code
+    pub fn is_error(&self) -> bool {
+        matches!(
+            self,
+            Issue::Empty
+                | Issue::InvalidLabel { .. }
+                | Issue::MemOutOfRange { .. }
+                | Issue::UnknownInstruction { .. }
+                | Issue::InvalidArguments { .. }
+        )
+    }
+
+    /// Index of the first instruction with the issue
+    pub fn index(&self) -> usize {
+        match *self {
+            Issue::Empty | Issue::AnalysisIncomplete => 0,
+            Issue::InvalidLabel { index, .. }
+            | Issue::MemOutOfRange { index, .. }
+            | Issue::UnknownInstruction { index, .. }
+            | Issue::InvalidArguments { index }
+            | Issue::RetWithoutCall { index }
+            | Issue::StackOverflow { index } => index,
+            Issue::Unreachable { start, .. } | Issue::IdleLoop { start, .. } => start,
+        }
+    }
+}
+
+impl std::fmt::Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = if self.is_error() { "error" } else { "warning" };
+        match *self {
+            Issue::Empty => write!(f, "{kind}: empty genome"),
+            Issue::InvalidLabel { index, label } => {
+                write!(f, "{kind}: {index:04}: label {label} is out of the genome")
+            }
+            Issue::MemOutOfRange { index, addr } => {
+                write!(f, "{kind}: {index:04}: address [{addr}] is out of the RAM")
+            }
+            Issue::UnknownInstruction { index, opcode } => {
+                write!(f, "{kind}: {index:04}: unknown instruction op{opcode}")
+            }
+            Issue::InvalidArguments { index } => {
+                write!(
+                    f,
+                    "{kind}: {index:04}: arguments do not match the instruction"
+                )
+            }
+            Issue::Unreachable { start, end } => {
+                write!(f, "{kind}: {start:04}-{end:04}: unreachable instructions")
+            }
+            Issue::IdleLoop { start, end } => write!(
+                f,
+                "{kind}: {start:04}-{end:04}: loop without an action command"
+            ),
+            Issue::RetWithoutCall { index } => {
+                write!(f, "{kind}: {index:04}: ret may be executed without a call")
+            }
+            Issue::StackOverflow { index } => {
+                write!(f, "{kind}: {index:04}: call may overflow the stack")
+            }
+            Issue::AnalysisIncomplete => write!(
+                f,
+                "{kind}: analysis incomplete, the genome is too big to follow its flow"
+            ),
+        }
+    }
+}
+
+/// Control flow graph of the genome. Nodes are pairs of an instruction and the depth
+/// of the call stack, so returns go only to the calls that could be made before.
+struct Cfg {
+    len: usize,
+    stack_size: usize,
+    // instructions after the calls
+    ret_sites: Vec<usize>,
+}
+
+impl Cfg {
+    fn new(code: &[Command], stack_size: usize) -> Self {
+        let len = code.len();
+        let ret_sites = code
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| matches!(flow(c), Flow::Call(_)))
+            .map(|(i, _)| (i + 1) % len)
+            .collect();
+        Self {
+            len,
+            stack_size,
+            ret_sites,
+        }
+    }
+
+    fn node(&self, i: usize, depth: usize) -> usize {
+        depth * self.len + i
+    }
+
+    /// Successors of the instruction executed at the stack depth, labels out of
+    /// the genome are skipped
+    fn successors(&self, cmd: &Command, i: usize, depth: usize) -> Vec<(usize, usize)> {
+        let next = (i + 1) % self.len;
+        let valid = |l: Label| l < self.len;
+        match flow(cmd) {
+            Flow::Next => vec![(next, depth)],
+            Flow::Jump(l) => [(l, depth)].into_iter().filter(|n| valid(n.0)).collect(),
+            Flow::Branch(l) | Flow::Spawn(l) => [(next, depth), (l, depth)]
+                .into_iter()
+                .filter(|n| valid(n.0))
+                .collect(),
+            Flow::Call(l) if depth < self.stack_size && valid(l) => vec![(l, depth + 1)],
+            Flow::Call(_) => vec![(next, depth)],
+            Flow::Ret if depth == 0 => vec![(next, depth)],
+            Flow::Ret => self.ret_sites.iter().map(|s| (*s, depth - 1)).collect(),
+        }
+    }
+}
+
+/// Checks the genome, the issues are sorted by the instruction
+pub fn analyze(code: &[Command], limits: &Limits) -> Vec<Issue> {
+    analyze_with_isa(code, limits, &InstructionSet::builtin())
+}
+
+/// Checks the genome that may use the custom instructions of the set
+pub fn analyze_with_isa(code: &[Command], limits: &Limits, isa: &InstructionSet) -> Vec<Issue> {
+    let len = code.len();
+    if len == 0 {
+        return vec![Issue::Empty];
+    }
+    let mut issues = Vec::new();
+
+    for (index, c) in code.iter().enumerate() {
+        if let Command::Ext(opcode, args) = c {
+            match isa.spec(c.word()) {
+                None => issues.push(Issue::UnknownInstruction {
+                    index,
+                    opcode: *opcode,
+                }),
+                Some(spec) if !args.iter().map(|a| a.kind()).eq(spec.args.iter().copied()) => {
+                    issues.push(Issue::InvalidArguments { index })
+                }
+                _ => {}
+            }
+        }
+        for a in c.args() {
+            match a {
+                CommandArg::Label(label) if label >= len => {
+                    issues.push(Issue::InvalidLabel { index, label })
+                }
+                CommandArg::Mem(addr)
+                    if limits.ram_size.is_some_and(|size| addr >= size as Mem) =>
+                {
+                    issues.push(Issue::MemOutOfRange { index, addr })
+                }
+                _ => {}
+            }
+        }
+    }
+
+    match flow_issues(code, limits, isa) {
+        Some(found) => issues.extend(found),
+        None => issues.push(Issue::AnalysisIncomplete),
+    }
+    issues.sort_by_key(|i| i.index());
+    issues
+}
+
+/// Issues of the control flow, None if the analysis does not fit `MAX_ANALYSIS_STEPS`
+fn flow_issues(code: &[Command], limits: &Limits, isa: &InstructionSet) -> Option<Vec<Issue>> {
+    let len = code.len();
+    let mut issues = Vec::new();
+
+    // reachability of the (instruction, depth) states from the start
+    let stack_size = limits.stack_size.unwrap_or(DEFAULT_STACK_SIZE);
+    let nodes = stack_size
+        .checked_add(1)
+        .and_then(|d| d.checked_mul(len))
+        .filter(|n| *n <= MAX_ANALYSIS_STEPS)?;
+    let cfg = Cfg::new(code, stack_size);
+    let mut edges = 0;
+    let mut reached = vec![false; nodes];
+    let mut preds: Vec<Vec<usize>> = vec![Vec::new(); nodes];
+    let mut queue = VecDeque::from([(0, 0)]);
+    reached[0] = true;
+    let mut ret_without_call = vec![false; len];
+    let mut overflow = vec![false; len];
+    while let Some((i, depth)) = queue.pop_front() {
+        match flow(&code[i]) {
+            Flow::Ret if depth == 0 => ret_without_call[i] = true,
+            Flow::Call(l) if depth >= stack_size && l < len => overflow[i] = true,
+            _ => {}
+        }
+        let successors = cfg.successors(&code[i], i, depth);
+        edges += successors.len();
+        if edges > MAX_ANALYSIS_STEPS {
+            return None;
+        }
+        for (j, d) in successors {
+            let n = cfg.node(j, d);
+            preds[n].push(cfg.node(i, depth));
+            if !reached[n] {
+                reached[n] = true;
+                queue.push_back((j, d));
+            }
+        }
+    }
+
+    // states that lead to an action command
+    let mut active = vec![false; nodes];
+    let mut queue: VecDeque<usize> = (0..nodes)
+        .filter(|n| reached[*n] && isa.spec(code[n % len].word()).is_some_and(|s| s.action))
+        .collect();
+    queue.iter().for_each(|n| active[*n] = true);
+    while let Some(n) = queue.pop_front() {
+        for p in preds[n].iter() {
+            if !active[*p] {
+                active[*p] = true;
+                queue.push_back(*p);
+            }
+        }
+    }
+
+    let mut executed = vec![false; len];
+    let mut idle = vec![false; len];
+    for n in (0..nodes).filter(|n| reached[*n]) {
+        executed[n % len] = true;
+        idle[n % len] |= !active[n];
+    }
+    issues.extend(
+        ranges(&executed.iter().map(|e| !e).collect::<Vec<_>>())
+            .map(|(start, end)| Issue::Unreachable { start, end }),
+    );
+    issues.extend(ranges(&idle).map(|(start, end)| Issue::IdleLoop { start, end }));
+    issues.extend(
+        (0..len)
+            .filter(|i| ret_without_call[*i])
+            .map(|index| Issue::RetWithoutCall { index }),
+    );
+    // overflows are expected only for the known stack
+    if limits.stack_size.is_some() {
+        issues.extend(
+            (0..len)
+                .filter(|i| overflow[*i])
+                .map(|index| Issue::StackOverflow { index }),
+        );
+    }
+    Some(issues)
+}
+
+/// Ranges of the consecutive set flags
+fn ranges(flags: &[bool]) -> impl Iterator<Item = (usize, usize)> + '_ {
+    let mut i = 0;
+    std::iter::from_fn(move || {
+        let start = i + flags[i..].iter().position(|f| *f)?;
+        let end = start + flags[start..].iter().take_while(|f| **f).count() - 1;
+        i = end + 1;
+        Some((start, end))
+    })
+}
@@ -3,16 +3,71 @@ use anyhow::{bail, Error};
 use super::{Dir, Label, Mem, Reg, RwReg, Val};
 use crate::{
     decl_tokens_enum,
+    diagnostic::{Diagnostic, ErrorCode},
     token::{FromTokenStream, TokenStream},
 };
 
 pub(crate) const COMMAND_REGEX: &str = "^[a-zA-Z]*$";
 
+/// Version of the instruction set, stored in the packed genomes. Every command has
+/// an opcode pinned in the list of commands, it never changes, so the codes of
+/// the older versions stay valid.
+/// Version 2 added `mate`, version 3 added custom instructions.
+pub const ISA_VERSION: u16 = 3;
+
+/// Stable number of an instruction, see `crate::isa`
+pub type Opcode = u16;
+
+/// Name of a command, the custom ones are known by their opcodes
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Word {
+    Builtin(CommandWord),
+    Custom(Opcode),
+}
+
+impl Word {
+    pub fn opcode(self) -> Opcode {
+        match self {
+            Word::Builtin(w) => w.opcode(),
+            Word::Custom(opcode) => opcode,
+        }
+    }
+}
+
+impl From<CommandWord> for Word {
+    fn from(value: CommandWord) -> Self {
+        Word::Builtin(value)
+    }
+}
+
+impl std::fmt::Display for Word {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Word::Builtin(w) => write!(f, "{w}"),
+            Word::Custom(opcode) => write!(f, "op{opcode}"),
+        }
+    }
+}
+
+impl std::str::FromStr for Word {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(w) = s.parse::<CommandWord>() {
+            return Ok(Word::Builtin(w));
+        }
+        s.to_lowercase()
+            .strip_prefix("op")
+            .and_then(|op| op.parse::<Opcode>().ok())
+            .map(Word::Custom)
+            .ok_or_else(|| format!("Failed to parse \"{}\" as command", s))
+    }
+}
+
 macro_rules! decl_command_enum {
     (PossibleArgs: ($($pargs:ident),*)
-     Commands: $(($str_name:literal, $enum_entry:ident $(, $($args:ident),*)?)),*) => {
+     Commands: $(($opcode:literal, $str_name:literal, $enum_entry:ident $(, $($args:ident),*)?)),*) => {
 
-        #[derive(Debug, serde::Serialize, serde::Deserialize, Eq, PartialEq)]
+        #[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Eq, PartialEq)]
         pub enum CommandArg {
             $($pargs($pargs)),*
         }
@@ -25,38 +80,98 @@ macro_rules! decl_command_enum {
             }
         }
 
+        impl CommandArg {
+            pub fn kind(&self) -> ArgKind {
+                match self {
+                    $(CommandArg::$pargs(_) => ArgKind::$pargs),*
+                }
+            }
+        }
+
+        /// Type of a command argument
+        #[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Copy, Eq, PartialEq)]
+        #[serde(rename_all = "lowercase")]
+        pub enum ArgKind {
+            $($pargs),*
+        }
+
+        impl ArgKind {
+            pub(crate) fn parse(self, toks: &mut TokenStream) -> Result<CommandArg, Diagnostic> {
+                Ok(match self {
+                    $(ArgKind::$pargs => CommandArg::$pargs(<$pargs>::from_toks(toks)?)),*
+                })
+            }
+        }
+
         decl_tokens_enum! {
             CommandWord,
             $(($str_name, $enum_entry)),*
         }
 
+        impl CommandWord {
+            pub const ALL: &'static [CommandWord] = &[$(CommandWord::$enum_entry),*];
+
+            /// Stable number of the command, pinned in the list of commands
+            pub fn opcode(self) -> Opcode {
+                match self {
+                    $(CommandWord::$enum_entry => $opcode),*
+                }
+            }
+
+            pub fn from_opcode(opcode: Opcode) -> Option<Self> {
+                match opcode {
+                    $($opcode => Some(CommandWord::$enum_entry),)*
+                    _ => None,
+                }
+            }
+
+            /// Types of the arguments in the order they are written
+            pub fn arg_kinds(self) -> Vec<ArgKind> {
+                match self {
+                    $(CommandWord::$enum_entry => vec![$($(ArgKind::$args),*)?]),*
+                }
+            }
+        }
+
         #[derive(Debug)]
         pub struct Expr {
-            pub cmd: CommandWord,
+            pub cmd: Word,
             pub args: Vec<CommandArg>
         }
 
         impl FromTokenStream for Expr {
-            fn from_toks(toks: &mut TokenStream) -> anyhow::Result<Expr> {
-                let cmd = CommandWord::from_toks(toks)?;
+            fn from_toks(toks: &mut TokenStream) -> Result<Expr, Diagnostic> {
+                let (cmd_tok, _) = toks.peek()?;
+                let cmd = CommandWord::from_toks(toks).map_err(|d| {
+                    Diagnostic::new(
+                        ErrorCode::UnknownCommand,
+                        format!("Unknown command \"{}\"", cmd_tok.orign_string),
+                        d.span,
+                    )
+                })?;
                 let args = match cmd {
                     $(CommandWord::$enum_entry =>
                         vec![$($(CommandArg::$args(<$args>::from_toks(toks)?)),*)?]),*
                 };
-                Ok(Expr{cmd, args})
+                Ok(Expr{cmd: Word::Builtin(cmd), args})
             }
         }
 
+        /// The serde form names the variants and is used by the world snapshots,
+        /// the packed genomes store the opcodes, see `crate::code_packer`
         #[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Eq, PartialEq)]
         pub enum Command {
-            $($enum_entry$(($($args),*))?),*
+            $($enum_entry$(($($args),*))?),*,
+            /// custom instruction with its opcode and arguments
+            Ext(Opcode, Vec<CommandArg>)
         }
 
         impl TryFrom<Expr> for Command {
             type Error = anyhow::Error;
             fn try_from(value: Expr) -> Result<Self, Error> {
                 match value.cmd {
-                    $(CommandWord::$enum_entry => {
+                    Word::Custom(opcode) => Ok(Command::Ext(opcode, value.args)),
+                    $(Word::Builtin(CommandWord::$enum_entry) => {
                         let mut _iter = value.args.into_iter();
                         Ok(Command::$enum_entry$((
                             $(if let Some(CommandArg::$args(arg)) = _iter.next() {
@@ -74,11 +189,35 @@ macro_rules! decl_command_enum {
             type Error = anyhow::Error;
             fn try_from(value: Command) -> Result<Self, Error> {
                 $(Command_to_Expr_convertor!{$enum_entry, value, $($($args),*)?})*
+                if let Command::Ext(opcode, args) = value {
+                    return Ok(Expr { cmd: Word::Custom(opcode), args });
+                }
                 Err(anyhow::Error::msg("Failed to convert Command to Expr"))
             }
         }
 
         impl Command {
+            pub fn word(&self) -> Word {
+                match self {
+                    $(Command::$enum_entry { .. } => Word::Builtin(CommandWord::$enum_entry)),*,
+                    Command::Ext(opcode, _) => Word::Custom(*opcode),
+                }
+            }
+
+            /// Arguments of the command in the order they are written
+            pub fn args(&self) -> Vec<CommandArg> {
+                Expr::try_from(self.clone()).map(|e| e.args).unwrap_or_default()
+            }
+
+            /// The same command with other arguments, None if their types do not fit
+            pub fn with_args(&self, args: Vec<CommandArg>) -> Option<Command> {
+                let old = self.args();
+                if args.len() != old.len() || args.iter().zip(old.iter()).any(|(a, o)| a.kind() != o.kind()) {
+                    return None;
+                }
+                Command::try_from(Expr { cmd: self.word(), args }).ok()
+            }
+
             pub fn rand<R: rand::Rng + ?Sized>(rng: &mut R, len: usize, max_val: isize, max_mem: isize) -> Command {
                 let cmd: CommandWord = rng.gen();
                 Command::try_from(
@@ -97,7 +236,7 @@ macro_rules! decl_command_enum {
                                     }
                                 });)*)*
                                 Expr {
-                                    cmd: CommandWord::$enum_entry,
+                                    cmd: Word::Builtin(CommandWord::$enum_entry),
                                     args
                                 }
                             }
@@ -113,7 +252,7 @@ macro_rules! Command_to_Expr_convertor {
     ($enum_entry:ident, $value:ident, ) => {
         if let Command::$enum_entry {} = $value {
             return Ok(Expr {
-                cmd: CommandWord::$enum_entry,
+                cmd: Word::Builtin(CommandWord::$enum_entry),
                 args: vec![],
             });
         }
@@ -121,7 +260,7 @@ macro_rules! Command_to_Expr_convertor {
     ($enum_entry:ident, $value:ident, $args1:ident) => {
         if let Command::$enum_entry(a) = $value {
             return Ok(Expr {
-                cmd: CommandWord::$enum_entry,
+                cmd: Word::Builtin(CommandWord::$enum_entry),
                 args: vec![CommandArg::$args1(a)],
             });
         }
@@ -129,7 +268,7 @@ macro_rules! Command_to_Expr_convertor {
     ($enum_entry:ident, $value:ident, $args1:ident, $args2:ident) => {
         if let Command::$enum_entry(a, b) = $value {
             return Ok(Expr {
-                cmd: CommandWord::$enum_entry,
+                cmd: Word::Builtin(CommandWord::$enum_entry),
                 args: vec![CommandArg::$args1(a), CommandArg::$args2(b)],
             });
         }
@@ -140,49 +279,50 @@ decl_command_enum! {
     PossibleArgs:
         (Dir, Label, Reg, RwReg, Val, Mem)
     Commands:
-        ("nop",    Nop                 ),
-        ("mov",    Mov,    Dir         ),
-        ("rot",    Rot,    Dir         ),
-        ("jmp",    Jmp,    Label       ),
-        ("cmp",    Cmp,    Reg,   Reg  ),
-        ("jme",    Jme,    Label       ),
-        ("jne",    Jne,    Label       ),
-        ("jmg",    Jmg,    Label       ),
-        ("jml",    Jml,    Label       ),
-        ("jle",    Jle,    Label       ),
-        ("jge",    Jge,    Label       ),
-        ("jmo",    Jmo,    Label       ),
-        ("jno",    Jno,    Label       ),
-        ("jmb",    Jmb,    Label       ),
-        ("jnb",    Jnb,    Label       ),
-        ("jmc",    Jmc,    Label       ),
-        ("jnc",    Jnc,    Label       ),
-        ("jmf",    Jmf,    Label       ),
-        ("jnf",    Jnf,    Label       ),
-        ("chk",    Chk,    Dir         ),
-        ("cmpv",   Cmpv,   Reg,   Val  ),
-        ("split",  Split,  Dir,   Label),
-        ("fork",   Fork,   Dir,   Label),
-        ("bite",   Bite,   Dir         ),
-        ("eatsun", Eatsun              ),
-        ("absorb", Absorb              ),
-        ("call",   Call,   Label       ),
-        ("ret",    Ret                 ),
-        ("ld",     Ld,     RwReg, Reg  ),
-        ("ldv",    Ldv,    RwReg, Val  ),
-        ("ldr",    Ldr,    Mem,   Reg  ),
-        ("ldm",    Ldm,    RwReg, Mem  ),
-        ("neg",    Neg,    RwReg       ),
-        ("add",    Add,    RwReg, Reg  ),
-        ("addv",   Addv,   RwReg, Val  ),
-        ("sub",    Sub,    RwReg, Reg  ),
-        ("subv",   Subv,   RwReg, Val  ),
-        ("mul",    Mul,    RwReg, Reg  ),
-        ("mulv",   Mulv,   RwReg, Val  ),
-        ("div",    Div,    RwReg, Reg  ),
-        ("divv",   Divv,   RwReg, Val  ),
-        ("mod",    Mod,    RwReg, Reg  ),
-        ("modv",   Modv,   RwReg, Val  ),
-        ("pow",    Pow,    RwReg, Reg  ),
-        ("powv",   Powv,   RwReg, Val  )
+        (0x00, "nop",    Nop                 ),
+        (0x01, "mov",    Mov,    Dir         ),
+        (0x02, "rot",    Rot,    Dir         ),
+        (0x03, "jmp",    Jmp,    Label       ),
+        (0x04, "cmp",    Cmp,    Reg,   Reg  ),
+        (0x05, "jme",    Jme,    Label       ),
+        (0x06, "jne",    Jne,    Label       ),
+        (0x07, "jmg",    Jmg,    Label       ),
+        (0x08, "jml",    Jml,    Label       ),
+        (0x09, "jle",    Jle,    Label       ),
+        (0x0a, "jge",    Jge,    Label       ),
+        (0x0b, "jmo",    Jmo,    Label       ),
+        (0x0c, "jno",    Jno,    Label       ),
+        (0x0d, "jmb",    Jmb,    Label       ),
+        (0x0e, "jnb",    Jnb,    Label       ),
+        (0x0f, "jmc",    Jmc,    Label       ),
+        (0x10, "jnc",    Jnc,    Label       ),
+        (0x11, "jmf",    Jmf,    Label       ),
+        (0x12, "jnf",    Jnf,    Label       ),
+        (0x13, "chk",    Chk,    Dir         ),
+        (0x14, "cmpv",   Cmpv,   Reg,   Val  ),
+        (0x15, "split",  Split,  Dir,   Label),
+        (0x16, "fork",   Fork,   Dir,   Label),
+        (0x17, "bite",   Bite,   Dir         ),
+        (0x18, "eatsun", Eatsun              ),
+        (0x19, "absorb", Absorb              ),
+        (0x1a, "call",   Call,   Label       ),
+        (0x1b, "ret",    Ret                 ),
+        (0x1c, "ld",     Ld,     RwReg, Reg  ),
+        (0x1d, "ldv",    Ldv,    RwReg, Val  ),
+        (0x1e, "ldr",    Ldr,    Mem,   Reg  ),
+        (0x1f, "ldm",    Ldm,    RwReg, Mem  ),
+        (0x20, "neg",    Neg,    RwReg       ),
+        (0x21, "add",    Add,    RwReg, Reg  ),
+        (0x22, "addv",   Addv,   RwReg, Val  ),
+        (0x23, "sub",    Sub,    RwReg, Reg  ),
+        (0x24, "subv",   Subv,   RwReg, Val  ),
+        (0x25, "mul",    Mul,    RwReg, Reg  ),
+        (0x26, "mulv",   Mulv,   RwReg, Val  ),
+        (0x27, "div",    Div,    RwReg, Reg  ),
+        (0x28, "divv",   Divv,   RwReg, Val  ),
+        (0x29, "mod",    Mod,    RwReg, Reg  ),
+        (0x2a, "modv",   Modv,   RwReg, Val  ),
+        (0x2b, "pow",    Pow,    RwReg, Reg  ),
+        (0x2c, "powv",   Powv,   RwReg, Val  ),
+        (0x2d, "mate",   Mate,   Dir,   Label)
 }
@@ -2,7 +2,9 @@ pub const LABEL_REGEX: &str = "^[a-zA-Z_][a-zA-Z_0-9]*:$";
 
 pub type Label = usize;
 impl crate::token::FromTokenStream for Label {
-    fn from_toks(toks: &mut crate::token::TokenStream) -> anyhow::Result<Label> {
+    fn from_toks(
+        toks: &mut crate::token::TokenStream,
+    ) -> Result<Label, crate::diagnostic::Diagnostic> {
         let (_, index) = toks.next()?;
         Ok(index)
     }
@@ -1,22 +1,22 @@
-use anyhow::ensure;
+use crate::diagnostic::{Diagnostic, ErrorCode};
+
 pub type Mem = u64;
 impl crate::token::FromTokenStream for Mem {
-    fn from_toks(toks: &mut crate::token::TokenStream) -> anyhow::Result<Mem> {
-        use anyhow::Context;
+    fn from_toks(toks: &mut crate::token::TokenStream) -> Result<Mem, Diagnostic> {
         let (val_tok, _) = toks.next()?;
+        let error =
+            |msg: String| Diagnostic::new(ErrorCode::InvalidArgument, msg, Some(val_tok.span));
 
-        let string = val_tok.orign_string.clone();
-        ensure!(
-            string.len() > 2,
-            format!("Failed to parse {} as memory address", string)
-        );
-        let mut chars: Vec<_> = string.chars().collect();
-        ensure!(
-            chars.remove(0) == '[' && chars.pop().unwrap() == ']',
-            format!("Failed to parse {} as memory address", string)
-        );
-        let addr = chars.into_iter().collect::<String>().parse::<Mem>()
-            .context(format!("Failed to parse {} as number", val_tok))?;
-        Ok(addr)
+        let string = val_tok.orign_string.as_str();
+        let Some(addr) = string.strip_prefix('[').and_then(|s| s.strip_suffix(']')) else {
+            return Err(
+                error(format!("Failed to parse \"{}\" as memory address", string)).with_note(
+                    "memory address must be written in square brackets: [123]",
+                    None,
+                ),
+            );
+        };
+        addr.parse::<Mem>()
+            .map_err(|e| error(format!("Failed to parse \"{}\" as number: {}", addr, e)))
     }
 }
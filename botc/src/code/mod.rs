@@ -6,7 +6,7 @@ pub(crate) mod reg;
 pub(crate) mod val;
 pub(crate) use label::LABEL_REGEX;
 
-pub use command::Command;
+pub use command::{ArgKind, Command, CommandArg, CommandWord, Opcode, Word, ISA_VERSION};
 pub use dir::Dir;
 pub use label::Label;
 pub use mem::Mem;
@@ -1,11 +1,18 @@
+use crate::diagnostic::{Diagnostic, ErrorCode};
+
 pub type Val = isize;
 impl crate::token::FromTokenStream for Val {
-    fn from_toks(toks: &mut crate::token::TokenStream) -> anyhow::Result<Val> {
-        use anyhow::Context;
+    fn from_toks(toks: &mut crate::token::TokenStream) -> Result<Val, Diagnostic> {
         let (val_tok, _) = toks.next()?;
-        Ok(val_tok
-            .orign_string
-            .parse::<Val>()
-            .context(format!("Failed to parse {} as number", val_tok))?)
+        val_tok.orign_string.parse::<Val>().map_err(|e| {
+            Diagnostic::new(
+                ErrorCode::InvalidArgument,
+                format!(
+                    "Failed to parse \"{}\" as number: {}",
+                    val_tok.orign_string, e
+                ),
+                Some(val_tok.span),
+            )
+        })
     }
 }
@@ -1,40 +1,529 @@
-use anyhow::{ensure, Context, Result};
-use flate2::Compression;
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL, Engine};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use std::io::{Read, Write};
 
-use crate::code::Command;
+use crate::{
+    code::{command::Expr, ArgKind, Command, CommandArg, CommandWord, Opcode, Word, ISA_VERSION},
+    compiler,
+    isa::CUSTOM_OPCODES_START,
+};
 
 const BASE32_ALP: base32::Alphabet = base32::Alphabet::Rfc4648 { padding: false };
 
+/// Version of the packed genome format:
+/// - 0 is the headerless bincode of the commands with absolute labels
+/// - 1 starts with `MAGIC` and the version, labels are stored as offsets from their
+///   commands, so jumps that do not cross an inserted or deleted command keep their targets
+/// - 2 adds the instruction set version, the genome length, `#mem_size` and the CRC32
+///   of the commands
+/// - 3 stores the commands as their opcodes followed by the arguments, the older
+///   formats store the index of the command in the list of the instruction set 3
+pub const FORMAT_VERSION: u8 = 3;
+
+/// Default limit of the unpacked genome size in bytes
+pub const MAX_UNPACKED_SIZE: usize = 1024 * 1024;
+
+// the headerless format starts with the number of commands, this one would need
+// millions of them, so the formats are not confused
+const MAGIC: &[u8; 3] = b"TLG";
+// magic, format, isa, len, mem_size and crc
+const HEADER_SIZE: usize = 3 + 1 + 2 + 4 + 4 + 4;
+// `#mem_size` is not specified
+const NO_MEM_SIZE: u32 = u32::MAX;
+// index of the custom instruction in the commands of the formats 0-2, the builtin
+// commands before it are stored as their opcodes
+const LEGACY_EXT_INDEX: u32 = 46;
+
+/// Header of the packed genome, the codes of the old formats get a header with
+/// what is known about them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub format_version: u8,
+    /// `ISA_VERSION` of the compiler, 1 for the codes without it
+    pub isa_version: u16,
+    pub len: usize,
+    pub mem_size: Option<usize>,
+}
+
 pub fn from_b32(b32: &str) -> Result<Vec<Command>> {
+    decode(b32).map(|(_, cmds)| cmds)
+}
+
+pub fn to_b32(cmds: &[Command]) -> Result<String> {
+    encode(cmds, None)
+}
+
+/// Unpacks the genome of any format version, checks its header
+pub fn decode(b32: &str) -> Result<(Header, Vec<Command>)> {
+    decode_with_limit(b32, MAX_UNPACKED_SIZE)
+}
+
+/// Unpacks the genome, fails if the unpacked commands take more than `max_size` bytes
+pub fn decode_with_limit(b32: &str, max_size: usize) -> Result<(Header, Vec<Command>)> {
     let compresed_bin = base32::decode(BASE32_ALP, b32).context("Failed to decode as base32")?;
+    decode_bin(&compresed_bin, max_size)
+}
 
-    let mut decompressor = flate2::Decompress::new(false);
-    // TODO: make the buffer size dynamic, now the hard size is 1 MB
-    let mut bin = Vec::with_capacity(1024*1024);
-    let status = decompressor
-        .decompress_vec(&compresed_bin, &mut bin, flate2::FlushDecompress::Finish)
+/// Unpacks the raw binary genome
+fn decode_bin(compresed_bin: &[u8], max_size: usize) -> Result<(Header, Vec<Command>)> {
+    // one byte over the limit tells a too big code from the one of the limit size
+    let mut bin = Vec::new();
+    DeflateDecoder::new(compresed_bin)
+        .take((max_size as u64).saturating_add(1))
+        .read_to_end(&mut bin)
         .context("Failed to decompress")?;
     ensure!(
-        status == flate2::Status::StreamEnd,
-        "Some error while decompressing code: {:?}",
-        status
+        bin.len() <= max_size,
+        "Unpacked code is bigger than the limit of {max_size} bytes"
     );
 
-    Ok(bincode::deserialize(&bin).context("Failed to deserialize commands")?)
+    let Some(bin) = bin.strip_prefix(MAGIC) else {
+        let cmds = read_legacy_commands(&bin)?;
+        return Ok((old_header(0, &cmds), cmds));
+    };
+    match bin.first() {
+        Some(1) => {
+            let cmds = from_relative(&read_legacy_commands(&bin[1..])?);
+            Ok((old_header(1, &cmds), cmds))
+        }
+        Some(v @ (2 | 3)) => {
+            ensure!(
+                bin.len() >= HEADER_SIZE - MAGIC.len(),
+                "Truncated code header"
+            );
+            let u16_at = |i: usize| u16::from_le_bytes([bin[i], bin[i + 1]]);
+            let u32_at =
+                |i: usize| u32::from_le_bytes([bin[i], bin[i + 1], bin[i + 2], bin[i + 3]]);
+            let (isa_version, len, mem_size, crc) = (u16_at(1), u32_at(3), u32_at(7), u32_at(11));
+            let payload = &bin[HEADER_SIZE - MAGIC.len()..];
+            ensure!(
+                isa_version <= ISA_VERSION,
+                "Unsupported instruction set version {isa_version}, expected up to {ISA_VERSION}"
+            );
+            ensure!(crc32(payload) == crc, "Code checksum mismatch");
+            let cmds = match v {
+                2 => read_legacy_commands(payload)?,
+                _ => read_commands(payload, len as usize)?,
+            };
+            ensure!(
+                cmds.len() == len as usize,
+                "Code length {} does not match the header length {}",
+                cmds.len(),
+                len
+            );
+            let header = Header {
+                format_version: *v,
+                isa_version,
+                len: len as usize,
+                mem_size: (mem_size != NO_MEM_SIZE).then_some(mem_size as usize),
+            };
+            Ok((header, from_relative(&cmds)))
+        }
+        Some(v) => bail!("Unsupported code format version {v}"),
+        None => bail!("Missing code format version"),
+    }
 }
 
-pub fn to_b32(cmds: &Vec<Command>) -> Result<String> {
-    let bin = bincode::serialize(&cmds).context("Failed to serialize commands")?;
-    let mut compressor = flate2::Compress::new(Compression::best(), false);
-    let mut compresed_bin = Vec::with_capacity(bin.len());
-    let status = compressor
-        .compress_vec(&bin, &mut compresed_bin, flate2::FlushCompress::Finish)
-        .context("Failed to compress")?;
-    ensure!(
-        status == flate2::Status::StreamEnd,
-        "Some error while compressing code: {:?}",
-        status
-    );
+/// Packs the genome in the current format, `mem_size` is the `#mem_size` of its code
+pub fn encode(cmds: &[Command], mem_size: Option<usize>) -> Result<String> {
+    Ok(base32::encode(BASE32_ALP, &encode_bin(cmds, mem_size)?))
+}
+
+/// Packs the genome into raw binary
+fn encode_bin(cmds: &[Command], mem_size: Option<usize>) -> Result<Vec<u8>> {
+    check_labels(cmds)?;
+    let payload = write_commands(&to_relative(cmds))?;
+    let mem_size = match mem_size {
+        Some(m) => u32::try_from(m)
+            .ok()
+            .filter(|m| *m != NO_MEM_SIZE)
+            .context("Memory size is too big")?,
+        None => NO_MEM_SIZE,
+    };
+    let len = u32::try_from(cmds.len()).context("Code is too long")?;
+
+    let mut bin = Vec::with_capacity(HEADER_SIZE + payload.len());
+    bin.extend_from_slice(MAGIC);
+    bin.push(FORMAT_VERSION);
+    bin.extend_from_slice(&ISA_VERSION.to_le_bytes());
+    bin.extend_from_slice(&len.to_le_bytes());
+    bin.extend_from_slice(&mem_size.to_le_bytes());
+    bin.extend_from_slice(&crc32(&payload).to_le_bytes());
+    bin.extend_from_slice(&payload);
+
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(&bin).context("Failed to compress")?;
+    encoder.finish().context("Failed to compress")
+}
+
+/// Form of the genome for storing and sharing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Base32,
+    /// the shortest text form, safe for URLs
+    Base64Url,
+    Hex,
+    /// packed bytes as they are, for files
+    Binary,
+    /// botlang code that is compiled back on decoding
+    Text,
+}
+
+impl Encoding {
+    /// Order in which the encodings are tried on decoding, a text is rarely a valid
+    /// packed genome, so it is tried after the packed forms
+    pub const ALL: [Encoding; 5] = [
+        Encoding::Base32,
+        Encoding::Hex,
+        Encoding::Base64Url,
+        Encoding::Text,
+        Encoding::Binary,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Base32 => "base32",
+            Encoding::Base64Url => "base64url",
+            Encoding::Hex => "hex",
+            Encoding::Binary => "binary",
+            Encoding::Text => "text",
+        }
+    }
+}
+
+impl std::fmt::Display for Encoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl std::str::FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Encoding::ALL
+            .into_iter()
+            .find(|e| e.name() == s.to_lowercase())
+            .ok_or_else(|| {
+                let names: Vec<&str> = Encoding::ALL.iter().map(|e| e.name()).collect();
+                format!(
+                    "Unknown encoding \"{s}\", expected one of: {}",
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// Genome decoded from any encoding
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unpacked {
+    pub encoding: Encoding,
+    pub header: Header,
+    pub commands: Vec<Command>,
+}
+
+/// Packs the genome in the encoding, `mem_size` is the `#mem_size` of its code
+pub fn pack(cmds: &[Command], mem_size: Option<usize>, encoding: Encoding) -> Result<Vec<u8>> {
+    Ok(match encoding {
+        Encoding::Base32 => encode(cmds, mem_size)?.into_bytes(),
+        Encoding::Base64Url => BASE64_URL.encode(encode_bin(cmds, mem_size)?).into_bytes(),
+        Encoding::Hex => hex::encode(encode_bin(cmds, mem_size)?).into_bytes(),
+        Encoding::Binary => encode_bin(cmds, mem_size)?,
+        Encoding::Text => {
+            // the text of custom instructions can not be compiled back without their specs
+            if let Some(cmd) = cmds.iter().find(|c| matches!(c, Command::Ext(..))) {
+                bail!(
+                    "Custom instruction {} can not be packed as text",
+                    cmd.word()
+                );
+            }
+            check_labels(cmds)?;
+            let mut res = String::new();
+            if let Some(mem_size) = mem_size {
+                res.push_str(&format!("#mem_size {mem_size}\n"));
+            }
+            for line in compiler::decompile(cmds.to_vec()) {
+                res.push_str(&line);
+                res.push('\n');
+            }
+            res.into_bytes()
+        }
+    })
+}
+
+/// Unpacks the genome detecting its encoding
+pub fn unpack(data: &[u8]) -> Result<Unpacked> {
+    unpack_with_limit(data, MAX_UNPACKED_SIZE)
+}
+
+/// Unpacks the genome detecting its encoding, fails if the unpacked commands or
+/// the text take more than `max_size` bytes
+pub fn unpack_with_limit(data: &[u8], max_size: usize) -> Result<Unpacked> {
+    let text = std::str::from_utf8(data).ok().map(str::trim);
+    ensure!(!text.map_or(data.is_empty(), str::is_empty), "Empty genome");
+    // the errors of every encoding are reported, a botlang typo may look like base64url
+    let mut errs = Vec::new();
+    for encoding in Encoding::ALL {
+        if !may_be(encoding, text) {
+            continue;
+        }
+        match unpack_as(data, text, encoding, max_size) {
+            Ok((header, commands)) => {
+                return Ok(Unpacked {
+                    encoding,
+                    header,
+                    commands,
+                })
+            }
+            Err(e) => errs.push(format!(
+                "Failed to decode as {encoding}: {}",
+                format!("{e:#}").trim_end()
+            )),
+        }
+    }
+    ensure!(!errs.is_empty(), "Unknown genome encoding");
+    bail!("{}", errs.join("\n"))
+}
+
+/// Whether the data has only the characters of the encoding
+fn may_be(encoding: Encoding, text: Option<&str>) -> bool {
+    let all = |f: fn(char) -> bool| text.is_some_and(|t| !t.is_empty() && t.chars().all(f));
+    match encoding {
+        Encoding::Base32 => all(|c| c.is_ascii_uppercase() || ('2'..='7').contains(&c)),
+        Encoding::Hex => all(|c| c.is_ascii_hexdigit()),
+        Encoding::Base64Url => all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+        Encoding::Text => text.is_some(),
+        Encoding::Binary => true,
+    }
+}
+
+fn unpack_as(
+    data: &[u8],
+    text: Option<&str>,
+    encoding: Encoding,
+    max_size: usize,
+) -> Result<(Header, Vec<Command>)> {
+    let text = text.unwrap_or_default();
+    match encoding {
+        Encoding::Base32 => decode_with_limit(text, max_size),
+        Encoding::Base64Url => decode_bin(&BASE64_URL.decode(text)?, max_size),
+        Encoding::Hex => decode_bin(&hex::decode(text)?, max_size),
+        Encoding::Binary => decode_bin(data, max_size),
+        Encoding::Text => {
+            ensure!(
+                text.len() <= max_size,
+                "Code text is bigger than the limit of {max_size} bytes"
+            );
+            let (cmds, symbols) = compiler::compile_with_symbols(text.to_string())
+                .map_err(|d| anyhow!("{}", d.render()))?;
+            let header = Header {
+                format_version: FORMAT_VERSION,
+                isa_version: ISA_VERSION,
+                len: cmds.len(),
+                mem_size: symbols.mem_size,
+            };
+            Ok((header, cmds))
+        }
+    }
+}
+
+fn old_header(format_version: u8, cmds: &[Command]) -> Header {
+    Header {
+        format_version,
+        isa_version: 1,
+        len: cmds.len(),
+        mem_size: None,
+    }
+}
+
+fn crc32(bin: &[u8]) -> u32 {
+    let mut crc = flate2::Crc::new();
+    crc.update(bin);
+    crc.sum()
+}
+
+/// Labels out of the genome can not be stored as offsets, they would come back wrapped.
+/// The genomes of a world never have them: `World::spawn` and `World::load` reject
+/// such genomes, the mutations and the crossover keep the labels in the genome.
+fn check_labels(cmds: &[Command]) -> Result<()> {
+    for (i, cmd) in cmds.iter().enumerate() {
+        for arg in cmd.args() {
+            if let CommandArg::Label(l) = arg {
+                ensure!(
+                    l < cmds.len(),
+                    "Label {l} of command {i} is out of the genome of {} commands",
+                    cmds.len()
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Replaces the labels with their offsets from the commands, the signed offsets are
+/// zigzag encoded: 0, -1, 1, -2, 2 ... become 0, 1, 2, 3, 4 ...
+fn to_relative(cmds: &[Command]) -> Vec<Command> {
+    map_labels(cmds, |i, l| {
+        let offset = l as i64 - i as i64;
+        ((offset << 1) ^ (offset >> 63)) as usize
+    })
+}
+
+/// Restores the absolute labels, the ones out of the genome are wrapped, they come
+/// only from broken codes
+fn from_relative(cmds: &[Command]) -> Vec<Command> {
+    let len = cmds.len() as i64;
+    map_labels(cmds, |i, l| {
+        let offset = (l >> 1) as i64 ^ -((l & 1) as i64);
+        (i as i64).wrapping_add(offset).rem_euclid(len) as usize
+    })
+}
+
+fn map_labels<F: Fn(usize, usize) -> usize>(cmds: &[Command], func: F) -> Vec<Command> {
+    cmds.iter()
+        .enumerate()
+        .map(|(i, c)| {
+            let args = c
+                .args()
+                .into_iter()
+                .map(|a| match a {
+                    CommandArg::Label(l) => CommandArg::Label(func(i, l)),
+                    a => a,
+                })
+                .collect();
+            c.with_args(args).unwrap_or_else(|| c.clone())
+        })
+        .collect()
+}
+
+/// Writes the commands as their opcodes followed by the arguments, the custom
+/// instructions also store the number and the kinds of their arguments
+fn write_commands(cmds: &[Command]) -> Result<Vec<u8>> {
+    let mut bin = Vec::new();
+    for cmd in cmds {
+        let args = cmd.args();
+        bin.extend_from_slice(&cmd.word().opcode().to_le_bytes());
+        if let Command::Ext(..) = cmd {
+            bin.push(u8::try_from(args.len()).context("Too many arguments")?);
+            bin.extend(args.iter().map(|a| arg_kind_code(a.kind())));
+        }
+        for arg in args {
+            let res = match arg {
+                CommandArg::Dir(a) => bincode::serialize_into(&mut bin, &a),
+                CommandArg::Label(a) => bincode::serialize_into(&mut bin, &a),
+                CommandArg::Reg(a) => bincode::serialize_into(&mut bin, &a),
+                CommandArg::RwReg(a) => bincode::serialize_into(&mut bin, &a),
+                CommandArg::Val(a) => bincode::serialize_into(&mut bin, &a),
+                CommandArg::Mem(a) => bincode::serialize_into(&mut bin, &a),
+            };
+            res.context("Failed to serialize commands")?;
+        }
+    }
+    Ok(bin)
+}
+
+/// Reads `len` commands written by `write_commands`
+fn read_commands(mut bin: &[u8], len: usize) -> Result<Vec<Command>> {
+    let mut cmds = Vec::new();
+    for _ in 0..len {
+        let opcode: Opcode = read(&mut bin)?;
+        let (word, kinds) = match CommandWord::from_opcode(opcode) {
+            Some(w) => (Word::Builtin(w), w.arg_kinds()),
+            None => {
+                ensure!(opcode >= CUSTOM_OPCODES_START, "Unknown opcode {opcode}");
+                let cnt: u8 = read(&mut bin)?;
+                let kinds = (0..cnt)
+                    .map(|_| arg_kind_from_code(read(&mut bin)?))
+                    .collect::<Result<_>>()?;
+                (Word::Custom(opcode), kinds)
+            }
+        };
+        let args = kinds
+            .into_iter()
+            .map(|k| read_arg(&mut bin, k))
+            .collect::<Result<_>>()?;
+        cmds.push(Command::try_from(Expr { cmd: word, args })?);
+    }
+    ensure!(bin.is_empty(), "Unexpected data after the commands");
+    Ok(cmds)
+}
+
+/// Reads the bincode of the commands of the formats 0-2, it stores the index of
+/// the command in the list of the instruction set 3, which is its opcode
+fn read_legacy_commands(mut bin: &[u8]) -> Result<Vec<Command>> {
+    let len: u64 = read(&mut bin)?;
+    let mut cmds = Vec::new();
+    for _ in 0..len {
+        let index: u32 = read(&mut bin)?;
+        let cmd = if index == LEGACY_EXT_INDEX {
+            let opcode: Opcode = read(&mut bin)?;
+            let cnt: u64 = read(&mut bin)?;
+            let args = (0..cnt)
+                .map(|_| {
+                    let kind: u32 = read(&mut bin)?;
+                    let kind = u8::try_from(kind).map_err(|_| anyhow!("Unknown argument kind"))?;
+                    read_arg(&mut bin, arg_kind_from_code(kind)?)
+                })
+                .collect::<Result<_>>()?;
+            Command::Ext(opcode, args)
+        } else {
+            let w = u16::try_from(index)
+                .ok()
+                .filter(|i| (*i as u32) < LEGACY_EXT_INDEX)
+                .and_then(CommandWord::from_opcode)
+                .ok_or_else(|| anyhow!("Unknown command {index}"))?;
+            let args = w
+                .arg_kinds()
+                .into_iter()
+                .map(|k| read_arg(&mut bin, k))
+                .collect::<Result<_>>()?;
+            Command::try_from(Expr {
+                cmd: Word::Builtin(w),
+                args,
+            })?
+        };
+        cmds.push(cmd);
+    }
+    Ok(cmds)
+}
+
+fn read<T: serde::de::DeserializeOwned>(bin: &mut &[u8]) -> Result<T> {
+    bincode::deserialize_from(bin).context("Failed to deserialize commands")
+}
+
+fn read_arg(bin: &mut &[u8], kind: ArgKind) -> Result<CommandArg> {
+    Ok(match kind {
+        ArgKind::Dir => CommandArg::Dir(read(bin)?),
+        ArgKind::Label => CommandArg::Label(read(bin)?),
+        ArgKind::Reg => CommandArg::Reg(read(bin)?),
+        ArgKind::RwReg => CommandArg::RwReg(read(bin)?),
+        ArgKind::Val => CommandArg::Val(read(bin)?),
+        ArgKind::Mem => CommandArg::Mem(read(bin)?),
+    })
+}
+
+// codes of the argument kinds of the custom instructions, they never change and
+// match the indexes of the kinds in the formats 0-2
+fn arg_kind_code(kind: ArgKind) -> u8 {
+    match kind {
+        ArgKind::Dir => 0,
+        ArgKind::Label => 1,
+        ArgKind::Reg => 2,
+        ArgKind::RwReg => 3,
+        ArgKind::Val => 4,
+        ArgKind::Mem => 5,
+    }
+}
 
-    Ok(base32::encode(BASE32_ALP, &compresed_bin))
+fn arg_kind_from_code(code: u8) -> Result<ArgKind> {
+    Ok(match code {
+        0 => ArgKind::Dir,
+        1 => ArgKind::Label,
+        2 => ArgKind::Reg,
+        3 => ArgKind::RwReg,
+        4 => ArgKind::Val,
+        5 => ArgKind::Mem,
+        _ => bail!("Unknown argument kind {code}"),
+    })
 }
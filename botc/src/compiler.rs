@@ -1,35 +1,98 @@
-use anyhow::{bail, ensure, Context, Result};
 use regex::Regex;
-use std::collections::HashMap;
+use std::{collections::HashMap, path::Path};
 
 use crate::{
     code::{
-        command::{Command, CommandArg, CommandWord, Expr, COMMAND_REGEX},
-        Label, LABEL_REGEX,
+        command::{Command, CommandArg, CommandWord, Expr, Word, COMMAND_REGEX},
+        Dir, Label, Mem, Reg, Val, LABEL_REGEX,
     },
-    token::{FromTokenStream, Token, TokenStream},
+    diagnostic::{Diagnostic, Diagnostics, ErrorCode, Note, Span},
+    isa::InstructionSet,
+    preprocessor::{Preprocessed, Preprocessor, LINE_COMMENTS_START},
+    symbols::{SourceLine, SymbolMap, SymbolRef},
+    token::{FromTokenStream, TokenStream},
 };
 
-pub fn compile(code: String) -> Result<Vec<Command>> {
-    let tokens = Compiler::preproc(code).context("Failed to preproc")?;
-    Compiler::new()
-        .translate(tokens)
-        .context("Failed to translate")
+/// Limit of `#len`, the code is padded to this length with `nop`
+pub const MAX_GENOM_LEN: usize = 1 << 16;
+
+/// Compiles the code, reporting all errors found in it. Includes are not allowed.
+pub fn compile(code: String) -> Result<Vec<Command>, Diagnostics> {
+    compile_with_symbols(code).map(|(code, _)| code)
+}
+
+/// Compiles the file, includes are searched relative to the including file
+pub fn compile_file(path: &Path) -> Result<Vec<Command>, Diagnostics> {
+    compile_file_with_symbols(path).map(|(code, _)| code)
+}
+
+/// Compiles the code and returns the names given to the numbers in it
+pub fn compile_with_symbols(code: String) -> Result<(Vec<Command>, SymbolMap), Diagnostics> {
+    compile_with_isa(code, &InstructionSet::builtin())
+}
+
+/// Compiles the file and returns the names given to the numbers in it
+pub fn compile_file_with_symbols(path: &Path) -> Result<(Vec<Command>, SymbolMap), Diagnostics> {
+    compile_file_with_isa(path, &InstructionSet::builtin())
+}
+
+/// Compiles the code that may use the custom instructions of the set
+pub fn compile_with_isa(
+    code: String,
+    isa: &InstructionSet,
+) -> Result<(Vec<Command>, SymbolMap), Diagnostics> {
+    Compiler::new(isa).translate(Preprocessor::process_code(&code, "input"))
+}
+
+/// Compiles the file that may use the custom instructions of the set
+pub fn compile_file_with_isa(
+    path: &Path,
+    isa: &InstructionSet,
+) -> Result<(Vec<Command>, SymbolMap), Diagnostics> {
+    Compiler::new(isa).translate(Preprocessor::process_path(path))
 }
 
 pub fn decompile(code: Vec<Command>) -> Vec<String> {
+    decompile_with_symbols(code, &SymbolMap::default())
+}
+
+/// Decompiles the code, labels and arguments written as constants and variables
+/// get their names back
+pub fn decompile_with_symbols(code: Vec<Command>, symbols: &SymbolMap) -> Vec<String> {
+    decompile_lines(code, symbols)
+        .into_iter()
+        .map(|(_, line)| line)
+        .collect()
+}
+
+/// Decompiles the code into lines paired with the index of the instruction they hold,
+/// declarations and labels have no index
+pub fn decompile_lines(code: Vec<Command>, symbols: &SymbolMap) -> Vec<(Option<usize>, String)> {
     let mut req_labels: Vec<(usize, String)> = Vec::new();
-    let mut res: Vec<String> = code
+    let cmds: Vec<String> = code
         .into_iter()
-        .map(|c| {
+        .enumerate()
+        .map(|(i, c)| {
             let expr = TryInto::<Expr>::try_into(c).unwrap();
-            let mut res: String = format!("{}", expr.cmd);
-            for a in expr.args {
+            let mut res: String = symbols.command_name(expr.cmd);
+            for (arg, a) in expr.args.into_iter().enumerate() {
                 if let CommandArg::Label(index) = a {
-                    res.push_str(format!(" label_{a}").as_str());
-                    req_labels.push((index, format!("label_{a}:")));
+                    let name = match symbols.label_name(index) {
+                        Some(name) => name.to_string(),
+                        None => format!("label_{a}"),
+                    };
+                    res.push_str(format!(" {name}").as_str());
+                    req_labels.push((index, format!("{name}:")));
                 } else if let CommandArg::Mem(addr) = a {
-                    res.push_str(format!(" [{addr}]").as_str());
+                    match symbols.mem_name(i, arg, addr) {
+                        Some(name) => res.push_str(format!(" [{name}]").as_str()),
+                        None => res.push_str(format!(" [{addr}]").as_str()),
+                    }
+                } else if let Some(name) = match a {
+                    CommandArg::Val(v) => symbols.val_name(i, arg, v),
+                    _ => None,
+                } {
+                    res.push_str(format!(" {name}").as_str());
                 } else {
                     res.push_str(format!(" {a}").as_str());
                 }
@@ -37,202 +100,521 @@ pub fn decompile(code: Vec<Command>) -> Vec<String> {
             res
         })
         .collect();
-    req_labels.sort_by(|(i1, _), (i2, _)| i2.cmp(i1));
+    // named labels are kept even if nothing refers to them
+    req_labels.extend(
+        symbols
+            .labels
+            .values()
+            .map(|l| (*l, format!("{}:", symbols.label_name(*l).unwrap()))),
+    );
+    req_labels.sort_by_key(|(i, _)| *i);
     req_labels.dedup_by(|(i1, _), (i2, _)| i1 == i2);
-    for (i, l) in req_labels {
-        res.insert(i, l);
+
+    let mut res: Vec<(Option<usize>, String)> = symbols
+        .declarations()
+        .into_iter()
+        .map(|d| (None, d))
+        .collect();
+    let mut labels = req_labels.into_iter().peekable();
+    for (i, c) in cmds.into_iter().enumerate() {
+        while let Some((_, l)) = labels.next_if(|(li, _)| *li <= i) {
+            res.push((None, l));
+        }
+        res.push((Some(i), c));
     }
+    // labels outside the code
+    res.extend(labels.map(|(_, l)| (None, l)));
     res
 }
 
 const DIRECTIVE_REGEX: &str = "^#[a-zA-Z_]*$";
-const LINE_COMMENTS_START: &'static str = "//";
+const SYMBOL_REGEX: &str = "^[a-zA-Z_][a-zA-Z_0-9]*$";
 
-struct Compiler {
-    exist_labels: HashMap<String, usize>,
-    commands: Vec<Expr>,
+struct Compiler<'a> {
+    isa: &'a InstructionSet,
+    // label name -> (position, definition)
+    exist_labels: HashMap<String, (usize, Span)>,
+    // command and index of its first token, padding commands have no tokens
+    commands: Vec<(Expr, Option<usize>)>,
     gen_len: isize,
+    gen_len_span: Option<Span>,
     mem_size: isize,
+    mem_size_span: Option<Span>,
+    // symbol name -> (declared name, value, definition)
+    consts: HashMap<String, (String, Val, Span)>,
+    vars: HashMap<String, (String, Mem, Span)>,
+    // arguments written as symbols, the index is the command index
+    symbol_refs: Vec<SymbolRef>,
+    symbol_regex: Regex,
+    diagnostics: Vec<Diagnostic>,
 }
 
-impl Compiler {
-    fn new() -> Self {
+impl<'a> Compiler<'a> {
+    fn new(isa: &'a InstructionSet) -> Self {
         Self {
+            isa,
             exist_labels: HashMap::new(),
             commands: Vec::new(),
             gen_len: -1,
+            gen_len_span: None,
             mem_size: -1,
+            mem_size_span: None,
+            consts: HashMap::new(),
+            vars: HashMap::new(),
+            symbol_refs: Vec::new(),
+            symbol_regex: Regex::new(SYMBOL_REGEX).unwrap(),
+            diagnostics: Vec::new(),
         }
     }
 
-    fn preproc(code: String) -> Result<TokenStream> {
-        // code preparation: removing comments, case alignment, splitting into tokens and numbering
-        Ok(TokenStream::from_vec(
-            code.lines()
-                .map(|mut l| {
-                    // removing comments
-                    if let Some(i) = l.find(LINE_COMMENTS_START) {
-                        l = &l[..i];
-                    }
-                    // splitting into tokens
-                    l.split_whitespace()
-                        .map(|t| t.trim().to_string())
-                        .filter(|t| !t.is_empty())
-                        .collect::<Vec<String>>()
-                })
-                // numbering lines
-                .zip(1..code.lines().count() + 1)
-                .filter(|(ts, _)| !ts.is_empty())
-                .map(|(ts, ln)| {
-                    ts.iter()
-                        // numbering words
-                        .zip(1..ts.len() + 1)
-                        .map(|(t, wn)| Token {
-                            line_index: ln,
-                            word_index: wn,
-                            orign_string: t.to_string(),
-                        })
-                        .collect()
-                })
-                .fold(Vec::new(), |mut acc, mut t| {
-                    acc.append(&mut t);
-                    acc
-                }),
-        ))
-    }
+    fn translate(mut self, code: Preprocessed) -> Result<(Vec<Command>, SymbolMap), Diagnostics> {
+        let Preprocessed {
+            toks,
+            files,
+            expansions,
+            diagnostics,
+        } = code;
+        self.diagnostics = diagnostics;
+        let mut toks = TokenStream::from_vec(toks);
 
-    fn translate(mut self, mut toks: TokenStream) -> Result<Vec<Command>> {
         // parse tokens
+        let directive_regex = Regex::new(DIRECTIVE_REGEX).unwrap();
+        let command_regex = Regex::new(COMMAND_REGEX).unwrap();
+        let label_regex = Regex::new(LABEL_REGEX).unwrap();
         while let Ok((token, _)) = toks.peek() {
-            match token.orign_string.as_str() {
-                s if Regex::new(DIRECTIVE_REGEX)?.is_match(s) => self
-                    .parse_directive(&mut toks)
-                    .context("Failed to translate directive")?,
-                s if Regex::new(COMMAND_REGEX)?.is_match(s) => self
-                    .parse_command(&mut toks)
-                    .context("Failed to translate command")?,
-                s if Regex::new(LABEL_REGEX)?.is_match(s) => self
-                    .parse_label(&mut toks)
-                    .context("Failed to translate label")?,
-                _ => bail!("Unexpected token {}", token),
+            // every statement is placed on one line
+            let line = token.line_index;
+            toks.limit_line(Some(line));
+            let res = match token.orign_string.as_str() {
+                s if directive_regex.is_match(s) => self.parse_directive(&mut toks),
+                s if command_regex.is_match(s) => self.parse_command(&mut toks),
+                s if label_regex.is_match(s) => self.parse_label(&mut toks),
+                s => Err(Diagnostic::new(
+                    ErrorCode::UnexpectedToken,
+                    format!("Unexpected token \"{}\"", s),
+                    Some(token.span),
+                )),
             };
+            toks.limit_line(None);
+            if let Err(d) = res {
+                // the rest of the line can not be parsed correctly
+                self.diagnostics.push(d);
+                toks.skip_line(line);
+            }
         }
 
         // checking genome length
         // if the length is specified then the genome is added to the specified length
         if self.gen_len < 0 {
             self.gen_len = self.commands.len() as isize;
-        } else {
-            ensure!(
-                self.gen_len as usize >= self.commands.len(),
-                "The generated code does not fit into the specified size. Code len: {}, expect: {}",
-                self.commands.len(),
-                self.gen_len
-            );
+        } else if self.gen_len as usize >= self.commands.len() {
             for _ in self.commands.len()..(self.gen_len as usize) {
-                self.commands.push(Expr {
-                    cmd: CommandWord::Nop,
-                    args: Vec::new(),
-                });
+                self.commands.push((
+                    Expr {
+                        cmd: Word::Builtin(CommandWord::Nop),
+                        args: Vec::new(),
+                    },
+                    None,
+                ));
             }
+        } else {
+            let span = self.commands[self.gen_len as usize]
+                .1
+                .and_then(|i| toks.get(i).ok())
+                .map(|(t, _)| t.span);
+            self.diagnostics.push(
+                Diagnostic::new(
+                    ErrorCode::CodeTooLong,
+                    format!(
+                        "The generated code does not fit into the specified size. \
+                         Code len: {}, expect: {}",
+                        self.commands.len(),
+                        self.gen_len
+                    ),
+                    span,
+                )
+                .with_note("size is specified here", self.gen_len_span),
+            );
         }
 
         // mem size check
         if self.mem_size >= 0 {
-            if let Some(addr) = self
-                .commands
-                .iter()
-                .map(|c| c.args.iter())
-                .flatten()
-                .find(|a| {
-                    if let CommandArg::Mem(m) = a {
-                        *m >= (self.mem_size as u64)
-                    } else {
-                        false
+            let mut vars: Vec<&(String, Mem, Span)> = self.vars.values().collect();
+            vars.sort_by_key(|(_, addr, _)| *addr);
+            for (name, addr, span) in vars {
+                if *addr >= self.mem_size as Mem {
+                    self.diagnostics.push(
+                        Diagnostic::new(
+                            ErrorCode::MemOutOfRange,
+                            format!(
+                                "Variable \"{}\" does not fit into memory: \
+                                 address [{}] not in 0..{}",
+                                name, addr, self.mem_size
+                            ),
+                            Some(*span),
+                        )
+                        .with_note("memory size is specified here", self.mem_size_span),
+                    );
+                }
+            }
+        }
+        for (ci, (c, tok_index)) in self.commands.iter().enumerate() {
+            for (i, a) in c.args.iter().enumerate() {
+                if let CommandArg::Mem(m) = a {
+                    // variables are checked at their declaration
+                    if self
+                        .symbol_refs
+                        .iter()
+                        .any(|r| r.index == ci && r.arg == i && self.is_var(&r.name))
+                    {
+                        continue;
                     }
-                })
-            {
-                bail!(
-                    "The memory address argument of one of the commands is outside the \
-                     specified limits: address [{}] not in 0..{}",
-                    addr,
-                    self.mem_size
-                );
+                    let span = tok_index
+                        .and_then(|ti| toks.get(ti + 1 + i).ok())
+                        .map(|(t, _)| t.span);
+                    if let Some((name, _, var_span)) =
+                        self.vars.values().find(|(_, addr, _)| addr == m)
+                    {
+                        self.diagnostics.push(
+                            Diagnostic::new(
+                                ErrorCode::MemConflict,
+                                format!(
+                                    "The memory address [{}] is allocated for variable \"{}\"",
+                                    m, name
+                                ),
+                                span,
+                            )
+                            .with_note("variable is declared here", Some(*var_span)),
+                        );
+                    }
+                    if self.mem_size >= 0 && *m >= (self.mem_size as u64) {
+                        self.diagnostics.push(
+                            Diagnostic::new(
+                                ErrorCode::MemOutOfRange,
+                                format!(
+                                    "The memory address is outside the specified limits: \
+                                         address [{}] not in 0..{}",
+                                    m, self.mem_size
+                                ),
+                                span,
+                            )
+                            .with_note("memory size is specified here", self.mem_size_span),
+                        );
+                    }
+                }
             }
         }
 
         // label resoling
-        for c in self.commands.iter_mut() {
+        for (c, _) in self.commands.iter_mut() {
             for a in c.args.iter_mut() {
                 if let CommandArg::Label(token_index) = a {
-                    let (label_tok, _) = toks.get(*token_index as usize)?;
-                    let label_pos = self
+                    let (label_tok, _) = match toks.get(*token_index) {
+                        Ok(t) => t,
+                        Err(d) => {
+                            self.diagnostics.push(d);
+                            continue;
+                        }
+                    };
+                    match self
                         .exist_labels
                         .get(&label_tok.orign_string.to_lowercase())
-                        .context(format!("Label {} not found", label_tok))?;
-                    let label_pos = *label_pos % self.gen_len as usize;
-                    *a = CommandArg::Label(label_pos as Label);
+                    {
+                        Some((label_pos, _)) => {
+                            let label_pos = *label_pos % self.gen_len.max(1) as usize;
+                            *a = CommandArg::Label(label_pos as Label);
+                        }
+                        None => self.diagnostics.push(Diagnostic::new(
+                            ErrorCode::LabelNotFound,
+                            format!("Label \"{}\" not found", label_tok.orign_string),
+                            Some(label_tok.span),
+                        )),
+                    }
                 }
             }
         }
 
+        if !self.diagnostics.is_empty() {
+            // explain where the tokens produced by the preprocessor came from
+            for d in self.diagnostics.iter_mut() {
+                let mut expansion = d.span.map_or(0, |s| s.expansion);
+                while let Some(e) = expansion.checked_sub(1).and_then(|i| expansions.get(i)) {
+                    d.notes.push(Note {
+                        message: e.message.clone(),
+                        span: Some(e.span),
+                    });
+                    expansion = e.parent;
+                }
+            }
+            self.diagnostics
+                .sort_by_key(|d| d.span.map_or((usize::MAX, 0), |s| (s.file, s.start)));
+            return Err(Diagnostics {
+                list: self.diagnostics,
+                files,
+            });
+        }
+
+        let lines = self
+            .commands
+            .iter()
+            .map(|(_, tok_index)| {
+                let (tok, _) = toks.get((*tok_index)?).ok()?;
+                let file = files.get(tok.span.file)?;
+                Some(SourceLine {
+                    file: file.name.clone(),
+                    line: tok.span.line,
+                    text: file
+                        .text
+                        .lines()
+                        .nth(tok.span.line - 1)
+                        .unwrap_or_default()
+                        .split(LINE_COMMENTS_START)
+                        .next()
+                        .unwrap_or_default()
+                        .trim()
+                        .to_string(),
+                })
+            })
+            .collect();
+        let gen_len = self.gen_len.max(1) as usize;
+        let symbols = SymbolMap {
+            lines,
+            labels: self
+                .exist_labels
+                .into_iter()
+                .map(|(name, (pos, _))| (name, pos % gen_len))
+                .collect(),
+            consts: self
+                .consts
+                .into_values()
+                .map(|(name, val, _)| (name, val))
+                .collect(),
+            vars: self
+                .vars
+                .into_values()
+                .map(|(name, addr, _)| (name, addr))
+                .collect(),
+            refs: self.symbol_refs,
+            mem_size: (self.mem_size >= 0).then_some(self.mem_size as usize),
+            instructions: self
+                .isa
+                .custom()
+                .filter(|spec| {
+                    self.commands
+                        .iter()
+                        .any(|(c, _)| c.cmd == Word::Custom(spec.opcode))
+                })
+                .cloned()
+                .collect(),
+        };
+
         // conversion to command vector
         self.commands
             .into_iter()
-            .map(|c| Ok(c.try_into()?))
-            .collect::<Result<Vec<Command>>>()
+            .map(|(c, _)| {
+                c.try_into().map_err(|e: anyhow::Error| {
+                    Diagnostic::new(ErrorCode::Internal, e.to_string(), None)
+                })
+            })
+            .collect::<Result<Vec<Command>, Diagnostic>>()
+            .map(|code| (code, symbols))
+            .map_err(|d| Diagnostics {
+                list: vec![d],
+                files,
+            })
+    }
+
+    fn parse_usize_arg(toks: &mut TokenStream) -> Result<(usize, Span), Diagnostic> {
+        let (tok, _) = toks.next()?;
+        let val = tok.orign_string.parse::<usize>().map_err(|e| {
+            Diagnostic::new(
+                ErrorCode::InvalidArgument,
+                format!("Failed to parse \"{}\" as usize: {}", tok.orign_string, e),
+                Some(tok.span),
+            )
+        })?;
+        Ok((val, tok.span))
     }
 
-    fn parse_directive(&mut self, toks: &mut TokenStream) -> Result<()> {
+    fn parse_directive(&mut self, toks: &mut TokenStream) -> Result<(), Diagnostic> {
         let (directive, _) = toks.next()?;
+        let redefined = |name: &str, first: Option<Span>| {
+            Diagnostic::new(
+                ErrorCode::DirectiveRedefined,
+                format!("{} directive redefined", name),
+                Some(directive.span),
+            )
+            .with_note("first defined here", first)
+        };
         match &directive.orign_string.to_lowercase().as_str()[1..] {
             "len" => {
-                ensure!(
-                    self.gen_len == -1,
-                    "len directive redefined at {}",
-                    directive
-                );
-                let (len, _) = toks.next()?;
-                self.gen_len = len
-                    .orign_string
-                    .parse::<usize>()
-                    .context(format!("Failed to parse {} as usize", len))?
-                    as isize;
+                if self.gen_len != -1 {
+                    return Err(redefined("len", self.gen_len_span));
+                }
+                let (len, span) = Self::parse_usize_arg(toks)?;
+                if len > MAX_GENOM_LEN {
+                    return Err(Diagnostic::new(
+                        ErrorCode::InvalidArgument,
+                        format!(
+                            "Genome length {} is bigger than the limit of {}",
+                            len, MAX_GENOM_LEN
+                        ),
+                        Some(span),
+                    ));
+                }
+                self.gen_len = len as isize;
+                self.gen_len_span = Some(span);
             }
             "mem_size" => {
-                ensure!(
-                    self.mem_size == -1,
-                    "mem_size directive redefined at {}",
-                    directive
-                );
-                let (size, _) = toks.next()?;
-                self.mem_size = size
-                    .orign_string
-                    .parse::<usize>()
-                    .context(format!("Failed to parse {} as usize", size))?
-                    as isize;
+                if self.mem_size != -1 {
+                    return Err(redefined("mem_size", self.mem_size_span));
+                }
+                let (size, span) = Self::parse_usize_arg(toks)?;
+                self.mem_size = size as isize;
+                self.mem_size_span = Some(span);
+            }
+            "const" => {
+                let (name, span) = self.parse_symbol_name(toks)?;
+                let (val_tok, _) = toks.peek()?;
+                let val = Val::from_toks(toks).map_err(|d| {
+                    d.with_note("constant value must be a number", Some(val_tok.span))
+                })?;
+                self.consts.insert(name.to_lowercase(), (name, val, span));
+            }
+            "var" => {
+                let (name, span) = self.parse_symbol_name(toks)?;
+                // slots are allocated in the order of declaration
+                let addr = self.vars.len() as Mem;
+                self.vars.insert(name.to_lowercase(), (name, addr, span));
+            }
+            _ => {
+                return Err(Diagnostic::new(
+                    ErrorCode::UnknownDirective,
+                    format!("Unexpected directive \"{}\"", directive.orign_string),
+                    Some(directive.span),
+                ))
             }
-            _ => bail!("Unexpected directive {}", directive),
         }
         Ok(())
     }
 
-    fn parse_label(&mut self, toks: &mut TokenStream) -> Result<()> {
+    /// Parses the name of a new constant or variable
+    fn parse_symbol_name(&self, toks: &mut TokenStream) -> Result<(String, Span), Diagnostic> {
+        let (tok, _) = toks.next()?;
+        let name = tok.orign_string.clone();
+        // register and direction names can not be shadowed
+        let reserved = Reg::from_toks(&mut TokenStream::from_vec(vec![tok.clone()])).is_ok()
+            || Dir::from_toks(&mut TokenStream::from_vec(vec![tok.clone()])).is_ok();
+        if !self.symbol_regex.is_match(&name) || reserved {
+            return Err(Diagnostic::new(
+                ErrorCode::InvalidArgument,
+                format!("\"{}\" can not be used as a symbol name", name),
+                Some(tok.span),
+            ));
+        }
+        let key = name.to_lowercase();
+        // a label with the same name would be replaced in jumps by the symbol value
+        let first = (self.consts.get(&key).map(|(_, _, span)| span))
+            .or_else(|| self.vars.get(&key).map(|(_, _, span)| span))
+            .or_else(|| self.exist_labels.get(&key).map(|(_, span)| span));
+        if let Some(first) = first {
+            return Err(Diagnostic::new(
+                ErrorCode::SymbolRedefined,
+                format!("Symbol \"{}\" redefined", name),
+                Some(tok.span),
+            )
+            .with_note("first defined here", Some(*first)));
+        }
+        Ok((name, tok.span))
+    }
+
+    fn is_var(&self, name: &str) -> bool {
+        self.vars.contains_key(&name.to_lowercase())
+    }
+
+    fn parse_label(&mut self, toks: &mut TokenStream) -> Result<(), Diagnostic> {
         let (label, _) = toks.next()?;
-        ensure!(
-            self.exist_labels
-                .insert(
-                    label.orign_string.as_str()[..label.orign_string.len() - 1].to_lowercase(),
-                    self.commands.len()
-                )
-                .is_none(),
-            "label override {}",
-            label
-        );
-        Ok(())
+        let name = label.orign_string.as_str()[..label.orign_string.len() - 1].to_lowercase();
+        if let Some((_, first)) = self.exist_labels.get(&name) {
+            return Err(Diagnostic::new(
+                ErrorCode::LabelRedefined,
+                format!("Label \"{}\" redefined", name),
+                Some(label.span),
+            )
+            .with_note("label defined here", Some(*first)));
+        }
+        let symbol = (self.consts.get(&name).map(|(_, _, span)| span))
+            .or_else(|| self.vars.get(&name).map(|(_, _, span)| span));
+        let res = match symbol {
+            Some(first) => Err(Diagnostic::new(
+                ErrorCode::SymbolRedefined,
+                format!("Label \"{}\" has the name of a symbol", name),
+                Some(label.span),
+            )
+            .with_note("symbol defined here", Some(*first))),
+            None => Ok(()),
+        };
+        // the label is kept to not report the jumps to it
+        self.exist_labels
+            .insert(name, (self.commands.len(), label.span));
+        res
     }
 
-    fn parse_command(&mut self, toks: &mut TokenStream) -> Result<()> {
-        Ok(self.commands.push(Expr::from_toks(toks)?))
+    fn parse_command(&mut self, toks: &mut TokenStream) -> Result<(), Diagnostic> {
+        let (_, index) = toks.peek()?;
+
+        // replacing symbols with their values
+        let mut refs = Vec::new();
+        for (i, t) in toks.rest_of_line_mut().enumerate().skip(1) {
+            let text = t.orign_string.to_lowercase();
+            let (inner, in_brackets) =
+                match text.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+                    Some(inner) => (inner, true),
+                    None => (text.as_str(), false),
+                };
+            if self.exist_labels.contains_key(inner) {
+                continue;
+            }
+            let (name, val) = match (self.consts.get(inner), self.vars.get(inner)) {
+                (Some((name, val, _)), _) => (name, val.to_string()),
+                // variables are addresses, they are used only in brackets
+                (None, Some((name, addr, _))) if in_brackets => (name, addr.to_string()),
+                _ => continue,
+            };
+            t.orign_string = if in_brackets {
+                format!("[{}]", val)
+            } else {
+                val
+            };
+            refs.push(SymbolRef {
+                index: self.commands.len(),
+                arg: i - 1,
+                name: name.clone(),
+            });
+        }
+
+        let (cmd_tok, _) = toks.peek()?;
+        let isa = self.isa;
+        let expr = match isa
+            .find(&cmd_tok.orign_string)
+            .filter(|spec| spec.is_custom())
+        {
+            Some(spec) => {
+                toks.next()?;
+                let args = spec
+                    .args
+                    .iter()
+                    .map(|kind| kind.parse(toks))
+                    .collect::<Result<_, _>>()?;
+                Expr {
+                    cmd: Word::Custom(spec.opcode),
+                    args,
+                }
+            }
+            None => Expr::from_toks(toks)?,
+        };
+        self.commands.push((expr, Some(index)));
+        self.symbol_refs.extend(refs);
+        Ok(())
     }
 }
@@ -0,0 +1,206 @@
+use serde::{Serialize, Serializer};
+use std::fmt::Display;
+
+/// Position of a source fragment
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Span {
+    /// Byte offset of the first character
+    pub start: usize,
+    /// Byte offset after the last character
+    pub end: usize,
+    /// Line number, starting from 1
+    pub line: usize,
+    /// Column number in characters, starting from 1
+    pub column: usize,
+    /// Index of the file in the compiled files list
+    pub file: usize,
+    /// Id of the macro expansion the fragment came from, 0 if none
+    pub expansion: usize,
+}
+
+/// Source of one of the compiled files
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceFile {
+    pub name: String,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    Internal,
+    UnexpectedToken,
+    UnexpectedEnd,
+    UnknownCommand,
+    InvalidArgument,
+    UnknownDirective,
+    DirectiveRedefined,
+    LabelRedefined,
+    LabelNotFound,
+    CodeTooLong,
+    MemOutOfRange,
+    IncludeFailed,
+    UnterminatedMacro,
+    MacroExpansion,
+    SymbolRedefined,
+    MemConflict,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::Internal => "E0000",
+            ErrorCode::UnexpectedToken => "E0001",
+            ErrorCode::UnexpectedEnd => "E0002",
+            ErrorCode::UnknownCommand => "E0003",
+            ErrorCode::InvalidArgument => "E0004",
+            ErrorCode::UnknownDirective => "E0005",
+            ErrorCode::DirectiveRedefined => "E0006",
+            ErrorCode::LabelRedefined => "E0007",
+            ErrorCode::LabelNotFound => "E0008",
+            ErrorCode::CodeTooLong => "E0009",
+            ErrorCode::MemOutOfRange => "E0010",
+            ErrorCode::IncludeFailed => "E0011",
+            ErrorCode::UnterminatedMacro => "E0012",
+            ErrorCode::MacroExpansion => "E0013",
+            ErrorCode::SymbolRedefined => "E0014",
+            ErrorCode::MemConflict => "E0015",
+        }
+    }
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl Serialize for ErrorCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Note {
+    pub message: String,
+    pub span: Option<Span>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Diagnostic {
+    pub code: ErrorCode,
+    pub message: String,
+    pub span: Option<Span>,
+    pub notes: Vec<Note>,
+}
+
+impl Diagnostic {
+    pub fn new<S: Into<String>>(code: ErrorCode, message: S, span: Option<Span>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            span,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn with_note<S: Into<String>>(mut self, message: S, span: Option<Span>) -> Self {
+        self.notes.push(Note {
+            message: message.into(),
+            span,
+        });
+        self
+    }
+
+    /// Renders the diagnostic with excerpts of the sources it was produced from
+    pub fn render(&self, files: &[SourceFile]) -> String {
+        let mut res = format!("error[{}]: {}\n", self.code, self.message);
+        if let Some(span) = &self.span {
+            render_excerpt(&mut res, files, span);
+        }
+        for note in &self.notes {
+            res.push_str(format!("note: {}\n", note.message).as_str());
+            if let Some(span) = &note.span {
+                render_excerpt(&mut res, files, span);
+            }
+        }
+        res
+    }
+}
+
+fn render_excerpt(res: &mut String, files: &[SourceFile], span: &Span) {
+    let Some(SourceFile {
+        name: file_name,
+        text: source,
+    }) = files.get(span.file)
+    else {
+        return;
+    };
+    let line = source
+        .lines()
+        .nth(span.line.saturating_sub(1))
+        .unwrap_or_default();
+    let number = span.line.to_string();
+    let pad = " ".repeat(number.len());
+    let underline_len = source
+        .get(span.start..span.end)
+        .map(|s| s.chars().count())
+        .unwrap_or(1)
+        .max(1);
+    res.push_str(format!("{pad}--> {}:{}:{}\n", file_name, span.line, span.column).as_str());
+    res.push_str(format!("{pad} |\n").as_str());
+    res.push_str(format!("{number} | {line}\n").as_str());
+    res.push_str(
+        format!(
+            "{pad} | {}{}\n",
+            " ".repeat(span.column.saturating_sub(1)),
+            "^".repeat(underline_len)
+        )
+        .as_str(),
+    );
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(span) = &self.span {
+            write!(f, "{}:{}: ", span.line, span.column)?;
+        }
+        write!(f, "error[{}]: {}", self.code, self.message)
+    }
+}
+
+/// All errors found in a compiled file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostics {
+    pub list: Vec<Diagnostic>,
+    /// Files the spans refer to, the compiled file is the first
+    pub files: Vec<SourceFile>,
+}
+
+impl Diagnostics {
+    pub fn render(&self) -> String {
+        self.list
+            .iter()
+            .map(|d| d.render(&self.files))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&self.list).unwrap_or_else(|_| "[]".into())
+    }
+}
+
+impl Display for Diagnostics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, d) in self.list.iter().enumerate() {
+            if i != 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", d)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostics {}
@@ -0,0 +1,168 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use crate::{
+    analysis::{flow, Flow},
+    code::{
+        command::{CommandArg, Expr},
+        Command,
+    },
+    symbols::SymbolMap,
+};
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+enum LabelKind {
+    Jump,
+    Spawn,
+    Call,
+}
+
+struct Target {
+    kind: LabelKind,
+    refs: BTreeSet<usize>,
+}
+
+/// Disassembles the genome into a listing with instruction addresses, basic blocks,
+/// labels named by their use, cross-references and unreachable instructions.
+/// The listing remains valid botlang code.
+pub fn disassemble(code: &[Command]) -> Vec<String> {
+    disassemble_with_symbols(code, &SymbolMap::default())
+}
+
+/// Disassembles the genome using the names and source lines from its symbol map
+pub fn disassemble_with_symbols(code: &[Command], symbols: &SymbolMap) -> Vec<String> {
+    let len = code.len();
+    let next = |i: usize| (i + 1) % len.max(1);
+
+    // collect jump targets with their kinds and references
+    let mut targets: BTreeMap<usize, Target> = BTreeMap::new();
+    for (i, c) in code.iter().enumerate() {
+        let (kind, l) = match flow(c) {
+            Flow::Jump(l) | Flow::Branch(l) => (LabelKind::Jump, l),
+            Flow::Call(l) => (LabelKind::Call, l),
+            Flow::Spawn(l) => (LabelKind::Spawn, l),
+            Flow::Next | Flow::Ret => continue,
+        };
+        let t = targets.entry(l).or_insert(Target {
+            kind,
+            refs: BTreeSet::new(),
+        });
+        // a label used in several ways is named by the most specific one
+        t.kind = t.kind.max(kind);
+        t.refs.insert(i);
+    }
+
+    // reachability from the genome start and from the start points of new bots
+    let mut reachable = vec![false; len];
+    let mut queue = VecDeque::from([0]);
+    while let Some(i) = queue.pop_front() {
+        if i >= len || reachable[i] {
+            continue;
+        }
+        reachable[i] = true;
+        match flow(&code[i]) {
+            Flow::Next => queue.push_back(next(i)),
+            Flow::Jump(l) => queue.push_back(l),
+            Flow::Branch(l) | Flow::Call(l) | Flow::Spawn(l) => {
+                queue.push_back(next(i));
+                queue.push_back(l);
+            }
+            // return addresses are the instructions after calls, they are already queued
+            Flow::Ret => {}
+        }
+    }
+
+    // basic block leaders: the start, jump targets and instructions after control transfers
+    let mut leaders: BTreeSet<usize> = targets.keys().copied().filter(|l| *l < len).collect();
+    leaders.insert(0);
+    for (i, c) in code.iter().enumerate() {
+        if !matches!(flow(c), Flow::Next | Flow::Spawn(_)) && i + 1 < len {
+            leaders.insert(i + 1);
+        }
+    }
+
+    let label_name = |l: usize| -> String {
+        if let Some(name) = symbols.label_name(l) {
+            return name.to_string();
+        }
+        let prefix = match targets.get(&l).map(|t| t.kind) {
+            Some(LabelKind::Call) => "func",
+            Some(LabelKind::Spawn) => "child",
+            _ => "label",
+        };
+        format!("{prefix}_{l}")
+    };
+
+    let refs = |t: &Target| {
+        t.refs
+            .iter()
+            .map(|r| r.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    let mut res = symbols.declarations();
+    if !res.is_empty() {
+        res.push(String::new());
+    }
+    let mut block = 0;
+    for (i, c) in code.iter().enumerate() {
+        if leaders.contains(&i) {
+            if i != 0 {
+                res.push(String::new());
+            }
+            res.push(format!("// block {block}"));
+            block += 1;
+        }
+        // labels out of the genome are defined where the VM wraps them by default,
+        // so that the listing still compiles
+        for (l, t) in targets.range(len..).filter(|(l, _)| *l % len == i) {
+            res.push(format!(
+                "{:<31} // invalid target, referenced from {}",
+                format!("{}:", label_name(*l)),
+                refs(t)
+            ));
+        }
+        if let Some(t) = targets.get(&i) {
+            res.push(format!(
+                "{:<31} // referenced from {}",
+                format!("{}:", label_name(i)),
+                refs(t)
+            ));
+        }
+
+        let expr = TryInto::<Expr>::try_into(c.clone()).unwrap();
+        let mut line = format!("    {}", symbols.command_name(expr.cmd));
+        let mut invalid_target = None;
+        for (arg, a) in expr.args.into_iter().enumerate() {
+            match a {
+                CommandArg::Label(l) => {
+                    line.push_str(format!(" {}", label_name(l)).as_str());
+                    if l >= len {
+                        invalid_target = Some(l);
+                    }
+                }
+                CommandArg::Mem(addr) => match symbols.mem_name(i, arg, addr) {
+                    Some(name) => line.push_str(format!(" [{name}]").as_str()),
+                    None => line.push_str(format!(" [{addr}]").as_str()),
+                },
+                CommandArg::Val(v) => match symbols.val_name(i, arg, v) {
+                    Some(name) => line.push_str(format!(" {name}").as_str()),
+                    None => line.push_str(format!(" {v}").as_str()),
+                },
+                a => line.push_str(format!(" {a}").as_str()),
+            }
+        }
+        let mut comment = format!("// {i:04}");
+        if !reachable[i] {
+            comment.push_str(" unreachable");
+        }
+        if let Some(l) = invalid_target {
+            comment.push_str(format!(" invalid target {l}").as_str());
+        }
+        if let Some(src) = symbols.source_line(i) {
+            comment.push_str(format!(" {}:{}: {}", src.file, src.line, src.text).as_str());
+        }
+        res.push(format!("{line:<31} {comment}"));
+    }
+    res
+}
@@ -0,0 +1,157 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    analysis,
+    code::{ArgKind, CommandWord, Opcode, Word},
+};
+
+/// Opcodes below are reserved for the builtin commands, they are pinned in the list
+/// of commands of `decl_command_enum!`
+pub const CUSTOM_OPCODES_START: Opcode = 0x100;
+
+/// Description of an instruction: its name in botlang, stable opcode and arguments
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InstructionSpec {
+    pub name: String,
+    pub opcode: Opcode,
+    #[serde(default)]
+    pub args: Vec<ArgKind>,
+    /// whether the instruction ends the cycle of the bot
+    #[serde(default)]
+    pub action: bool,
+}
+
+impl InstructionSpec {
+    pub fn new(name: &str, opcode: Opcode, args: Vec<ArgKind>, action: bool) -> Self {
+        Self {
+            name: name.to_lowercase(),
+            opcode,
+            args,
+            action,
+        }
+    }
+
+    pub fn is_custom(&self) -> bool {
+        self.opcode >= CUSTOM_OPCODES_START
+    }
+}
+
+/// Spec file with the custom instructions
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct SpecFile {
+    instructions: Vec<InstructionSpec>,
+}
+
+/// Instructions known to the compiler and the bot VM, the builtin commands and
+/// the registered custom ones
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstructionSet {
+    specs: BTreeMap<Opcode, InstructionSpec>,
+}
+
+impl Default for InstructionSet {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl InstructionSet {
+    pub fn builtin() -> Self {
+        let specs = CommandWord::ALL
+            .iter()
+            .map(|w| {
+                let spec = InstructionSpec {
+                    name: w.to_string(),
+                    opcode: w.opcode(),
+                    args: w.arg_kinds(),
+                    action: analysis::is_action_word(*w),
+                };
+                (spec.opcode, spec)
+            })
+            .collect();
+        Self { specs }
+    }
+
+    /// Adds the custom instruction, its opcode and name must not be taken
+    pub fn register(&mut self, spec: InstructionSpec) -> Result<(), String> {
+        if !spec.is_custom() {
+            return Err(format!(
+                "Opcode {} of \"{}\" is reserved, custom opcodes start from {}",
+                spec.opcode, spec.name, CUSTOM_OPCODES_START
+            ));
+        }
+        if spec.name.is_empty() || !spec.name.chars().all(|c| c.is_ascii_alphabetic()) {
+            return Err(format!(
+                "Invalid instruction name \"{}\", only letters are allowed",
+                spec.name
+            ));
+        }
+        if let Some(other) = self.specs.get(&spec.opcode) {
+            return Err(format!(
+                "Opcode {} of \"{}\" is taken by \"{}\"",
+                spec.opcode, spec.name, other.name
+            ));
+        }
+        if self.find(&spec.name).is_some() {
+            return Err(format!("Instruction \"{}\" already exists", spec.name));
+        }
+        let spec = InstructionSpec {
+            name: spec.name.to_lowercase(),
+            ..spec
+        };
+        self.specs.insert(spec.opcode, spec);
+        Ok(())
+    }
+
+    /// Builtin commands with the custom instructions of the spec file
+    pub fn from_json(json: &str) -> Result<InstructionSet, String> {
+        let file: SpecFile = serde_json::from_str(json)
+            .map_err(|e| format!("Failed to parse instruction set: {e}"))?;
+        let mut isa = Self::builtin();
+        for spec in file.instructions {
+            isa.register(spec)?;
+        }
+        Ok(isa)
+    }
+
+    /// Spec file with the custom instructions
+    pub fn to_json(&self) -> Result<String, String> {
+        let file = SpecFile {
+            instructions: self.custom().cloned().collect(),
+        };
+        serde_json::to_string_pretty(&file)
+            .map_err(|e| format!("Failed to serialize instruction set: {e}"))
+    }
+
+    pub fn get(&self, opcode: Opcode) -> Option<&InstructionSpec> {
+        self.specs.get(&opcode)
+    }
+
+    /// Instruction by its case-insensitive name
+    pub fn find(&self, name: &str) -> Option<&InstructionSpec> {
+        let name = name.to_lowercase();
+        self.specs.values().find(|s| s.name == name)
+    }
+
+    /// Spec of the command, custom opcodes never refer to the builtin commands
+    pub fn spec(&self, word: Word) -> Option<&InstructionSpec> {
+        match word {
+            Word::Builtin(w) => self.get(w.opcode()),
+            Word::Custom(opcode) => self.get(opcode).filter(|s| s.is_custom()),
+        }
+    }
+
+    /// Name of the command, unknown custom instructions are named `op<opcode>`
+    pub fn name(&self, word: Word) -> String {
+        match self.spec(word) {
+            Some(spec) => spec.name.clone(),
+            None => word.to_string(),
+        }
+    }
+
+    pub fn custom(&self) -> impl Iterator<Item = &InstructionSpec> {
+        self.specs.values().filter(|s| s.is_custom())
+    }
+}
@@ -1,21 +1,33 @@
+pub mod analysis;
 pub mod code;
 pub mod code_packer;
 pub mod compiler;
+pub mod diagnostic;
+pub mod disassembler;
+pub mod isa;
+pub(crate) mod preprocessor;
+pub mod symbols;
 pub(crate) mod token;
 
 macro_rules! decl_tokens_enum {
     ($enum_name:ident, $(($str_name:literal, $enum_entry:ident)),*) => {
-        #[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Copy, num_derive::FromPrimitive, Eq, PartialEq)]
+        #[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Copy, num_derive::FromPrimitive, Eq, PartialEq, Ord, PartialOrd, Hash)]
         pub enum $enum_name {
             $($enum_entry),*
         }
 
         impl crate::token::FromTokenStream for $enum_name {
-            fn from_toks(toks: &mut crate::token::TokenStream) -> anyhow::Result<$enum_name> {
+            fn from_toks(
+                toks: &mut crate::token::TokenStream,
+            ) -> Result<$enum_name, crate::diagnostic::Diagnostic> {
                 let (cmd_tok, _) = toks.next()?;
                 Ok(match cmd_tok.orign_string.to_lowercase().as_str() {
                     $($str_name => $enum_name::$enum_entry),*,
-                    _ => anyhow::bail!("Failed to parse {} as {}", cmd_tok, stringify!($enum_name))
+                    _ => return Err(crate::diagnostic::Diagnostic::new(
+                        crate::diagnostic::ErrorCode::InvalidArgument,
+                        format!("Failed to parse \"{}\" as {}", cmd_tok.orign_string, stringify!($enum_name)),
+                        Some(cmd_tok.span),
+                    ))
                 })
             }
         }
@@ -28,6 +40,16 @@ macro_rules! decl_tokens_enum {
             }
         }
 
+        impl std::str::FromStr for $enum_name {
+            type Err = String;
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                match s.to_lowercase().as_str() {
+                    $($str_name => Ok($enum_name::$enum_entry)),*,
+                    _ => Err(format!("Failed to parse \"{}\" as {}", s, stringify!($enum_name))),
+                }
+            }
+        }
+
         impl rand::prelude::Distribution<$enum_name> for rand::distributions::Standard {
             fn sample<R: rand::Rng + ?Sized>(&self, rng: &mut R) -> $enum_name {
                 const ENUM_VARIANT_COUNTL: usize = [
@@ -53,35 +75,29 @@ use wasm_bindgen::prelude::*;
 #[cfg(target_arch = "wasm32")]
 #[wasm_bindgen]
 pub fn compile(input: String) -> String {
-    match compiler::compile(input) {
-        Ok(code) => {
-            match code_packer::to_b32(&code) {
-                Ok(res) => format!("Compiled successfully.\nBot Code:\n{}", res),
-                Err(err) => format!("Failed to encode compiled code: {err}")
-            }
+    match compiler::compile_with_symbols(input) {
+        Ok((code, symbols)) => match code_packer::encode(&code, symbols.mem_size) {
+            Ok(res) => format!("Compiled successfully.\nBot Code:\n{}", res),
+            Err(err) => format!("Failed to encode compiled code: {err}"),
         },
-        Err(e) => {
-            let mut err = String::new();
-            err.push_str(format!("{e}").as_str());
-            for e in e.chain() {
-                err.push_str(format!("\n    {e}").as_str());
-            }
-            err
-        }
+        // list of diagnostics for the editor
+        Err(diagnostics) => diagnostics.to_json(),
     }
 }
 
 #[cfg(target_arch = "wasm32")]
 #[wasm_bindgen]
 pub fn decompile(input: String) -> String {
-    match code_packer::from_b32(&input) {
-        Ok(code) => {
-            compiler::decompile(code).iter().fold(String::new(), |mut acc, cmd| {
-                acc.push_str(format!("{}", cmd).as_str());
-                acc.push_str("\n");
-                acc
-            })
-        },
+    match code_packer::unpack(input.as_bytes()) {
+        Ok(unpacked) => {
+            compiler::decompile(unpacked.commands)
+                .iter()
+                .fold(String::new(), |mut acc, cmd| {
+                    acc.push_str(format!("{}", cmd).as_str());
+                    acc.push_str("\n");
+                    acc
+                })
+        }
         Err(err) => format!("Failed to decode code: {err}"),
     }
 }
@@ -1,9 +1,19 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use clap::Parser;
 use clap_derive::Parser;
-use std::fs::{read_to_string, write};
+use std::{
+    fs::{read, read_to_string, write},
+    path::Path,
+};
 
-use botc::{code_packer, compiler};
+use botc::{
+    analysis::{self, Limits},
+    code::Command,
+    code_packer::{self, Encoding},
+    compiler, disassembler,
+    isa::InstructionSet,
+    symbols::SymbolMap,
+};
 
 /// Genetic code compiler
 #[derive(Parser)]
@@ -19,32 +29,127 @@ struct Options {
     /// Decompile the input file
     #[arg(short, long, default_value_t = false)]
     decompile: bool,
+
+    /// Decompile without addresses, blocks and cross-references
+    #[arg(short, long, default_value_t = false, requires = "decompile")]
+    raw: bool,
+
+    /// Save the symbol map next to the output file, it is used by the decompiler
+    /// when found next to the input file
+    #[arg(short, long, default_value_t = false, requires = "output")]
+    map: bool,
+
+    /// Encoding of the compiled code: base32, base64url, hex, binary or text.
+    /// The decompiler detects the encoding itself
+    #[arg(short, long, value_name = "format", default_value_t = Encoding::Base32)]
+    format: Encoding,
+
+    /// Check the code for unreachable instructions, loops without actions, invalid
+    /// addresses and unbalanced calls. Fails if errors are found
+    #[arg(short, long, default_value_t = false)]
+    check: bool,
+
+    /// RAM size of the bot for the check, the `#mem_size` of the source by default
+    #[arg(long, value_name = "size", requires = "check")]
+    ram_size: Option<usize>,
+
+    /// Spec file with the custom instructions of the code
+    #[arg(short, long, value_name = "spec")]
+    isa: Option<String>,
+}
+
+fn check(commands: &[Command], ram_size: Option<usize>, isa: &InstructionSet) -> Result<()> {
+    let limits = Limits {
+        ram_size,
+        ..Default::default()
+    };
+    let issues = analysis::analyze_with_isa(commands, &limits, isa);
+    for issue in issues.iter() {
+        eprintln!("{}", issue);
+    }
+    let errors = issues.iter().filter(|i| i.is_error()).count();
+    if errors > 0 {
+        bail!("Check failed: {} error(s)", errors);
+    }
+    Ok(())
 }
 
 fn main() -> Result<()> {
     let opt = Options::parse();
-    let code = read_to_string(&opt.input)
-        .context(format!("Failed to open input file \"{}\"", opt.input))?;
-
-    let result: String = if opt.decompile {
-        let decoded = code_packer::from_b32(&code).context("Failed to decode input file")?;
-        botc::compiler::decompile(decoded)
+    let isa = match &opt.isa {
+        Some(path) => {
+            let json = read_to_string(path)
+                .context(format!("Failed to open instruction set \"{}\"", path))?;
+            InstructionSet::from_json(&json).map_err(anyhow::Error::msg)?
+        }
+        None => InstructionSet::builtin(),
+    };
+    let result: Vec<u8> = if opt.decompile {
+        let code =
+            read(&opt.input).context(format!("Failed to open input file \"{}\"", opt.input))?;
+        let unpacked = code_packer::unpack(&code).context("Failed to decode input file")?;
+        let decoded = unpacked.commands;
+        let map_path = SymbolMap::path_for(Path::new(&opt.input));
+        let mut symbols = match read_to_string(&map_path) {
+            Ok(json) => SymbolMap::from_json(&json).map_err(anyhow::Error::msg)?,
+            Err(_) => SymbolMap::default(),
+        };
+        // the spec file names the custom instructions the symbol map does not know
+        for spec in isa.custom() {
+            if !symbols.instructions.iter().any(|s| s.opcode == spec.opcode) {
+                symbols.instructions.push(spec.clone());
+            }
+        }
+        if opt.check {
+            let isa = symbols.instruction_set().map_err(anyhow::Error::msg)?;
+            check(&decoded, opt.ram_size.or(unpacked.header.mem_size), &isa)?;
+        }
+        let listing = if opt.raw {
+            compiler::decompile_with_symbols(decoded, &symbols)
+        } else {
+            disassembler::disassemble_with_symbols(&decoded, &symbols)
+        };
+        listing
             .iter()
             .fold(String::new(), |mut acc, cmd| {
-                acc.push_str(format!("{}", cmd).as_str());
-                acc.push_str("\n");
+                acc.push_str(cmd.to_string().as_str());
+                acc.push('\n');
                 acc
             })
+            .into_bytes()
     } else {
-        let commands = compiler::compile(code)
-            .context(format!("Failed to compile input file \"{}\"", opt.input))?;
-        code_packer::to_b32(&commands).context("Failed to encode compiled code")?
+        let (commands, symbols) = match compiler::compile_file_with_isa(Path::new(&opt.input), &isa)
+        {
+            Ok(res) => res,
+            Err(diagnostics) => {
+                eprintln!("{}", diagnostics.render());
+                bail!(
+                    "Failed to compile input file \"{}\": {} error(s)",
+                    opt.input,
+                    diagnostics.list.len()
+                );
+            }
+        };
+        if opt.check {
+            check(&commands, opt.ram_size.or(symbols.mem_size), &isa)?;
+        }
+        if let (true, Some(file)) = (opt.map, &opt.output) {
+            let map_path = SymbolMap::path_for(Path::new(file));
+            write(&map_path, symbols.to_json().map_err(anyhow::Error::msg)?).context(format!(
+                "Failed to write symbol map \"{}\"",
+                map_path.to_string_lossy()
+            ))?;
+        }
+        code_packer::pack(&commands, symbols.mem_size, opt.format)
+            .context("Failed to encode compiled code")?
     };
 
     if let Some(file) = &opt.output {
         write(file, result).context(format!("Failed to output input file \"{}\"", file))?;
+    } else if opt.format == Encoding::Binary && !opt.decompile {
+        bail!("Binary code can only be written to the output file");
     } else {
-        println!("Result:\n{}", result);
+        println!("Result:\n{}", String::from_utf8_lossy(&result));
     }
     Ok(())
 }
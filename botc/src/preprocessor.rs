@@ -0,0 +1,473 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use regex::Regex;
+
+use crate::{
+    code::LABEL_REGEX,
+    compiler::MAX_GENOM_LEN,
+    diagnostic::{Diagnostic, ErrorCode, SourceFile, Span},
+    token::Token,
+};
+
+pub(crate) const LINE_COMMENTS_START: &str = "//";
+// protection against recursive macros
+const MAX_EXPANSION_DEPTH: usize = 32;
+// protection against macros and includes doubling the code at every level,
+// counts the lines they produce, a genome is much shorter anyway
+const MAX_EXPANDED_LINES: usize = 4 * MAX_GENOM_LEN;
+
+/// Origin of a token that was not written at its place: a macro expansion or a define
+/// substitution. Tokens refer to it by `Span::expansion`, ids start from 1.
+pub struct Expansion {
+    pub message: String,
+    pub span: Span,
+    // expansion of the place where this one happened, 0 if none
+    pub parent: usize,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Vec<Token>>,
+    // labels defined in the body, they are made unique for every expansion
+    labels: HashSet<String>,
+}
+
+pub struct Preprocessed {
+    pub toks: Vec<Token>,
+    pub files: Vec<SourceFile>,
+    pub expansions: Vec<Expansion>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Resolves `#include`, `#define` and `#macro` directives.
+/// Other directives are passed to the compiler.
+pub struct Preprocessor {
+    files: Vec<SourceFile>,
+    // allows includes if set
+    include_paths: bool,
+    include_stack: Vec<PathBuf>,
+    defines: HashMap<String, (String, Span)>,
+    macros: HashMap<String, Macro>,
+    expansions: Vec<Expansion>,
+    diagnostics: Vec<Diagnostic>,
+    toks: Vec<Token>,
+    line_cnt: usize,
+    // lines produced by macros and includes
+    expanded_lines: usize,
+    label_regex: Regex,
+}
+
+impl Preprocessor {
+    fn new(include_paths: bool) -> Self {
+        Self {
+            files: Vec::new(),
+            include_paths,
+            include_stack: Vec::new(),
+            defines: HashMap::new(),
+            macros: HashMap::new(),
+            expansions: Vec::new(),
+            diagnostics: Vec::new(),
+            toks: Vec::new(),
+            line_cnt: 0,
+            expanded_lines: 0,
+            label_regex: Regex::new(LABEL_REGEX).unwrap(),
+        }
+    }
+
+    /// Preprocesses the code, includes are not allowed
+    pub fn process_code(code: &str, name: &str) -> Preprocessed {
+        let mut pp = Self::new(false);
+        pp.process_file(name.into(), code.to_string(), None, 0);
+        pp.finish()
+    }
+
+    /// Preprocesses the file, includes are searched relative to the including file
+    pub fn process_path(path: &Path) -> Preprocessed {
+        let mut pp = Self::new(true);
+        match std::fs::read_to_string(path) {
+            Ok(code) => pp.process_file(path.to_string_lossy().into(), code, Some(path.into()), 0),
+            Err(e) => pp.diagnostics.push(Diagnostic::new(
+                ErrorCode::IncludeFailed,
+                format!("Failed to read file \"{}\": {}", path.to_string_lossy(), e),
+                None,
+            )),
+        }
+        pp.finish()
+    }
+
+    fn finish(self) -> Preprocessed {
+        Preprocessed {
+            toks: self.toks,
+            files: self.files,
+            expansions: self.expansions,
+            diagnostics: self.diagnostics,
+        }
+    }
+
+    fn process_file(&mut self, name: String, code: String, path: Option<PathBuf>, depth: usize) {
+        let file = self.files.len();
+        let lines = tokenize(&code, file);
+        self.files.push(SourceFile { name, text: code });
+        if let Some(path) = &path {
+            // the same file may be reached by different relative paths
+            self.include_stack
+                .push(path.canonicalize().unwrap_or_else(|_| path.clone()));
+        }
+        self.process_lines(lines, depth);
+        if path.is_some() {
+            self.include_stack.pop();
+        }
+    }
+
+    fn error(&mut self, code: ErrorCode, message: String, span: Span) {
+        self.diagnostics
+            .push(Diagnostic::new(code, message, Some(span)));
+    }
+
+    /// Counts the lines produced by a macro or an include,
+    /// reports once and returns false if there are too many
+    fn expand_budget(&mut self, lines: usize, span: Span) -> bool {
+        if self.expanded_lines > MAX_EXPANDED_LINES {
+            return false;
+        }
+        self.expanded_lines += lines;
+        if self.expanded_lines > MAX_EXPANDED_LINES {
+            self.error(
+                ErrorCode::MacroExpansion,
+                format!(
+                    "Macros and includes produce more than {} lines",
+                    MAX_EXPANDED_LINES
+                ),
+                span,
+            );
+            return false;
+        }
+        true
+    }
+
+    fn process_lines(&mut self, lines: Vec<Vec<Token>>, depth: usize) {
+        let mut lines = lines.into_iter();
+        while let Some(line) = lines.next() {
+            let first = &line[0];
+            match first.orign_string.to_lowercase().as_str() {
+                "#include" => self.include(&line, depth),
+                "#define" => self.define(&line),
+                "#macro" => {
+                    // the macro body lasts until #endmacro
+                    let mut body = Vec::new();
+                    let mut closed = false;
+                    for l in lines.by_ref() {
+                        if l[0].orign_string.eq_ignore_ascii_case("#endmacro") {
+                            closed = true;
+                            break;
+                        }
+                        body.push(l);
+                    }
+                    if closed {
+                        self.define_macro(&line, body);
+                    } else {
+                        self.error(
+                            ErrorCode::UnterminatedMacro,
+                            "Macro without #endmacro".into(),
+                            first.span,
+                        );
+                    }
+                }
+                "#endmacro" => self.error(
+                    ErrorCode::UnexpectedToken,
+                    "#endmacro without #macro".into(),
+                    first.span,
+                ),
+                name if self.macros.contains_key(name) => {
+                    if depth >= MAX_EXPANSION_DEPTH {
+                        self.error(
+                            ErrorCode::MacroExpansion,
+                            format!(
+                                "Macro \"{}\" expansion is too deep, recursive macro?",
+                                first.orign_string
+                            ),
+                            first.span,
+                        );
+                        continue;
+                    }
+                    let expanded = self.expand(name.to_string(), &line);
+                    if self.expand_budget(expanded.len(), first.span) {
+                        self.process_lines(expanded, depth + 1);
+                    }
+                }
+                _ => {
+                    self.line_cnt += 1;
+                    for mut t in line {
+                        self.substitute_define(&mut t);
+                        t.line_index = self.line_cnt;
+                        self.toks.push(t);
+                    }
+                }
+            }
+        }
+    }
+
+    fn include(&mut self, line: &[Token], depth: usize) {
+        let directive = &line[0];
+        let [_, name_tok] = line else {
+            self.error(
+                ErrorCode::InvalidArgument,
+                "#include expects one file name in quotes".into(),
+                directive.span,
+            );
+            return;
+        };
+        let Some(name) = name_tok
+            .orign_string
+            .strip_prefix('"')
+            .and_then(|s| s.strip_suffix('"'))
+        else {
+            self.error(
+                ErrorCode::InvalidArgument,
+                format!(
+                    "Failed to parse {} as file name in quotes",
+                    name_tok.orign_string
+                ),
+                name_tok.span,
+            );
+            return;
+        };
+        if !self.include_paths {
+            self.error(
+                ErrorCode::IncludeFailed,
+                "Includes are not supported here".into(),
+                directive.span,
+            );
+            return;
+        }
+        let path = match self.include_stack.last().and_then(|p| p.parent()) {
+            Some(dir) => dir.join(name),
+            None => PathBuf::from(name),
+        };
+        let included = path
+            .canonicalize()
+            .is_ok_and(|p| self.include_stack.contains(&p));
+        if included {
+            self.error(
+                ErrorCode::IncludeFailed,
+                format!("File \"{}\" includes itself", name),
+                name_tok.span,
+            );
+            return;
+        }
+        if depth >= MAX_EXPANSION_DEPTH {
+            self.error(
+                ErrorCode::IncludeFailed,
+                format!(
+                    "File \"{}\" is included too deep, more than {} levels",
+                    name, MAX_EXPANSION_DEPTH
+                ),
+                name_tok.span,
+            );
+            return;
+        }
+        match std::fs::read_to_string(&path) {
+            Ok(code) => {
+                if self.expand_budget(code.lines().count(), name_tok.span) {
+                    self.process_file(path.to_string_lossy().into(), code, Some(path), depth + 1)
+                }
+            }
+            Err(e) => self.error(
+                ErrorCode::IncludeFailed,
+                format!("Failed to read file \"{}\": {}", path.to_string_lossy(), e),
+                name_tok.span,
+            ),
+        }
+    }
+
+    fn define(&mut self, line: &[Token]) {
+        let [directive, name, value] = line else {
+            self.error(
+                ErrorCode::InvalidArgument,
+                "#define expects a name and a value".into(),
+                line[0].span,
+            );
+            return;
+        };
+        let key = name.orign_string.to_lowercase();
+        if let Some((_, first)) = self.defines.get(&key) {
+            self.diagnostics.push(
+                Diagnostic::new(
+                    ErrorCode::DirectiveRedefined,
+                    format!("\"{}\" redefined", name.orign_string),
+                    Some(directive.span),
+                )
+                .with_note("first defined here", Some(*first)),
+            );
+            return;
+        }
+        let mut value = value.clone();
+        self.substitute_define(&mut value);
+        self.defines.insert(key, (value.orign_string, name.span));
+    }
+
+    fn define_macro(&mut self, line: &[Token], body: Vec<Vec<Token>>) {
+        let directive = &line[0];
+        let Some(name) = line.get(1) else {
+            self.error(
+                ErrorCode::InvalidArgument,
+                "#macro expects a name".into(),
+                directive.span,
+            );
+            return;
+        };
+        let key = name.orign_string.to_lowercase();
+        if self.macros.contains_key(&key) {
+            self.error(
+                ErrorCode::DirectiveRedefined,
+                format!("Macro \"{}\" redefined", name.orign_string),
+                name.span,
+            );
+            return;
+        }
+        let labels = body
+            .iter()
+            .flatten()
+            .filter(|t| self.label_regex.is_match(&t.orign_string))
+            .map(|t| t.orign_string[..t.orign_string.len() - 1].to_lowercase())
+            .collect();
+        self.macros.insert(
+            key,
+            Macro {
+                params: line[2..]
+                    .iter()
+                    .map(|t| t.orign_string.to_lowercase())
+                    .collect(),
+                body,
+                labels,
+            },
+        );
+    }
+
+    fn expand(&mut self, name: String, line: &[Token]) -> Vec<Vec<Token>> {
+        let invocation = &line[0];
+        let m = &self.macros[&name];
+        let args = &line[1..];
+        if args.len() != m.params.len() {
+            let msg = format!(
+                "Macro \"{}\" expects {} arguments, got {}",
+                invocation.orign_string,
+                m.params.len(),
+                args.len()
+            );
+            self.error(ErrorCode::MacroExpansion, msg, invocation.span);
+            return Vec::new();
+        }
+
+        self.expansions.push(Expansion {
+            message: format!("in expansion of macro \"{}\"", invocation.orign_string),
+            span: invocation.span,
+            parent: invocation.span.expansion,
+        });
+        let id = self.expansions.len();
+
+        m.body
+            .iter()
+            .map(|l| {
+                l.iter()
+                    .map(|t| {
+                        let text = t.orign_string.to_lowercase();
+                        // parameters, also as memory address
+                        let (inner, in_brackets) =
+                            match text.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+                                Some(inner) => (inner, true),
+                                None => (text.as_str(), false),
+                            };
+                        if let Some(p) = m.params.iter().position(|p| p == inner) {
+                            let mut arg = args[p].clone();
+                            if in_brackets {
+                                arg.orign_string = format!("[{}]", arg.orign_string);
+                            }
+                            return arg;
+                        }
+
+                        let mut t = t.clone();
+                        t.span.expansion = id;
+                        // local labels and references to them
+                        if let Some(label) = text.strip_suffix(':') {
+                            if m.labels.contains(label) {
+                                t.orign_string = format!("{}__m{}:", label, id);
+                            }
+                        } else if m.labels.contains(&text) {
+                            t.orign_string = format!("{}__m{}", text, id);
+                        }
+                        t
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn substitute_define(&mut self, t: &mut Token) {
+        let text = t.orign_string.to_lowercase();
+        let (inner, in_brackets) = match text.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            Some(inner) => (inner, true),
+            None => (text.as_str(), false),
+        };
+        let Some((value, span)) = self.defines.get(inner) else {
+            return;
+        };
+        self.expansions.push(Expansion {
+            message: format!("\"{}\" is defined here", inner),
+            span: *span,
+            parent: t.span.expansion,
+        });
+        t.span.expansion = self.expansions.len();
+        t.orign_string = if in_brackets {
+            format!("[{}]", value)
+        } else {
+            value.clone()
+        };
+    }
+}
+
+/// Removes comments and splits the code into lines of tokens
+fn tokenize(code: &str, file: usize) -> Vec<Vec<Token>> {
+    let mut lines = Vec::new();
+    let mut line_offset = 0;
+    for (line_index, line) in code.split_inclusive('\n').enumerate() {
+        // removing comments
+        let l = match line.find(LINE_COMMENTS_START) {
+            Some(i) => &line[..i],
+            None => line,
+        };
+        // splitting into tokens
+        let mut toks = Vec::new();
+        let mut word_start = None;
+        for (i, c) in l.char_indices().chain([(l.len(), ' ')]) {
+            match (c.is_whitespace(), word_start) {
+                (false, None) => word_start = Some(i),
+                (true, Some(start)) => {
+                    word_start = None;
+                    toks.push(Token {
+                        line_index: line_index + 1,
+                        word_index: toks.len() + 1,
+                        orign_string: l[start..i].to_string(),
+                        span: Span {
+                            start: line_offset + start,
+                            end: line_offset + i,
+                            line: line_index + 1,
+                            column: l[..start].chars().count() + 1,
+                            file,
+                            expansion: 0,
+                        },
+                    });
+                }
+                _ => {}
+            }
+        }
+        if !toks.is_empty() {
+            lines.push(toks);
+        }
+        line_offset += line.len();
+    }
+    lines
+}
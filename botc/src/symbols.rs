@@ -0,0 +1,146 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    code::{Label, Mem, Val, Word},
+    isa::{InstructionSet, InstructionSpec},
+};
+
+/// Extension of the symbol map file saved next to the compiled genome
+pub const SYMBOL_MAP_EXTENSION: &str = "map";
+
+/// Argument of an instruction that was written as a symbol name
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SymbolRef {
+    /// Index of the instruction in the genome
+    pub index: usize,
+    /// Index of the argument in the instruction
+    pub arg: usize,
+    pub name: String,
+}
+
+/// Source line an instruction was compiled from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceLine {
+    pub file: String,
+    /// Line number, starting from 1
+    pub line: usize,
+    pub text: String,
+}
+
+/// Debug information lost when the genome is packed: names the compiler has given to
+/// numbers and the source of every instruction. Used to decompile the genome back
+/// into readable code.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SymbolMap {
+    /// Source of every instruction, padding instructions have none
+    pub lines: Vec<Option<SourceLine>>,
+    /// Label name -> instruction index
+    pub labels: BTreeMap<String, Label>,
+    /// `#const` name -> value
+    pub consts: BTreeMap<String, Val>,
+    /// `#var` name -> allocated memory address
+    pub vars: BTreeMap<String, Mem>,
+    pub refs: Vec<SymbolRef>,
+    /// `#mem_size` of the code, it is also kept in the packed genome
+    #[serde(default)]
+    pub mem_size: Option<usize>,
+    /// Custom instructions used in the code
+    #[serde(default)]
+    pub instructions: Vec<InstructionSpec>,
+}
+
+impl SymbolMap {
+    /// Path of the symbol map saved next to the genome file
+    pub fn path_for(code_path: &Path) -> PathBuf {
+        code_path.with_extension(SYMBOL_MAP_EXTENSION)
+    }
+
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize symbol map: {e}"))
+    }
+
+    pub fn from_json(json: &str) -> Result<SymbolMap, String> {
+        serde_json::from_str(json).map_err(|e| format!("Failed to parse symbol map: {e}"))
+    }
+
+    /// Name of the command, custom instructions without a spec are named `op<opcode>`
+    pub fn command_name(&self, word: Word) -> String {
+        match word {
+            Word::Builtin(w) => w.to_string(),
+            Word::Custom(opcode) => self
+                .instructions
+                .iter()
+                .find(|s| s.opcode == opcode)
+                .map_or_else(|| word.to_string(), |s| s.name.clone()),
+        }
+    }
+
+    /// Builtin commands with the custom instructions of the code
+    pub fn instruction_set(&self) -> Result<InstructionSet, String> {
+        let mut isa = InstructionSet::builtin();
+        for spec in self.instructions.iter() {
+            isa.register(spec.clone())?;
+        }
+        Ok(isa)
+    }
+
+    /// Source line the instruction was compiled from
+    pub fn source_line(&self, index: usize) -> Option<&SourceLine> {
+        self.lines.get(index).and_then(|l| l.as_ref())
+    }
+
+    /// Name of the label pointing to the instruction
+    pub fn label_name(&self, index: Label) -> Option<&str> {
+        self.labels
+            .iter()
+            .find(|(_, l)| **l == index)
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Name of the argument if it was written as a symbol that still has the same value
+    pub fn val_name(&self, index: usize, arg: usize, val: Val) -> Option<&str> {
+        self.find_ref(index, arg)
+            .filter(|name| self.consts.get(*name) == Some(&val))
+    }
+
+    /// Name of the memory address argument. Variables own their slots, so they are
+    /// recognized even in instructions where the address was written as a number.
+    pub fn mem_name(&self, index: usize, arg: usize, addr: Mem) -> Option<&str> {
+        if let Some(name) = self.find_ref(index, arg) {
+            if self.consts.get(name).is_some_and(|v| *v as Mem == addr)
+                || self.vars.get(name) == Some(&addr)
+            {
+                return Some(name);
+            }
+        }
+        self.vars
+            .iter()
+            .find(|(_, a)| **a == addr)
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Declarations of all symbols, variables are declared in the order of their addresses
+    /// so that the compiler allocates the same slots for them
+    pub fn declarations(&self) -> Vec<String> {
+        let mut vars: Vec<(&String, &Mem)> = self.vars.iter().collect();
+        vars.sort_by_key(|(_, addr)| **addr);
+        self.consts
+            .iter()
+            .map(|(name, val)| format!("#const {name} {val}"))
+            .chain(vars.into_iter().map(|(name, _)| format!("#var {name}")))
+            .collect()
+    }
+
+    fn find_ref(&self, index: usize, arg: usize) -> Option<&str> {
+        self.refs
+            .iter()
+            .find(|r| r.index == index && r.arg == arg)
+            .map(|r| r.name.as_str())
+    }
+}
@@ -1,12 +1,13 @@
 use std::fmt::Display;
 
-use anyhow::{ensure, Result};
+use crate::diagnostic::{Diagnostic, ErrorCode, Span};
 
 #[derive(Clone)]
 pub struct Token {
     pub line_index: usize,
     pub word_index: usize,
     pub orign_string: String,
+    pub span: Span,
 }
 
 impl Display for Token {
@@ -22,6 +23,8 @@ impl Display for Token {
 pub struct TokenStream {
     toks: Vec<Token>,
     toks_index: usize,
+    // if set, tokens can be taken only from this line
+    line_limit: Option<usize>,
 }
 
 impl TokenStream {
@@ -29,39 +32,84 @@ impl TokenStream {
         Self {
             toks,
             toks_index: 0,
+            line_limit: None,
         }
     }
 
-    pub fn next(&mut self) -> Result<(Token, usize)> {
-        ensure!(
-            self.toks.len() > self.toks_index,
-            "Unexpected end of tokens stream"
-        );
-        self.toks_index = self.toks_index + 1;
-        Ok((self.toks[self.toks_index - 1].clone(), self.toks_index - 1))
+    pub fn next(&mut self) -> Result<(Token, usize), Diagnostic> {
+        let res = self.peek()?;
+        self.toks_index += 1;
+        Ok(res)
     }
 
-    pub fn peek(&mut self) -> Result<(Token, usize)> {
-        ensure!(
-            self.toks.len() > self.toks_index,
-            "Unexpected end of tokens stream"
-        );
-        Ok((self.toks[self.toks_index].clone(), self.toks_index))
+    pub fn peek(&self) -> Result<(Token, usize), Diagnostic> {
+        let tok = self.toks.get(self.toks_index);
+        match (tok, self.line_limit) {
+            (Some(tok), Some(line)) if tok.line_index != line => Err(self.unexpected_end()),
+            (Some(tok), _) => Ok((tok.clone(), self.toks_index)),
+            (None, _) => Err(self.unexpected_end()),
+        }
+    }
+
+    pub fn get(&self, i: usize) -> Result<(Token, usize), Diagnostic> {
+        self.toks.get(i).map(|t| (t.clone(), i)).ok_or_else(|| {
+            Diagnostic::new(
+                ErrorCode::Internal,
+                format!("Token {} out of range 0..{}", i, self.toks.len()),
+                None,
+            )
+        })
+    }
+
+    /// Restricts the stream to the tokens of the given line
+    pub fn limit_line(&mut self, line: Option<usize>) {
+        self.line_limit = line;
     }
 
-    pub fn get(&mut self, i: usize) -> Result<(Token, usize)> {
-        ensure!(
-            self.toks.len() > i,
-            "{} out of range 0..{}",
-            i,
-            self.toks.len()
-        );
-        Ok((self.toks[i].clone(), i))
+    /// Skips the rest of the given line
+    pub fn skip_line(&mut self, line: usize) {
+        while self
+            .toks
+            .get(self.toks_index)
+            .is_some_and(|t| t.line_index == line)
+        {
+            self.toks_index += 1;
+        }
+    }
+
+    /// Tokens from the current position to the end of its line
+    pub fn rest_of_line_mut(&mut self) -> impl Iterator<Item = &mut Token> {
+        let line = self.toks.get(self.toks_index).map(|t| t.line_index);
+        let start = self.toks_index.min(self.toks.len());
+        self.toks[start..]
+            .iter_mut()
+            .take_while(move |t| Some(t.line_index) == line)
+    }
+
+    fn unexpected_end(&self) -> Diagnostic {
+        // point right after the last consumed token
+        let span = self
+            .toks_index
+            .checked_sub(1)
+            .and_then(|i| self.toks.get(i))
+            .map(|t| Span {
+                start: t.span.end,
+                end: t.span.end,
+                line: t.span.line,
+                column: t.span.column + t.orign_string.chars().count(),
+                ..t.span
+            });
+        let message = if self.line_limit.is_some() {
+            "Unexpected end of line"
+        } else {
+            "Unexpected end of tokens stream"
+        };
+        Diagnostic::new(ErrorCode::UnexpectedEnd, message, span)
     }
 }
 
 pub trait FromTokenStream {
-    fn from_toks(toks: &mut TokenStream) -> anyhow::Result<Self>
+    fn from_toks(toks: &mut TokenStream) -> Result<Self, Diagnostic>
     where
         Self: Sized;
 }
@@ -1,5 +1,14 @@
-use botc::{code::{Command, Dir, Reg, RwReg}, compiler};
-
+use botc::{
+    analysis::{self, Issue, Limits},
+    code::{ArgKind, Command, CommandArg, CommandWord, Dir, Reg, RwReg, Word, ISA_VERSION},
+    code_packer::{self, Encoding},
+    compiler,
+    diagnostic::ErrorCode,
+    disassembler,
+    isa::{InstructionSet, InstructionSpec, CUSTOM_OPCODES_START},
+    symbols::SymbolMap,
+};
+use proptest::prelude::*;
 
 #[test]
 fn compile_test() {
@@ -33,7 +42,7 @@ fn compile_test() {
         Command::Ld(RwReg::Ax, Reg::En),
         Command::Ldv(RwReg::Cx, 321),
         Command::Ldr(3, Reg::Ax),
-        Command::Ldm(RwReg::Bx, 4)
+        Command::Ldm(RwReg::Bx, 4),
     ];
     let text_code: &str = r#"
         start:
@@ -72,3 +81,769 @@ fn compile_test() {
     let comtiled = compiler::compile(text_code.into()).unwrap();
     assert_eq!(comtiled, expect_code);
 }
+
+#[test]
+fn diagnostics_test() {
+    let text_code: &str = "start:\n  jmp nowhere\nfoo\n#mem_size 2\nldr [5] Ax\nstart:\n";
+    let diagnostics = compiler::compile(text_code.into()).unwrap_err().list;
+    let found: Vec<_> = diagnostics
+        .iter()
+        .map(|d| (d.code, d.span.map(|s| (s.line, s.column))))
+        .collect();
+    assert_eq!(
+        found,
+        vec![
+            (ErrorCode::LabelNotFound, Some((2, 7))),
+            (ErrorCode::UnknownCommand, Some((3, 1))),
+            (ErrorCode::MemOutOfRange, Some((5, 5))),
+            (ErrorCode::LabelRedefined, Some((6, 1))),
+        ]
+    );
+    let span = diagnostics[0].span.unwrap();
+    assert_eq!(&text_code[span.start..span.end], "nowhere");
+
+    // the code is not padded to a huge length
+    let diagnostics = compiler::compile("#len 99999999999\neatsun\n".into())
+        .unwrap_err()
+        .list;
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].code, ErrorCode::InvalidArgument);
+    assert_eq!(
+        diagnostics[0].span.map(|s| (s.line, s.column)),
+        Some((1, 6))
+    );
+    let code = format!("#len {}\neatsun\n", compiler::MAX_GENOM_LEN);
+    assert_eq!(
+        compiler::compile(code).unwrap().len(),
+        compiler::MAX_GENOM_LEN
+    );
+}
+
+#[test]
+fn disassemble_test() {
+    let code = vec![
+        Command::Eatsun,
+        Command::Call(4),
+        Command::Jmp(0),
+        Command::Nop,
+        Command::Rot(Dir::Left),
+        Command::Ret,
+    ];
+    let listing = disassembler::disassemble(&code);
+    assert!(listing
+        .iter()
+        .any(|l| l.starts_with("func_4:") && l.ends_with("referenced from 1")));
+    assert!(listing
+        .iter()
+        .any(|l| l.contains("nop") && l.ends_with("0003 unreachable")));
+    assert_eq!(compiler::compile(listing.join("\n")).unwrap(), code);
+
+    // a label out of the genome is defined where it wraps, the listing still compiles
+    let code = vec![Command::Jmp(7), Command::Nop, Command::Call(8)];
+    let listing = disassembler::disassemble(&code);
+    assert!(listing
+        .iter()
+        .any(|l| l.starts_with("label_7:") && l.ends_with("invalid target, referenced from 0")));
+    assert_eq!(
+        compiler::compile(listing.join("\n")).unwrap(),
+        vec![Command::Jmp(1), Command::Nop, Command::Call(2)]
+    );
+}
+
+#[test]
+fn preprocessor_test() {
+    let text_code: &str = r#"
+        #define LIMIT 1500
+        #define SLOT 3
+        #macro eat_until N
+        again:
+            eatsun
+            cmpv en N
+            jle again
+        #endmacro
+        eat_until LIMIT
+        eat_until 10
+        ldr [SLOT] Ax
+    "#;
+    let expect_code: Vec<Command> = vec![
+        Command::Eatsun,
+        Command::Cmpv(Reg::En, 1500),
+        Command::Jle(0),
+        Command::Eatsun,
+        Command::Cmpv(Reg::En, 10),
+        Command::Jle(3),
+        Command::Ldr(3, Reg::Ax),
+    ];
+    assert_eq!(compiler::compile(text_code.into()).unwrap(), expect_code);
+
+    let text_code: &str = "#macro broken\n jmp nowhere\n#endmacro\nbroken\n";
+    let diagnostics = compiler::compile(text_code.into()).unwrap_err().list;
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].code, ErrorCode::LabelNotFound);
+    assert_eq!(diagnostics[0].span.unwrap().line, 2);
+    assert_eq!(diagnostics[0].notes[0].span.unwrap().line, 4);
+
+    // the included files are recognized by their canonical paths
+    let dir = std::env::temp_dir().join(format!("botc_include_{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("rv")).unwrap();
+    std::fs::write(dir.join("rv/eat.bot"), "eatsun\n").unwrap();
+    std::fs::write(
+        dir.join("rv/a.bot"),
+        "#include \"eat.bot\"\n#include \"../rv/a.bot\"\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("main.bot"),
+        "#include \"rv/eat.bot\"\n#include \"rv/eat.bot\"\n",
+    )
+    .unwrap();
+    assert_eq!(
+        compiler::compile_file(&dir.join("main.bot")).unwrap(),
+        vec![Command::Eatsun, Command::Eatsun]
+    );
+    let diagnostics = compiler::compile_file(&dir.join("rv/a.bot"))
+        .unwrap_err()
+        .list;
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].code, ErrorCode::IncludeFailed);
+    assert!(diagnostics[0].message.contains("includes itself"));
+
+    // a long chain of includes is not a recursion
+    for i in 0..40 {
+        std::fs::write(
+            dir.join(format!("chain{}.bot", i)),
+            format!("#include \"chain{}.bot\"\n", i + 1),
+        )
+        .unwrap();
+    }
+    let diagnostics = compiler::compile_file(&dir.join("chain0.bot"))
+        .unwrap_err()
+        .list;
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].code, ErrorCode::IncludeFailed);
+    assert!(diagnostics[0].message.contains("too deep"));
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // every macro doubles the code, the depth limit alone does not stop it
+    let mut text_code = String::from("#macro m0\n eatsun\n#endmacro\n");
+    for i in 1..30 {
+        text_code += &format!("#macro m{i}\n m{0}\n m{0}\n#endmacro\n", i - 1);
+    }
+    text_code += "m29\n";
+    let diagnostics = compiler::compile(text_code).unwrap_err().list;
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].code, ErrorCode::MacroExpansion);
+    assert!(diagnostics[0].message.contains("more than"));
+}
+
+#[test]
+fn symbols_test() {
+    let text_code: &str = r#"
+        #mem_size 2
+        #const ENERGY_THRESHOLD 500
+        #var counter
+        #var flag
+        start:
+            ldm Bx [counter]
+            cmpv En ENERGY_THRESHOLD
+            ldr [flag] Ax
+            cmpv En 500
+            jmp start
+    "#;
+    let expect_code: Vec<Command> = vec![
+        Command::Ldm(RwReg::Bx, 0),
+        Command::Cmpv(Reg::En, 500),
+        Command::Ldr(1, Reg::Ax),
+        Command::Cmpv(Reg::En, 500),
+        Command::Jmp(0),
+    ];
+    let (code, symbols) = compiler::compile_with_symbols(text_code.into()).unwrap();
+    assert_eq!(code, expect_code);
+    assert_eq!(symbols.consts["ENERGY_THRESHOLD"], 500);
+    assert_eq!(symbols.vars["counter"], 0);
+    assert_eq!(symbols.vars["flag"], 1);
+
+    // only the arguments written as symbols get their names back
+    let decompiled = compiler::decompile_with_symbols(code.clone(), &symbols);
+    assert!(decompiled.contains(&"ldm bx [counter]".to_string()));
+    assert!(decompiled.contains(&"cmpv en ENERGY_THRESHOLD".to_string()));
+    assert!(decompiled.contains(&"cmpv en 500".to_string()));
+    let (recompiled, resymbols) = compiler::compile_with_symbols(decompiled.join("\n")).unwrap();
+    assert_eq!(recompiled, code);
+    assert_eq!(resymbols.consts, symbols.consts);
+    assert_eq!(resymbols.vars, symbols.vars);
+    assert_eq!(resymbols.refs, symbols.refs);
+    assert!(
+        !compiler::decompile_with_symbols(code, &SymbolMap::default())
+            .iter()
+            .any(|l| l.contains("counter"))
+    );
+
+    let text_code: &str = "#mem_size 1\n#var a\n#var b\nldr [0] ax\n#const a 1\n";
+    let codes: Vec<ErrorCode> = compiler::compile(text_code.into())
+        .unwrap_err()
+        .list
+        .into_iter()
+        .map(|d| d.code)
+        .collect();
+    assert_eq!(
+        codes,
+        vec![
+            ErrorCode::MemOutOfRange,
+            ErrorCode::MemConflict,
+            ErrorCode::SymbolRedefined
+        ]
+    );
+
+    // a symbol named as a label would replace the jump target
+    for text_code in [
+        "#const loop 3\nloop:\n eatsun\n jmp loop\n",
+        "loop:\n eatsun\n jmp loop\n#const loop 3\n",
+        "#mem_size 1\nloop:\n jmp loop\n#var LOOP\n",
+    ] {
+        let diagnostics = compiler::compile(text_code.into()).unwrap_err().list;
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, ErrorCode::SymbolRedefined);
+        assert!(diagnostics[0].notes[0].span.is_some());
+    }
+}
+
+#[test]
+fn symbol_map_test() {
+    let text_code: &str = "#len 4\nstart:\n    eatsun // eat\n    call sub\nsub: ret\n";
+    let (code, symbols) = compiler::compile_with_symbols(text_code.into()).unwrap();
+    assert_eq!(code.len(), 4);
+    assert_eq!(symbols.labels["start"], 0);
+    assert_eq!(symbols.labels["sub"], 2);
+    let line = symbols.source_line(0).unwrap();
+    assert_eq!((line.line, line.text.as_str()), (3, "eatsun"));
+    assert_eq!(symbols.source_line(2).unwrap().text, "sub: ret");
+    // padding has no source
+    assert!(symbols.source_line(3).is_none());
+
+    let symbols = SymbolMap::from_json(&symbols.to_json().unwrap()).unwrap();
+    assert_eq!(
+        compiler::decompile_with_symbols(code.clone(), &symbols),
+        vec!["start:", "eatsun", "call sub", "sub:", "ret", "nop"]
+    );
+    let listing = disassembler::disassemble_with_symbols(&code, &symbols);
+    assert!(listing
+        .iter()
+        .any(|l| l.starts_with("    call sub") && l.ends_with("input:4: call sub")));
+    assert_eq!(compiler::compile(listing.join("\n")).unwrap(), code);
+}
+
+#[test]
+fn code_packer_test() {
+    let text_code = "start:\nchk front\njmb skip\neatsun\nskip:\nmov front\njmp start\n";
+    let code = compiler::compile(text_code.into()).unwrap();
+    let b32 = code_packer::to_b32(&code).unwrap();
+    assert_eq!(code_packer::from_b32(&b32).unwrap(), code);
+
+    // headerless codes of the version 0 are still read
+    let legacy = "GWELCCIAAAEMGFDRORZPD72LVXMAMQSD2OPOMFQM6ZAGP2Z3CY";
+    assert_ne!(legacy, b32);
+    assert_eq!(code_packer::from_b32(legacy).unwrap(), code);
+
+    // labels out of the genome would come back wrapped, so they are not packed
+    assert!(code_packer::to_b32(&[Command::Jmp(7), Command::Nop]).is_err());
+
+    let pack = |bin: &[u8]| {
+        let mut compressor = flate2::Compress::new(flate2::Compression::best(), false);
+        let mut compressed = Vec::with_capacity(bin.len() + 64);
+        compressor
+            .compress_vec(bin, &mut compressed, flate2::FlushCompress::Finish)
+            .unwrap();
+        base32::encode(base32::Alphabet::Rfc4648 { padding: false }, &compressed)
+    };
+    assert!(code_packer::from_b32(&pack(b"TLG\x09")).is_err());
+    assert!(code_packer::from_b32(&pack(b"TLG")).is_err());
+
+    // labels are offsets, so a command inserted into the packed code after the
+    // backward jump and before the forward one keeps both jumps right
+    let mut bin = b"TLG\x01".to_vec();
+    let mut relative = vec![
+        Command::Eatsun,
+        Command::Jmp(1), // -1
+        Command::Jmb(4), // +2
+        Command::Nop,
+        Command::Mov(Dir::Front),
+    ];
+    relative.insert(2, Command::Absorb);
+    bincode::serialize_into(&mut bin, &relative).unwrap();
+    assert_eq!(
+        code_packer::from_b32(&pack(&bin)).unwrap(),
+        vec![
+            Command::Eatsun,
+            Command::Jmp(0),
+            Command::Absorb,
+            Command::Jmb(5),
+            Command::Nop,
+            Command::Mov(Dir::Front)
+        ]
+    );
+
+    // the current format keeps the header and checks the commands
+    let (code, symbols) =
+        compiler::compile_with_symbols("#mem_size 4\nstart: jmp start\n".into()).unwrap();
+    let (header, decoded) =
+        code_packer::decode(&code_packer::encode(&code, symbols.mem_size).unwrap()).unwrap();
+    assert_eq!(decoded, code);
+    assert_eq!(
+        header,
+        code_packer::Header {
+            format_version: code_packer::FORMAT_VERSION,
+            isa_version: ISA_VERSION,
+            len: 1,
+            mem_size: Some(4)
+        }
+    );
+    assert_eq!(code_packer::decode(legacy).unwrap().0.format_version, 0);
+
+    let header = |isa: u16, len: u32, payload: &[u8], crc: u32| {
+        let mut bin = b"TLG\x02".to_vec();
+        bin.extend_from_slice(&isa.to_le_bytes());
+        bin.extend_from_slice(&len.to_le_bytes());
+        bin.extend_from_slice(&u32::MAX.to_le_bytes());
+        bin.extend_from_slice(&crc.to_le_bytes());
+        bin.extend_from_slice(payload);
+        pack(&bin)
+    };
+    let payload = bincode::serialize(&vec![Command::Nop, Command::Eatsun]).unwrap();
+    let mut crc = flate2::Crc::new();
+    crc.update(&payload);
+    let crc = crc.sum();
+    assert_eq!(
+        code_packer::from_b32(&header(1, 2, &payload, crc)).unwrap(),
+        vec![Command::Nop, Command::Eatsun]
+    );
+    // newer instruction set, wrong length, damaged commands
+    assert!(code_packer::from_b32(&header(ISA_VERSION + 1, 2, &payload, crc)).is_err());
+    assert!(code_packer::from_b32(&header(1, 3, &payload, crc)).is_err());
+    assert!(code_packer::from_b32(&header(1, 2, &payload, crc ^ 1)).is_err());
+    assert!(code_packer::from_b32(&pack(b"TLG\x02\x01")).is_err());
+
+    // the format 2 stores the custom instruction after the builtin commands
+    let code = vec![
+        Command::Mate(Dir::Front, 0),
+        Command::Ext(300, vec![CommandArg::Val(-1)]),
+    ];
+    let payload = bincode::serialize(&code).unwrap();
+    let mut crc = flate2::Crc::new();
+    crc.update(&payload);
+    assert_eq!(
+        code_packer::from_b32(&header(3, 2, &payload, crc.sum())).unwrap(),
+        code
+    );
+    // the current one stores the opcodes
+    let b32 = code_packer::to_b32(&code).unwrap();
+    assert_eq!(code_packer::from_b32(&b32).unwrap(), code);
+}
+
+#[test]
+fn code_packer_limits_test() {
+    // a genome bigger than the old 1 MB buffer
+    let code: Vec<Command> = (0..100_000).map(|i| Command::Ldv(RwReg::Ax, i)).collect();
+    let b32 = code_packer::to_b32(&code).unwrap();
+    let err = code_packer::from_b32(&b32).unwrap_err();
+    assert!(err.to_string().contains("bigger than the limit"), "{err}");
+    let (header, decoded) = code_packer::decode_with_limit(&b32, 16 * 1024 * 1024).unwrap();
+    assert_eq!((header.len, &decoded), (code.len(), &code));
+    // `usize::MAX` is no limit at all
+    assert_eq!(
+        code_packer::decode_with_limit(&b32, usize::MAX).unwrap().1,
+        code
+    );
+    let unpacked = code_packer::unpack_with_limit(b32.as_bytes(), usize::MAX).unwrap();
+    assert_eq!(unpacked.commands, code);
+
+    // the limit counts the unpacked bytes, not the packed ones
+    let small = code_packer::to_b32(&vec![Command::Eatsun; 4]).unwrap();
+    assert!(code_packer::decode_with_limit(&small, 8).is_err());
+    assert!(code_packer::decode_with_limit(&small, 1024).is_ok());
+
+    // a small string that unpacks into a lot of zeros
+    let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::best());
+    std::io::Write::write_all(&mut encoder, &vec![0; 8 * 1024 * 1024]).unwrap();
+    let bomb = base32::encode(
+        base32::Alphabet::Rfc4648 { padding: false },
+        &encoder.finish().unwrap(),
+    );
+    assert!(bomb.len() < 20_000);
+    assert!(code_packer::from_b32(&bomb).is_err());
+
+    // malformed inputs
+    for input in [
+        "",
+        "not base32!",
+        "AAAAAAAA",
+        "7777777777777777",
+        &b32[..b32.len() / 2],
+        &small[..small.len() - 4],
+    ] {
+        assert!(code_packer::from_b32(input).is_err(), "{input:.20}");
+    }
+}
+
+#[test]
+fn encodings_test() {
+    let (code, symbols) = compiler::compile_with_symbols(
+        "#mem_size 4\nstart:\nchk front\njmb start\neatsun\nldm ax [3]\n".into(),
+    )
+    .unwrap();
+    let mut sizes = Vec::new();
+    for encoding in Encoding::ALL {
+        assert_eq!(encoding.name().parse::<Encoding>(), Ok(encoding));
+        let packed = code_packer::pack(&code, symbols.mem_size, encoding).unwrap();
+        let unpacked = code_packer::unpack(&packed).unwrap();
+        assert_eq!(
+            (unpacked.encoding, &unpacked.commands),
+            (encoding, &code),
+            "{encoding}"
+        );
+        assert_eq!(unpacked.header.mem_size, Some(4));
+        sizes.push((encoding, packed.len()));
+    }
+    assert!("base64".parse::<Encoding>().is_err());
+    // base64url is the shortest text form
+    let size = |e| sizes.iter().find(|(s, _)| *s == e).unwrap().1;
+    assert!(size(Encoding::Base64Url) < size(Encoding::Base32));
+    assert!(size(Encoding::Base32) < size(Encoding::Hex));
+
+    // spaces and line ends around the code are ignored
+    let b32 = code_packer::to_b32(&code).unwrap();
+    assert_eq!(
+        code_packer::unpack(format!("  {b32}\n").as_bytes())
+            .unwrap()
+            .commands,
+        code
+    );
+    // a broken code is reported as such, not as a bad text
+    let err = code_packer::unpack(&b32.as_bytes()[..b32.len() - 2]).unwrap_err();
+    assert!(format!("{err:#}").contains("base32"), "{err:#}");
+    // so is a botlang typo that looks like base64url
+    let err = format!("{:#}", code_packer::unpack(b"eatsunn").unwrap_err());
+    assert!(
+        err.contains("Failed to decode as text") && err.contains("eatsunn"),
+        "{err}"
+    );
+    assert!(code_packer::unpack(b"").is_err());
+    assert!(code_packer::unpack(&[0xff, 0x00, 0x13]).is_err());
+}
+
+#[test]
+fn analysis_test() {
+    let code = compiler::compile(
+        "start:\ncall sub\neatsun\njmp start\nsub:\nldr [500] ax\nret\ndead:\nnop\nspin:\nadd ax bx\njmp spin\n".into(),
+    )
+    .unwrap();
+    let limits = Limits {
+        ram_size: Some(100),
+        stack_size: Some(8),
+    };
+    assert_eq!(
+        analysis::analyze(&code, &limits),
+        vec![
+            Issue::MemOutOfRange {
+                index: 3,
+                addr: 500
+            },
+            Issue::Unreachable { start: 5, end: 7 }
+        ]
+    );
+    // the address is not checked without the RAM size
+    assert_eq!(
+        analysis::analyze(&code, &Limits::default()),
+        vec![Issue::Unreachable { start: 5, end: 7 }]
+    );
+
+    // the loop is entered from a branch, the way to the action stays but is reported
+    let code = compiler::compile(
+        "start:\nchk front\njmb spin\neatsun\njmp start\nspin:\nadd ax bx\njmp spin\n".into(),
+    )
+    .unwrap();
+    let issues = analysis::analyze(&code, &limits);
+    assert_eq!(issues, vec![Issue::IdleLoop { start: 4, end: 5 }]);
+    assert!(!issues[0].is_error());
+
+    // ret at the top level and a recursion deeper than the stack
+    let code = compiler::compile("ret\nrec:\neatsun\ncall rec\n".into()).unwrap();
+    assert_eq!(
+        analysis::analyze(&code, &limits),
+        vec![
+            Issue::RetWithoutCall { index: 0 },
+            Issue::StackOverflow { index: 2 }
+        ]
+    );
+    // every call returns to the next instruction
+    let code = compiler::compile(
+        "start:\ncall sub\nmov front\ncall sub\njmp start\nsub:\nrot left\nret\n".into(),
+    )
+    .unwrap();
+    assert!(analysis::analyze(&code, &limits).is_empty());
+
+    let issues = analysis::analyze(&[Command::Jmp(7)], &limits);
+    assert_eq!(
+        issues,
+        vec![
+            Issue::InvalidLabel { index: 0, label: 7 },
+            Issue::IdleLoop { start: 0, end: 0 }
+        ]
+    );
+    assert!(issues[0].is_error());
+    assert_eq!(analysis::analyze(&[], &limits), vec![Issue::Empty]);
+
+    // the flow of a huge genome is not followed, its labels are still checked
+    let mut code: Vec<Command> = (0..compiler::MAX_GENOM_LEN)
+        .map(|i| {
+            if i % 2 == 0 {
+                Command::Call(i + 1)
+            } else {
+                Command::Ret
+            }
+        })
+        .collect();
+    code[1] = Command::Jmp(compiler::MAX_GENOM_LEN);
+    let issues = analysis::analyze(&code, &Limits::default());
+    assert_eq!(
+        issues,
+        vec![
+            Issue::AnalysisIncomplete,
+            Issue::InvalidLabel {
+                index: 1,
+                label: compiler::MAX_GENOM_LEN
+            }
+        ]
+    );
+    assert!(!issues[0].is_error());
+}
+
+#[test]
+fn isa_test() {
+    // opcodes of the builtin commands never change, the packed genomes store them
+    let pinned = [
+        "nop", "mov", "rot", "jmp", "cmp", "jme", "jne", "jmg", "jml", "jle", "jge", "jmo", "jno",
+        "jmb", "jnb", "jmc", "jnc", "jmf", "jnf", "chk", "cmpv", "split", "fork", "bite", "eatsun",
+        "absorb", "call", "ret", "ld", "ldv", "ldr", "ldm", "neg", "add", "addv", "sub", "subv",
+        "mul", "mulv", "div", "divv", "mod", "modv", "pow", "powv", "mate",
+    ];
+    for (opcode, name) in pinned.iter().enumerate() {
+        let w: CommandWord = name.parse().unwrap();
+        assert_eq!(w.opcode(), opcode as u16, "{name}");
+        assert_eq!(CommandWord::from_opcode(opcode as u16), Some(w));
+    }
+    let mut opcodes: Vec<u16> = CommandWord::ALL.iter().map(|w| w.opcode()).collect();
+    opcodes.sort();
+    opcodes.dedup();
+    assert_eq!(opcodes.len(), CommandWord::ALL.len());
+    assert!(opcodes.iter().all(|o| *o < CUSTOM_OPCODES_START));
+    assert_eq!(
+        InstructionSet::builtin().find("LDV").unwrap().args,
+        vec![ArgKind::RwReg, ArgKind::Val]
+    );
+
+    let spec = r#"{"instructions": [
+        {"name": "Scan", "opcode": 256, "args": ["dir", "rwreg"], "action": true},
+        {"name": "swap", "opcode": 300, "args": ["rwreg", "rwreg"]}
+    ]}"#;
+    let isa = InstructionSet::from_json(spec).unwrap();
+    assert_eq!(isa.custom().count(), 2);
+    assert_eq!(
+        InstructionSet::from_json(&isa.to_json().unwrap()).unwrap(),
+        isa
+    );
+
+    let code = "start:\nscan front ax\nswap ax bx\njmp start\n";
+    assert!(compiler::compile(code.into()).is_err());
+    let (cmds, symbols) = compiler::compile_with_isa(code.into(), &isa).unwrap();
+    assert_eq!(
+        cmds,
+        vec![
+            Command::Ext(
+                256,
+                vec![CommandArg::Dir(Dir::Front), CommandArg::RwReg(RwReg::Ax)]
+            ),
+            Command::Ext(
+                300,
+                vec![CommandArg::RwReg(RwReg::Ax), CommandArg::RwReg(RwReg::Bx)]
+            ),
+            Command::Jmp(0),
+        ]
+    );
+    assert_eq!(cmds[1].word(), Word::Custom(300));
+    assert_eq!(symbols.instructions.len(), 2);
+    let err = compiler::compile_with_isa("scan front 5\n".into(), &isa).unwrap_err();
+    assert_eq!(err.list[0].code, ErrorCode::InvalidArgument);
+
+    // the symbol map names the custom instructions, without it they are shown by opcode
+    let text = compiler::decompile_with_symbols(cmds.clone(), &symbols).join("\n");
+    assert_eq!(compiler::compile_with_isa(text, &isa).unwrap().0, cmds);
+    assert_eq!(compiler::decompile(cmds.clone())[1], "op256 front ax");
+
+    // the binary encodings keep the opcodes, the text can not be compiled without the spec
+    for encoding in [
+        Encoding::Base32,
+        Encoding::Base64Url,
+        Encoding::Hex,
+        Encoding::Binary,
+    ] {
+        assert_eq!(
+            code_packer::unpack(&code_packer::pack(&cmds, None, encoding).unwrap())
+                .unwrap()
+                .commands,
+            cmds
+        );
+    }
+    assert!(code_packer::pack(&cmds, None, Encoding::Text).is_err());
+
+    // the analysis knows the actions and the arguments of the instructions
+    assert!(analysis::analyze_with_isa(&cmds, &Limits::default(), &isa).is_empty());
+    assert_eq!(
+        analysis::analyze(&cmds, &Limits::default()),
+        vec![
+            Issue::UnknownInstruction {
+                index: 0,
+                opcode: 256
+            },
+            Issue::IdleLoop { start: 0, end: 2 },
+            Issue::UnknownInstruction {
+                index: 1,
+                opcode: 300
+            },
+        ]
+    );
+    let bad = vec![Command::Ext(256, vec![]), Command::Nop];
+    assert_eq!(
+        analysis::analyze_with_isa(&bad, &Limits::default(), &isa),
+        vec![Issue::InvalidArguments { index: 0 }]
+    );
+    let bad = vec![Command::Ext(5, vec![]), Command::Nop];
+    assert_eq!(
+        analysis::analyze_with_isa(&bad, &Limits::default(), &isa),
+        vec![Issue::UnknownInstruction {
+            index: 0,
+            opcode: 5
+        }]
+    );
+
+    // opcodes and names can not be taken twice
+    let mut isa = isa;
+    assert!(isa
+        .register(InstructionSpec::new("foo", 1, vec![], false))
+        .is_err());
+    assert!(isa
+        .register(InstructionSpec::new("foo", 256, vec![], false))
+        .is_err());
+    assert!(isa
+        .register(InstructionSpec::new("SWAP", 301, vec![], false))
+        .is_err());
+    assert!(isa
+        .register(InstructionSpec::new("mov", 301, vec![], false))
+        .is_err());
+    assert!(isa
+        .register(InstructionSpec::new("f00", 301, vec![], false))
+        .is_err());
+    assert!(isa
+        .register(InstructionSpec::new(
+            "foo",
+            CUSTOM_OPCODES_START + 45,
+            vec![],
+            false
+        ))
+        .is_ok());
+}
+
+// random genome of valid commands, the seed keeps proptest cases reproducible
+fn rand_genom(len: usize, seed: u64) -> Vec<Command> {
+    let mut rng = <rand::rngs::StdRng as rand::SeedableRng>::seed_from_u64(seed);
+    (0..len)
+        .map(|_| Command::rand(&mut rng, len, 1_000_000, 1024))
+        .collect()
+}
+
+fn genom_strategy() -> impl Strategy<Value = Vec<Command>> {
+    (1usize..64, any::<u64>()).prop_map(|(len, seed)| rand_genom(len, seed))
+}
+
+// botlang-like text: valid lines, directives and broken tokens mixed together
+fn text_strategy() -> impl Strategy<Value = String> {
+    let token = prop_oneof![
+        any::<u64>().prop_map(|seed| compiler::decompile(rand_genom(1, seed)).join(" ")),
+        prop::sample::select(vec![
+            "#define",
+            "#macro",
+            "#endmacro",
+            "#const",
+            "#var",
+            "#len",
+            "#mem_size",
+            "[",
+            "]",
+            "[]",
+            "[-1]",
+            "[18446744073709551616]",
+            "[1",
+            "1]",
+            ":",
+            "start:",
+            "start",
+            "-",
+            "--1",
+            "99999999999999999999",
+            "ax",
+            "front",
+            "//",
+            "\"",
+            "#",
+            "%",
+            "0x10",
+        ])
+        .prop_map(String::from),
+        "\\PC{0,8}",
+    ];
+    let line = prop::collection::vec(token, 0..6).prop_map(|t| t.join(" "));
+    prop::collection::vec(line, 0..20).prop_map(|l| l.join("\n"))
+}
+
+proptest! {
+    #[test]
+    fn decompile_roundtrip_test(code in genom_strategy()) {
+        prop_assert_eq!(compiler::compile(compiler::decompile(code.clone()).join("\n")).unwrap(), code.clone());
+        let listing = disassembler::disassemble_with_symbols(&code, &SymbolMap::default());
+        prop_assert_eq!(compiler::compile(listing.iter().map(|l| l.to_string()).collect::<Vec<_>>().join("\n")).unwrap(), code);
+    }
+
+    #[test]
+    fn packer_roundtrip_test(code in genom_strategy(), mem_size in prop::option::of(1024usize..4096)) {
+        prop_assert_eq!(code_packer::from_b32(&code_packer::to_b32(&code).unwrap()).unwrap(), code.clone());
+        for encoding in Encoding::ALL {
+            let unpacked = code_packer::unpack(&code_packer::pack(&code, mem_size, encoding).unwrap()).unwrap();
+            prop_assert_eq!(&unpacked.commands, &code);
+            prop_assert_eq!(unpacked.header.mem_size, mem_size);
+        }
+    }
+
+    #[test]
+    fn packer_invalid_label_test(code in genom_strategy(), index in any::<prop::sample::Index>(), over in 0usize..64) {
+        let mut code = code;
+        let i = index.index(code.len());
+        code[i] = Command::Jmp(code.len() + over);
+        for encoding in Encoding::ALL {
+            prop_assert!(code_packer::pack(&code, None, encoding).is_err());
+        }
+    }
+
+    #[test]
+    fn compile_no_panic_test(text in text_strategy()) {
+        // any text compiles or gives diagnostics, the compiled code survives a round trip
+        if let Ok(code) = compiler::compile(text) {
+            prop_assert_eq!(compiler::compile(compiler::decompile(code.clone()).join("\n")).unwrap(), code);
+        }
+    }
+
+    #[test]
+    fn unpack_no_panic_test(data in prop::collection::vec(any::<u8>(), 0..256)) {
+        let _ = code_packer::unpack(&data);
+        let _ = code_packer::from_b32(&String::from_utf8_lossy(&data));
+    }
+}
@@ -0,0 +1,12 @@
+#![no_main]
+
+use botc::compiler;
+use libfuzzer_sys::fuzz_target;
+
+// any text compiles or gives diagnostics, the compiled code survives a round trip
+fuzz_target!(|text: &str| {
+    if let Ok(code) = compiler::compile(text.into()) {
+        let listing = compiler::decompile(code.clone()).join("\n");
+        assert_eq!(compiler::compile(listing).unwrap(), code);
+    }
+});
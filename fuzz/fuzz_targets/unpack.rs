@@ -0,0 +1,22 @@
+#![no_main]
+
+use botc::code_packer;
+use libfuzzer_sys::fuzz_target;
+
+// any bytes unpack or give an error, the unpacked genome survives a round trip
+fuzz_target!(|data: &[u8]| {
+    let Ok(unpacked) = code_packer::unpack(data) else {
+        return;
+    };
+    let len = unpacked.commands.len();
+    let valid = unpacked.commands.iter().all(|c| {
+        c.args()
+            .iter()
+            .all(|a| !matches!(a, botc::code::CommandArg::Label(l) if *l >= len))
+    });
+    // labels out of the genome are rejected by the packer
+    match code_packer::to_b32(&unpacked.commands) {
+        Ok(b32) => assert_eq!(code_packer::from_b32(&b32).unwrap(), unpacked.commands),
+        Err(_) => assert!(!valid),
+    }
+});
@@ -0,0 +1,57 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use std::sync::Arc;
+use torland::{
+    testing::MockWorld,
+    world::{
+        bot::{Bot, BotCfg, Fault},
+        Rules,
+    },
+};
+
+const RULES: &str = r#"
+{
+    "max_commands_per_cycle": 10,
+    "energy_for_split": 1000,
+    "energy_per_sun": 10,
+    "energy_per_mineral": 10,
+    "energy_per_step": 50,
+    "age_per_energy_penalty": 100,
+    "start_energy": 100000,
+    "on_bite_energy_delimiter": 10,
+    "max_energy": 100000,
+    "max_random_value": 10000,
+    "mutation_ver": 0.2,
+    "energy_per_sun_free_boost": 10,
+    "energy_per_sun_bro_boost": 5,
+    "energy_per_sun_oth_boost": -2,
+    "ram_size": 16,
+    "stack_size": 8
+}
+"#;
+
+// the genome is decoded from the input without the analysis of `World::spawn`,
+// so every fault reaches the VM
+fuzz_target!(|data: &[u8]| {
+    let Some((mode, code)) = data.split_first() else {
+        return;
+    };
+    let Ok(genom) = botc::code_packer::from_b32(&String::from_utf8_lossy(code)) else {
+        return;
+    };
+    let mut rules: Rules = serde_json::from_str(RULES).unwrap();
+    rules.on_fault = [Fault::Wrap, Fault::Flag, Fault::Kill][*mode as usize % 3];
+    let mut bot = Bot::new(BotCfg {
+        colony_id: 0,
+        genom_id: 0,
+        genom: Arc::new(genom),
+        ram_size: rules.ram_size,
+        stack_size: rules.stack_size,
+        energy: rules.start_energy,
+    });
+    let mut world = MockWorld::new(10, 10);
+    for _ in 0..100 {
+        bot.update(&mut world, &rules).unwrap();
+    }
+});
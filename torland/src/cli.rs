@@ -0,0 +1,225 @@
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use clap_derive::{Parser, ValueEnum};
+use serde::Serialize;
+use std::{
+    collections::HashSet,
+    fs::{read, read_to_string, write, File},
+    io::{stdout, BufWriter, Write},
+    path::Path,
+};
+use torland::{util, vec2::Vec2u, world::World};
+
+/// Headless world simulation
+#[derive(Parser)]
+struct Options {
+    /// World config file
+    #[arg(value_name = "config", required_unless_present = "load")]
+    config: Option<String>,
+
+    /// Load the world from a snapshot instead of creating it from the config
+    #[arg(short, long, value_name = "snapshot")]
+    load: Option<String>,
+
+    /// Save the world snapshot after the simulation
+    #[arg(short, long, value_name = "snapshot")]
+    save: Option<String>,
+
+    /// Genom to spawn: code in any encoding of botc or file with it, optionally prefixed
+    /// with position "x,y:"
+    #[arg(short, long, value_name = "genom")]
+    genom: Vec<String>,
+
+    /// Number of world updates
+    #[arg(short, long, value_name = "ticks", default_value_t = 1000)]
+    ticks: usize,
+
+    /// Statistics output period in ticks
+    #[arg(short, long, value_name = "period", default_value_t = 100)]
+    period: usize,
+
+    /// Statistics format
+    #[arg(short, long, value_enum, default_value_t = Format::Csv)]
+    format: Format,
+
+    /// Statistics output file
+    #[arg(short, long, value_name = "output")]
+    output: Option<String>,
+
+    /// Record births and deaths of the bots and write them to the file in the statistics format
+    #[arg(long, value_name = "lineage")]
+    lineage: Option<String>,
+
+    /// Record the genome tree and write it to the file, in JSON for ".json" files and
+    /// in Newick format otherwise
+    #[arg(long, value_name = "phylogeny")]
+    phylogeny: Option<String>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Csv,
+    Json,
+}
+
+#[derive(Serialize)]
+struct Statistics {
+    tick: usize,
+    bots: usize,
+    colonies: usize,
+    genoms: usize,
+    min_energy: usize,
+    max_energy: usize,
+    min_age: usize,
+    max_age: usize,
+}
+
+const CSV_HEADER: &str = "tick,bots,colonies,genoms,min_energy,max_energy,min_age,max_age";
+
+impl Statistics {
+    fn collect(tick: usize, world: &World) -> Self {
+        let mut bots = 0;
+        let mut colonies = HashSet::new();
+        let mut genoms = HashSet::new();
+        world.foreach_bot(|_, _, b| {
+            let i = b.get_info();
+            bots += 1;
+            colonies.insert(i.colony_id);
+            genoms.insert(i.genom_id);
+        });
+        let i = world.get_info();
+        // ranges are meaningless without bots
+        let range = |min: usize, max: usize| if bots > 0 { (min, max) } else { (0, 0) };
+        let (min_energy, max_energy) = range(i.min_energy, i.max_energy);
+        let (min_age, max_age) = range(i.min_age, i.max_age);
+        Self {
+            tick,
+            bots,
+            colonies: colonies.len(),
+            genoms: genoms.len(),
+            min_energy,
+            max_energy,
+            min_age,
+            max_age,
+        }
+    }
+
+    fn write<W: Write>(&self, out: &mut W, format: Format) -> Result<()> {
+        match format {
+            Format::Csv => writeln!(
+                out,
+                "{},{},{},{},{},{},{},{}",
+                self.tick,
+                self.bots,
+                self.colonies,
+                self.genoms,
+                self.min_energy,
+                self.max_energy,
+                self.min_age,
+                self.max_age
+            )?,
+            Format::Json => writeln!(out, "{}", serde_json::to_string(self)?)?,
+        }
+        Ok(())
+    }
+}
+
+fn parse_genom(genom: &str, index: usize, cnt: usize, world: &World) -> Result<(Vec2u, Vec<u8>)> {
+    let i = world.get_info();
+    // only a valid position is a prefix, the code itself may contain ':'
+    let prefix = genom
+        .split_once(':')
+        .and_then(|(pos, code)| Some((util::parse_pos(pos).ok()?, code)));
+    let (pos, code) = match prefix {
+        Some(prefix) => prefix,
+        // genoms without position are spread along the world diagonal
+        None => (
+            ((index + 1) * i.w / (cnt + 1), (index + 1) * i.h / (cnt + 1)).into(),
+            genom,
+        ),
+    };
+    let code = if Path::new(code).is_file() {
+        read(code).context(format!("Failed to read genom file \"{}\"", code))?
+    } else {
+        code.trim().as_bytes().to_vec()
+    };
+    Ok((pos, code))
+}
+
+fn main() -> Result<()> {
+    let opt = Options::parse();
+
+    let mut world = if let Some(snapshot) = &opt.load {
+        let file =
+            File::open(snapshot).context(format!("Failed to open snapshot \"{}\"", snapshot))?;
+        World::load(file).map_err(anyhow::Error::msg)?
+    } else {
+        let Some(config) = &opt.config else {
+            bail!("Config file is not specified");
+        };
+        let cfg =
+            read_to_string(config).context(format!("Failed to open config \"{}\"", config))?;
+        util::make_world(&cfg).map_err(anyhow::Error::msg)?
+    };
+
+    if opt.lineage.is_some() {
+        world.set_lineage_tracking(true);
+    }
+    if opt.phylogeny.is_some() {
+        world.set_genom_tracking(true);
+    }
+
+    for (i, genom) in opt.genom.iter().enumerate() {
+        let (pos, code) = parse_genom(genom, i, opt.genom.len(), &world)
+            .context(format!("Failed to parse genom \"{}\"", genom))?;
+        if world.spawn(pos, &code).is_err() {
+            bail!("Failed to spawn genom \"{}\" at {}", genom, pos);
+        }
+    }
+
+    let mut out: Box<dyn Write> = match &opt.output {
+        Some(file) => Box::new(BufWriter::new(
+            File::create(file).context(format!("Failed to create output file \"{}\"", file))?,
+        )),
+        None => Box::new(BufWriter::new(stdout())),
+    };
+    if let Format::Csv = opt.format {
+        writeln!(out, "{}", CSV_HEADER)?;
+    }
+
+    // ticks are counted by the world, so they go on after a loaded snapshot
+    let period = opt.period.max(1);
+    for i in 1..=opt.ticks {
+        if world.update().is_err() {
+            bail!("Failed to update world at tick {}", world.get_tick());
+        }
+        if i % period == 0 || i == opt.ticks {
+            Statistics::collect(world.get_tick(), &world).write(&mut out, opt.format)?;
+        }
+    }
+    out.flush()?;
+
+    if let Some(file) = &opt.lineage {
+        let lineage = match opt.format {
+            Format::Csv => world.lineage().to_csv(),
+            Format::Json => world.lineage().to_json().map_err(anyhow::Error::msg)?,
+        };
+        write(file, lineage).context(format!("Failed to write lineage file \"{}\"", file))?;
+    }
+
+    if let Some(file) = &opt.phylogeny {
+        let tree = if Path::new(file).extension().is_some_and(|e| e == "json") {
+            world.genoms().to_json().map_err(anyhow::Error::msg)?
+        } else {
+            world.genoms().to_newick()
+        };
+        write(file, tree).context(format!("Failed to write phylogeny file \"{}\"", file))?;
+    }
+
+    if let Some(snapshot) = &opt.save {
+        let file = File::create(snapshot)
+            .context(format!("Failed to create snapshot \"{}\"", snapshot))?;
+        world.save(file).map_err(anyhow::Error::msg)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,191 @@
+use anyhow::{bail, Context, Result};
+use botc::{
+    code_packer::{self, Encoding},
+    compiler,
+    symbols::SymbolMap,
+};
+use clap::Parser;
+use clap_derive::Parser;
+use std::{
+    fs::{read, read_to_string},
+    io::{stdin, stdout, BufRead, Write},
+    path::Path,
+};
+use torland::{
+    util,
+    world::debugger::{Debugger, Stop},
+};
+
+/// Step debugger of a single bot
+#[derive(Parser)]
+struct Options {
+    /// Genom file: botlang source or base32 code, the symbol map is loaded from the file next to it
+    #[arg(value_name = "genom")]
+    genom: String,
+
+    /// World config file, a small uniform world is used by default
+    #[arg(short, long, value_name = "config")]
+    config: Option<String>,
+
+    /// Bot position "x,y", the world center by default
+    #[arg(short, long, value_name = "pos")]
+    pos: Option<String>,
+}
+
+// small world, so that the neighbours of the bot are easy to set up
+const WORLD_SIZE: usize = 5;
+
+// protection against endless loops without breakpoints
+const CONTINUE_LIMIT: usize = 1_000_000;
+
+const HELP: &str = "\
+commands:
+  s, step [n]          execute n instructions (1 by default)
+  t, tick              execute instructions until the end of the cycle
+  c, continue          execute until a breakpoint or death
+  b, break <loc>       set a breakpoint on an instruction index or a label
+  d, delete <loc>      remove the breakpoint
+  w, watch <expr>      show the expression after every stop
+  u, unwatch <expr>    stop showing the expression
+  p, print [expr]      print the expression or the whole bot state
+  l, list              print the genome
+  h, help              print this help
+  q, quit              exit
+expressions: registers (ax, en, ...), flags (fz, ef, ...), pc, sp, dir, ram, stack,
+memory cells [N] and [var]
+an empty line repeats the previous command";
+
+fn load_genom(path: &str) -> Result<(Vec<botc::code::Command>, SymbolMap)> {
+    let code = read(path).context(format!("Failed to read genom file \"{}\"", path))?;
+    // the source is compiled from the file, so includes are found and symbols are kept
+    if let Some(genom) = code_packer::unpack(&code)
+        .ok()
+        .filter(|u| u.encoding != Encoding::Text)
+    {
+        let genom = genom.commands;
+        let symbols = match read_to_string(SymbolMap::path_for(Path::new(path))) {
+            Ok(json) => SymbolMap::from_json(&json).map_err(anyhow::Error::msg)?,
+            Err(_) => SymbolMap::default(),
+        };
+        return Ok((genom, symbols));
+    }
+    match compiler::compile_file_with_symbols(Path::new(path)) {
+        Ok(res) => Ok(res),
+        Err(diagnostics) => {
+            eprintln!("{}", diagnostics.render());
+            bail!("Failed to compile genom file \"{}\"", path);
+        }
+    }
+}
+
+fn print_stop(dbg: &Debugger, stop: Stop) {
+    match stop {
+        Stop::Step => {}
+        Stop::Breakpoint(i) => println!("breakpoint at {i}"),
+        Stop::Died => println!("bot died at tick {}", dbg.tick()),
+    }
+    println!("tick {}: {}", dbg.tick(), dbg.current().trim_start());
+    for (expr, val) in dbg.watches() {
+        println!("  {expr} = {val}");
+    }
+}
+
+/// Executes the command, returns false to exit
+fn execute(dbg: &mut Debugger, line: &str) -> bool {
+    let mut words = line.split_whitespace();
+    let Some(cmd) = words.next() else {
+        return true;
+    };
+    let arg = words.next();
+    let report = |res: Result<String, String>| match res {
+        Ok(msg) => println!("{msg}"),
+        Err(e) => println!("error: {e}"),
+    };
+    match (cmd, arg) {
+        ("s" | "step", n) => match n.map_or(Ok(1), |n| n.parse::<usize>()) {
+            Ok(n) => {
+                let stop = dbg.run(n);
+                print_stop(dbg, stop);
+            }
+            Err(e) => println!("error: failed to parse \"{}\": {e}", n.unwrap_or_default()),
+        },
+        ("t" | "tick", None) => {
+            let stop = dbg.finish_tick();
+            print_stop(dbg, stop);
+        }
+        ("c" | "continue", None) => {
+            let stop = dbg.run(CONTINUE_LIMIT);
+            if stop == Stop::Step {
+                println!("no breakpoint reached in {CONTINUE_LIMIT} instructions");
+            }
+            print_stop(dbg, stop);
+        }
+        ("b" | "break", Some(loc)) => report(
+            dbg.add_breakpoint(loc)
+                .map(|i| format!("breakpoint at {i}")),
+        ),
+        ("d" | "delete", Some(loc)) => report(
+            dbg.remove_breakpoint(loc)
+                .map(|i| format!("breakpoint at {i} removed")),
+        ),
+        ("w" | "watch", Some(expr)) => report(
+            dbg.add_watch(expr)
+                .and_then(|_| dbg.eval(expr))
+                .map(|v| format!("{expr} = {v}")),
+        ),
+        ("u" | "unwatch", Some(expr)) => {
+            report(dbg.remove_watch(expr).map(|_| format!("{expr} removed")))
+        }
+        ("p" | "print", Some(expr)) => report(dbg.eval(expr).map(|v| format!("{expr} = {v}"))),
+        ("p" | "print", None) => {
+            print!("{}", dbg.info());
+            println!("pos: {}, tick: {}", dbg.pos(), dbg.tick());
+        }
+        ("l" | "list", None) => dbg.listing().iter().for_each(|l| println!("{l}")),
+        ("h" | "help", None) => println!("{HELP}"),
+        ("q" | "quit", None) => return false,
+        _ => println!("error: unknown command \"{line}\", type \"help\" for the list of commands"),
+    }
+    true
+}
+
+fn main() -> Result<()> {
+    let opt = Options::parse();
+
+    let cfg = match &opt.config {
+        Some(config) => {
+            read_to_string(config).context(format!("Failed to open config \"{}\"", config))?
+        }
+        None => util::default_config(WORLD_SIZE, WORLD_SIZE, 0.0, Some(0)),
+    };
+    let world = util::make_world(&cfg).map_err(anyhow::Error::msg)?;
+    let i = world.get_info();
+    let pos = match &opt.pos {
+        Some(pos) => util::parse_pos(pos).map_err(anyhow::Error::msg)?,
+        None => (i.w / 2, i.h / 2).into(),
+    };
+
+    let (genom, symbols) = load_genom(&opt.genom)?;
+    let mut dbg = Debugger::new(world, pos, genom, symbols).map_err(anyhow::Error::msg)?;
+    println!("type \"help\" for the list of commands");
+    print_stop(&dbg, Stop::Step);
+
+    let mut last = String::new();
+    let mut lines = stdin().lock().lines();
+    loop {
+        print!("(dbg) ");
+        stdout().flush()?;
+        let Some(line) = lines.next() else {
+            break;
+        };
+        let mut line = line?.trim().to_string();
+        if line.is_empty() {
+            line = last.clone();
+        }
+        if !execute(&mut dbg, &line) {
+            break;
+        }
+        last = line;
+    }
+    Ok(())
+}
@@ -1,3 +1,6 @@
+#![allow(clippy::result_unit_err)]
+
+pub mod testing;
 pub mod util;
 pub mod vec2;
 pub mod voronoi;
@@ -5,7 +8,6 @@ pub mod world;
 
 #[cfg(target_arch = "wasm32")]
 use {
-    std::borrow::Borrow,
     std::usize,
     wasm_bindgen::{prelude::*, Clamped},
     web_sys::{CanvasRenderingContext2d, ImageData},
@@ -26,6 +28,22 @@ impl WorldWraper {
         })
     }
 
+    pub fn load(snapshot: &str) -> Result<WorldWraper, String> {
+        Ok(Self {
+            world: world::World::load(snapshot.as_bytes())?,
+        })
+    }
+
+    pub fn save(&self) -> Result<String, String> {
+        let mut snapshot = Vec::new();
+        self.world.save(&mut snapshot)?;
+        String::from_utf8(snapshot).map_err(|e| format!("Failed to encode snapshot: {e}"))
+    }
+
+    pub fn get_seed(&self) -> u64 {
+        self.world.get_seed()
+    }
+
     pub fn update(&mut self) {
         self.world.update().ok();
     }
@@ -69,8 +87,10 @@ impl WorldWraper {
     }
 
     pub fn get_bot(&mut self, x: usize, y: usize) -> String {
-        self.world.get_bot_info((x, y).into()).ok().map(|i| {
-            botc::code_packer::to_b32(i.genom.borrow()).unwrap_or("Invalid Code".into())
-        }).unwrap_or("No Bot".into())
+        self.world
+            .get_bot_info((x, y).into())
+            .ok()
+            .map(|i| botc::code_packer::to_b32(&i.genom).unwrap_or("Invalid Code".into()))
+            .unwrap_or("No Bot".into())
     }
 }
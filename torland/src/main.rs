@@ -1,22 +1,52 @@
+use botc::symbols::SymbolMap;
 use glutin_window::GlutinWindow as Window;
 use graphics::rectangle::Border;
 use graphics::{clear, Context, DrawState, Image, Rectangle};
-use opengl_graphics::{CreateTexture, Format, GlGraphics, OpenGL, TextureSettings};
+use opengl_graphics::{CreateTexture, Format, GlGraphics, OpenGL, Texture, TextureSettings};
 use piston::event_loop::{EventSettings, Events};
 use piston::input::RenderEvent;
 use piston::window::WindowSettings;
 use piston::{Button, Key, MouseButton, MouseCursorEvent, PressEvent, UpdateEvent};
+use std::fs::{read, read_to_string, File};
+use std::path::Path;
 use torland::util::{self, get_coler_by_id, get_coler_name_by_id, COLERS_CNT};
+use torland::world::World;
 
 const WINDOW_H: f64 = 400.0;
 const WINDOW_W: f64 = 400.0;
 const WORLD_H: usize = 200;
 const WORLD_W: usize = 200;
 
-const Y_STEP: f64 = WINDOW_H as f64 / WORLD_H as f64;
-const X_STEP: f64 = WINDOW_W as f64 / WORLD_W as f64;
+const SNAPSHOT_FILE: &str = "torland_snapshot.json";
+const DEFAULT_GENOM: &str = "5XB4CCIAAAEAEQDJRBUNYRRP6DQRA6TQBONLJXN7EADQ";
+
+const Y_STEP: f64 = WINDOW_H / WORLD_H as f64;
+const X_STEP: f64 = WINDOW_W / WORLD_W as f64;
 
 fn main() {
+    // the genome spawned by the left click can be passed as the first argument,
+    // its symbol map is loaded from the file next to it
+    let (genom_code, symbols) = match std::env::args().nth(1) {
+        Some(path) => {
+            let code = read(&path)
+                .map_err(|e| eprintln!("Failed to read genome file \"{path}\": {e}"))
+                .unwrap();
+            let symbols = read_to_string(SymbolMap::path_for(Path::new(&path)))
+                .ok()
+                .and_then(|json| {
+                    SymbolMap::from_json(&json)
+                        .map_err(|e| eprintln!("{e}"))
+                        .ok()
+                });
+            (code, symbols)
+        }
+        None => (DEFAULT_GENOM.as_bytes().to_vec(), None),
+    };
+    let genom = botc::code_packer::unpack(&genom_code)
+        .map_err(|e| eprintln!("Failed to decode genome: {e}"))
+        .unwrap()
+        .commands;
+
     let mut window: Window = WindowSettings::new("", [WINDOW_H, WINDOW_W])
         .graphics_api(OpenGL::V3_2)
         .exit_on_esc(true)
@@ -24,34 +54,7 @@ fn main() {
         .unwrap();
     let mut gl = GlGraphics::new(OpenGL::V3_2);
 
-    let config = r#"
-    {
-        "sun_max_lvl": 10,
-        "mineral_max_lvl": 10,
-        "height": 200,
-        "width": 200,
-        "word_type": "Uniform",
-        "cluster_cnt": 100,
-        "rules": {
-            "max_commands_per_cycle": 10,
-            "energy_for_split": 1000,
-            "energy_per_sun": 10,
-            "energy_per_mineral": 10,
-            "energy_per_step": 50,
-            "age_per_energy_penalty": 100,
-            "start_energy": 100,
-            "on_bite_energy_delimiter": 10,
-            "max_energy": 10000,
-            "max_random_value": 10000,
-            "mutation_ver": 0.01,
-            "energy_per_sun_free_boost": 10,
-            "energy_per_sun_bro_boost": 5,
-            "energy_per_sun_oth_boost": -2,
-            "ram_size": 100,
-            "stack_size": 100
-        }
-    }
-    "#;
+    let config = util::default_config(WORLD_H, WORLD_W, 0.01, None);
     let mut world = util::make_world(&config)
         .map_err(|e| eprintln!("Failed to create world: {e}"))
         .unwrap();
@@ -62,32 +65,12 @@ fn main() {
 
     let mut pause = true;
     let mut by_step = true;
-    let mut cursor_pos = [0.0 as f64; 2];
+    let mut cursor_pos = [0.0_f64; 2];
 
     let event_settings = EventSettings::new();
     let mut events = Events::new(event_settings);
 
-    let mut background_texture_bytes = [0u8; WORLD_H*WORLD_W*4/*rgba - 4 bytes*/];
-    let i = world.get_info();
-    world.foreach_cell(|x, y, cell| {
-        let color = [
-            (cell.sun * 255 / i.max_sun) as u8,
-            (cell.sun * 255 / i.max_sun) as u8,
-            (cell.mineral * 255 / i.max_mineral) as u8,
-            255u8,
-        ];
-        background_texture_bytes[(y * WORLD_W + x) * 4..(y * WORLD_W + x + 1) * 4]
-            .copy_from_slice(&color);
-    });
-    let backgrount_settings = TextureSettings::new().filter(opengl_graphics::Filter::Nearest);
-    let background_texture = CreateTexture::create(
-        &mut (),
-        Format::Rgba8,
-        &background_texture_bytes,
-        [WORLD_W as u32, WORLD_H as u32],
-        &backgrount_settings,
-    )
-    .unwrap();
+    let mut background_texture = make_background_texture(&world);
     let background_img = Image::new()
         .src_rect([0.0, 0.0, WORLD_W as f64, WORLD_H as f64])
         .rect([0.0, 0.0, WINDOW_W, WINDOW_H]);
@@ -108,12 +91,10 @@ fn main() {
             });
         }
 
-        if let Some(_) = e.update_args() {
-            if !pause {
-                world.update().ok();
-                if by_step {
-                    pause = true;
-                }
+        if e.update_args().is_some() && !pause {
+            world.update().ok();
+            if by_step {
+                pause = true;
             }
         }
 
@@ -133,6 +114,38 @@ fn main() {
             if let Button::Keyboard(Key::I) = args {
                 eprintln!("pause: {pause}");
                 eprintln!("by_step: {by_step}");
+                eprintln!("seed: {}", world.get_seed());
+            }
+
+            if let Button::Keyboard(Key::F5) = args {
+                match File::create(SNAPSHOT_FILE)
+                    .map_err(|e| format!("Failed to create snapshot file: {e}"))
+                    .and_then(|f| world.save(f))
+                {
+                    Ok(_) => eprintln!("World saved to {SNAPSHOT_FILE}"),
+                    Err(e) => eprintln!("{e}"),
+                }
+            }
+
+            if let Button::Keyboard(Key::F9) = args {
+                match File::open(SNAPSHOT_FILE)
+                    .map_err(|e| format!("Failed to open snapshot file: {e}"))
+                    .and_then(World::load)
+                {
+                    Ok(w) if w.get_info().w != WORLD_W || w.get_info().h != WORLD_H => eprintln!(
+                        "Snapshot world size {}x{} does not match window world size {}x{}",
+                        w.get_info().w,
+                        w.get_info().h,
+                        WORLD_W,
+                        WORLD_H
+                    ),
+                    Ok(w) => {
+                        world = w;
+                        background_texture = make_background_texture(&world);
+                        eprintln!("World loaded from {SNAPSHOT_FILE}");
+                    }
+                    Err(e) => eprintln!("{e}"),
+                }
             }
 
             if let Button::Keyboard(Key::Space) = args {
@@ -146,9 +159,7 @@ fn main() {
                     (cursor_pos[0] / X_STEP) as usize,
                     (cursor_pos[1] / Y_STEP) as usize,
                 );
-                world
-                    .spawn(pos.into(), "5XB4CCIAAAEAEQDJRBUNYRRP6DQRA6TQBONLJXN7EADQ")
-                    .ok();
+                world.spawn(pos.into(), &genom_code).ok();
             }
 
             if let Button::Mouse(MouseButton::Right) = args {
@@ -158,17 +169,31 @@ fn main() {
                 );
                 if let Ok(i) = world.get_bot_info(pos.into()) {
                     eprintln!("{}", i);
+                    // the symbol map describes only descendants of the spawned genome
+                    // whose instructions were not moved by mutations
+                    let symbols = symbols
+                        .as_ref()
+                        .filter(|_| i.genom.len() == genom.len())
+                        .cloned()
+                        .unwrap_or_default();
                     eprintln!(
                         "code: \n=========================\n{}=========================",
-                        botc::compiler::decompile(Vec::clone(&i.genom))
+                        botc::compiler::decompile_lines(Vec::clone(&i.genom), &symbols)
                             .iter()
-                            .enumerate()
                             .fold(String::new(), |mut acc, (ix, cmd)| {
-                                acc.push_str(
-                                    format!("{}{}", if ix == i.pc { " >" } else { "  " }, cmd)
-                                        .as_str(),
-                                );
-                                acc.push_str("\n");
+                                let marker = if *ix == Some(i.pc) { " >" } else { "  " };
+                                // source is shown for instructions that were not mutated
+                                let src = ix
+                                    .filter(|ix| i.genom.get(*ix) == genom.get(*ix))
+                                    .and_then(|ix| symbols.source_line(ix));
+                                match src {
+                                    Some(src) => acc.push_str(
+                                        format!("{marker}{cmd:<30} // {}: {}", src.line, src.text)
+                                            .as_str(),
+                                    ),
+                                    None => acc.push_str(format!("{marker}{cmd}").as_str()),
+                                }
+                                acc.push('\n');
                                 acc
                             })
                     );
@@ -180,6 +205,30 @@ fn main() {
     }
 }
 
+fn make_background_texture(world: &World) -> Texture {
+    let mut background_texture_bytes = [0u8; WORLD_H*WORLD_W*4/*rgba - 4 bytes*/];
+    let i = world.get_info();
+    world.foreach_cell(|x, y, cell| {
+        let color = [
+            (cell.sun * 255 / i.max_sun) as u8,
+            (cell.sun * 255 / i.max_sun) as u8,
+            (cell.mineral * 255 / i.max_mineral) as u8,
+            255u8,
+        ];
+        background_texture_bytes[(y * WORLD_W + x) * 4..(y * WORLD_W + x + 1) * 4]
+            .copy_from_slice(&color);
+    });
+    let backgrount_settings = TextureSettings::new().filter(opengl_graphics::Filter::Nearest);
+    CreateTexture::create(
+        &mut (),
+        Format::Rgba8,
+        &background_texture_bytes,
+        [WORLD_W as u32, WORLD_H as u32],
+        &backgrount_settings,
+    )
+    .unwrap()
+}
+
 fn draw_cursor(cursor_pos: &[f64; 2], c: Context, g: &mut GlGraphics) {
     let rect = [
         X_STEP * (cursor_pos[0] / X_STEP).floor(),
@@ -0,0 +1,225 @@
+use botc::code::{Command, Dir};
+use rand::SeedableRng;
+use std::sync::Arc;
+
+use crate::world::{
+    bot::{self, Bot, BotCfg},
+    mutation::Mutation,
+    Rules, WorldAccessor, WorldRng,
+};
+
+/// Bot standing in a neighbour cell of the mock world
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MockBot {
+    pub colony_id: usize,
+    pub energy: isize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MockCell {
+    pub sun: isize,
+    pub mineral: isize,
+    pub bot: Option<MockBot>,
+}
+
+/// Action of the bot on the world
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Move(Dir),
+    /// a bot of the colony was born in the direction
+    Spawn(Dir, usize),
+    Kill(Dir),
+}
+
+/// World of one cell and its eight neighbours, indexed by absolute direction.
+/// The map does not change when the bot moves, actions are recorded as events.
+pub struct MockWorld {
+    pub sun: isize,
+    pub mineral: isize,
+    pub neighbours: [MockCell; 8],
+    /// genome ids and genomes of the neighbour bots, a bot can mate only with one that has it
+    pub genoms: [Option<(usize, Arc<Vec<Command>>)>; 8],
+    pub events: Vec<Event>,
+    colony_cnt: usize,
+    genom_cnt: usize,
+    rng: WorldRng,
+}
+
+impl MockWorld {
+    pub fn new(sun: isize, mineral: isize) -> Self {
+        Self {
+            sun,
+            mineral,
+            neighbours: [MockCell::default(); 8],
+            genoms: Default::default(),
+            events: Vec::new(),
+            colony_cnt: 1,
+            genom_cnt: 1,
+            rng: WorldRng::seed_from_u64(0),
+        }
+    }
+
+    pub fn neighbour(&mut self, dir: Dir) -> &mut MockCell {
+        &mut self.neighbours[dir as usize]
+    }
+}
+
+impl WorldAccessor for MockWorld {
+    fn mov(&mut self, dir: Dir) -> Result<(), ()> {
+        if self.neighbours[dir as usize].bot.is_some() {
+            return Err(());
+        }
+        self.events.push(Event::Move(dir));
+        Ok(())
+    }
+
+    fn get_new_colony_id(&mut self) -> usize {
+        self.colony_cnt += 1;
+        self.colony_cnt - 1
+    }
+
+    fn get_new_genom_id(&mut self, _parent: usize, _mutations: Vec<Mutation>) -> usize {
+        self.genom_cnt += 1;
+        self.genom_cnt - 1
+    }
+
+    fn get_rng(&mut self) -> &mut WorldRng {
+        &mut self.rng
+    }
+
+    fn spawn(&mut self, dir: Dir, b: Bot) -> Result<(), ()> {
+        let cell = &mut self.neighbours[dir as usize];
+        if cell.bot.is_some() {
+            return Err(());
+        }
+        let i = b.get_info();
+        cell.bot = Some(MockBot {
+            colony_id: i.colony_id,
+            energy: i.reg_en,
+        });
+        self.events.push(Event::Spawn(dir, i.colony_id));
+        Ok(())
+    }
+
+    fn kill(&mut self, dir: Dir) -> Option<isize> {
+        let bot = self.neighbours[dir as usize].bot.take()?;
+        self.events.push(Event::Kill(dir));
+        Some(bot.energy)
+    }
+
+    fn get_sun(&self) -> isize {
+        self.sun
+    }
+
+    fn get_sun_diff(&self, dir: Dir) -> isize {
+        self.sun - self.neighbours[dir as usize].sun
+    }
+
+    fn get_mineral(&self) -> isize {
+        self.mineral
+    }
+
+    fn get_mineral_diff(&self, dir: Dir) -> isize {
+        self.mineral - self.neighbours[dir as usize].mineral
+    }
+
+    fn is_some_colony(&self, dir: Dir, colony: usize) -> Option<bool> {
+        self.neighbours[dir as usize]
+            .bot
+            .map(|b| b.colony_id == colony)
+    }
+
+    fn get_genom(&self, dir: Dir) -> Option<(usize, Arc<Vec<Command>>)> {
+        self.neighbours[dir as usize].bot?;
+        self.genoms[dir as usize].clone()
+    }
+
+    fn energy_diffusion(&mut self, _colony: usize, energy: isize) -> isize {
+        energy
+    }
+}
+
+/// Runs one bot in a scripted environment and records the commands it executes
+pub struct Harness<W: WorldAccessor> {
+    pub world: W,
+    pub rules: Rules,
+    bot: Bot,
+    executed: Vec<Command>,
+}
+
+impl<W: WorldAccessor> Harness<W> {
+    pub fn new(world: W, rules: Rules, genom: Vec<Command>) -> Self {
+        let bot = Bot::new(BotCfg {
+            colony_id: 0,
+            genom_id: 0,
+            genom: Arc::new(genom),
+            ram_size: rules.ram_size,
+            stack_size: rules.stack_size,
+            energy: rules.start_energy,
+        });
+        Self {
+            world,
+            rules,
+            bot,
+            executed: Vec::new(),
+        }
+    }
+
+    /// Executes one command, returns true if it ends the cycle
+    pub fn step(&mut self) -> bool {
+        let i = self.bot.get_info();
+        if let Some(cmd) = i.genom.get(i.pc) {
+            self.executed.push(cmd.clone());
+        }
+        self.bot.do_command(&mut self.world, &self.rules)
+    }
+
+    /// Executes one cycle of the bot, the same way the world does
+    pub fn tick(&mut self) {
+        if !self.bot.is_live() {
+            return;
+        }
+        let mut cycles = 0;
+        while cycles < self.rules.max_commands_per_cycle {
+            cycles += self.bot.next_cycles(&self.rules);
+            if self.step() {
+                break;
+            }
+        }
+        self.bot.finish_cycle(&mut self.world, &self.rules);
+    }
+
+    pub fn run(&mut self, ticks: usize) {
+        for _ in 0..ticks {
+            self.tick();
+        }
+    }
+
+    pub fn info(&self) -> bot::Info {
+        self.bot.get_info()
+    }
+
+    pub fn is_live(&self) -> bool {
+        self.bot.is_live()
+    }
+
+    /// Commands executed so far
+    pub fn executed(&self) -> &[Command] {
+        &self.executed
+    }
+
+    /// Takes the list of executed commands, so the next checks see only new ones
+    pub fn take_executed(&mut self) -> Vec<Command> {
+        std::mem::take(&mut self.executed)
+    }
+}
+
+/// Compiles the botlang code and puts it into the mock world with the given sun and
+/// mineral levels, panics on compilation errors. The code may use the custom
+/// instructions of the rules.
+pub fn harness(code: &str, rules: Rules, sun: isize, mineral: isize) -> Harness<MockWorld> {
+    let genom = botc::compiler::compile_with_isa(code.into(), rules.instructions.set())
+        .map(|(genom, _)| genom)
+        .unwrap_or_else(|d| panic!("Failed to compile genom:\n{}", d.render()));
+    Harness::new(MockWorld::new(sun, mineral), rules, genom)
+}
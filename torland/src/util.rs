@@ -1,9 +1,8 @@
 use crate::voronoi::Voronoi;
-use crate::world::{bot, Rules, World, WorldConfig};
+use crate::world::{bot, Rules, World, WorldConfig, WorldRng};
 use crate::{vec2, world};
-use rand::{thread_rng, Rng};
+use rand::{thread_rng, Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
-use std::usize;
 
 #[derive(Deserialize)]
 struct Config {
@@ -14,6 +13,7 @@ struct Config {
     word_type: WorldType,
     rules: Rules,
     cluster_cnt: Option<usize>,
+    seed: Option<u64>,
 }
 #[derive(Deserialize, Serialize)]
 enum WorldType {
@@ -25,12 +25,16 @@ enum WorldType {
 pub fn make_world(cfg: &str) -> Result<World, String> {
     let cfg: Config =
         serde_json::from_str(cfg).map_err(|e| format!("Failed to parse config: {e}"))?;
+    // without a seed the run is not reproducible, the generated seed can be taken from World
+    let seed = cfg.seed.unwrap_or_else(|| thread_rng().gen());
+    cfg.rules.check()?;
 
     match cfg.word_type {
         WorldType::Uniform => Ok(World::new(WorldConfig {
             h: cfg.height,
             w: cfg.width,
             rules: cfg.rules,
+            seed,
             sun: |_, _| cfg.sun_max_lvl,
             mineral: |_, _| cfg.mineral_max_lvl,
         })),
@@ -38,23 +42,27 @@ pub fn make_world(cfg: &str) -> Result<World, String> {
             h: cfg.height,
             w: cfg.width,
             rules: cfg.rules,
+            seed,
             sun: |_, y| (cfg.height - y) * cfg.sun_max_lvl / cfg.height,
             mineral: |_, y| y * cfg.sun_max_lvl / cfg.height,
         })),
         WorldType::Clustered => {
             let cluster_cnt = cfg.cluster_cnt.unwrap_or(1);
+            // separate generator, so the simulation random stream does not depend on the map type
+            let mut rng = WorldRng::seed_from_u64(seed);
             let mut cluster_info = Vec::<vec2::Vec2u>::new();
             for _ in 0..cluster_cnt {
-                let sun = thread_rng().gen_range(0..=cfg.sun_max_lvl);
-                let mineral = thread_rng().gen_range(0..=cfg.mineral_max_lvl);
+                let sun = rng.gen_range(0..=cfg.sun_max_lvl);
+                let mineral = rng.gen_range(0..=cfg.mineral_max_lvl);
                 cluster_info.push((sun, mineral).into());
             }
             cluster_info.push((0usize, 0).into());
-            let voron = Voronoi::new(&mut thread_rng(), cfg.height, cfg.width, cluster_cnt);
+            let voron = Voronoi::new(&mut rng, cfg.height, cfg.width, cluster_cnt);
             Ok(World::new(WorldConfig {
                 h: cfg.height,
                 w: cfg.width,
                 rules: cfg.rules,
+                seed,
                 sun: |x, y| cluster_info[voron.get(x, y)].x,
                 mineral: |x, y| cluster_info[voron.get(x, y)].y,
             }))
@@ -62,6 +70,54 @@ pub fn make_world(cfg: &str) -> Result<World, String> {
     }
 }
 
+/// Config of a uniform world with the default rules, used by the binaries when no
+/// config file is given. Without a seed the world gets a random one.
+pub fn default_config(h: usize, w: usize, mutation_ver: f64, seed: Option<u64>) -> String {
+    serde_json::json!({
+        "sun_max_lvl": 10,
+        "mineral_max_lvl": 10,
+        "height": h,
+        "width": w,
+        "word_type": "Uniform",
+        "seed": seed,
+        "rules": {
+            "max_commands_per_cycle": 10,
+            "energy_for_split": 1000,
+            "energy_per_sun": 10,
+            "energy_per_mineral": 10,
+            "energy_per_step": 50,
+            "age_per_energy_penalty": 100,
+            "start_energy": 100,
+            "on_bite_energy_delimiter": 10,
+            "max_energy": 10000,
+            "max_random_value": 10000,
+            "mutation_ver": mutation_ver,
+            "energy_per_sun_free_boost": 10,
+            "energy_per_sun_bro_boost": 5,
+            "energy_per_sun_oth_boost": -2,
+            "ram_size": 100,
+            "stack_size": 100
+        }
+    })
+    .to_string()
+}
+
+/// Parses the position written as "x,y"
+pub fn parse_pos(pos: &str) -> Result<vec2::Vec2u, String> {
+    let (x, y) = pos
+        .split_once(',')
+        .ok_or_else(|| format!("Failed to parse position \"{}\"", pos))?;
+    let x = x
+        .trim()
+        .parse::<usize>()
+        .map_err(|e| format!("Failed to parse x \"{}\": {}", x, e))?;
+    let y = y
+        .trim()
+        .parse::<usize>()
+        .map_err(|e| format!("Failed to parse y \"{}\": {}", y, e))?;
+    Ok((x, y).into())
+}
+
 fn get_color_by_id(seed: usize) -> (u8, u8, u8) {
     const M: usize = 1 << 31;
     const A: usize = 1103515245;
@@ -69,8 +125,8 @@ fn get_color_by_id(seed: usize) -> (u8, u8, u8) {
     let color = (A * seed + C) % M;
     (
         (color % 0xff) as u8,
-        (color >> 8 % 0xff) as u8,
-        (color >> 16 % 0xff) as u8,
+        (color >> 8) as u8,
+        (color >> 16) as u8,
     )
 }
 
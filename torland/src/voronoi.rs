@@ -1,5 +1,3 @@
-use std::usize;
-
 struct Cluster {
     x: usize,
     y: usize,
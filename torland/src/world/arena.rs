@@ -0,0 +1,215 @@
+use serde::{Deserialize, Serialize};
+
+/// Stable id of a bot. The slot of a dead bot is reused, the generation tells
+/// the new bot from the old one, so stale ids do not refer to the newcomer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct BotId {
+    index: usize,
+    generation: usize,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Slot<T> {
+    generation: usize,
+    value: Option<T>,
+}
+
+/// Slab of values addressed by generational ids. Iteration goes in the order of
+/// the slots, which is stable while no value is removed or inserted.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Arena<T> {
+    slots: Vec<Slot<T>>,
+    // free slots, the last one is reused first
+    free: Vec<usize>,
+    len: usize,
+}
+
+impl<T> Default for Arena<T> {
+    fn default() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+            len: 0,
+        }
+    }
+}
+
+impl<T> Arena<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn insert(&mut self, value: T) -> BotId {
+        self.len += 1;
+        match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index];
+                slot.value = Some(value);
+                BotId {
+                    index,
+                    generation: slot.generation,
+                }
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    value: Some(value),
+                });
+                BotId {
+                    index: self.slots.len() - 1,
+                    generation: 0,
+                }
+            }
+        }
+    }
+
+    /// Removes the value, the id and all its copies become stale
+    pub fn remove(&mut self, id: BotId) -> Option<T> {
+        let slot = self.slots.get_mut(id.index)?;
+        if slot.generation != id.generation {
+            return None;
+        }
+        let value = slot.value.take()?;
+        slot.generation += 1;
+        self.free.push(id.index);
+        self.len -= 1;
+        Some(value)
+    }
+
+    pub fn get(&self, id: BotId) -> Option<&T> {
+        self.slots
+            .get(id.index)
+            .filter(|s| s.generation == id.generation)
+            .and_then(|s| s.value.as_ref())
+    }
+
+    pub fn get_mut(&mut self, id: BotId) -> Option<&mut T> {
+        self.slots
+            .get_mut(id.index)
+            .filter(|s| s.generation == id.generation)
+            .and_then(|s| s.value.as_mut())
+    }
+
+    pub fn contains(&self, id: BotId) -> bool {
+        self.get(id).is_some()
+    }
+
+    /// Takes the value out without releasing the slot, so the rest of the arena can be
+    /// borrowed while the value is changed. The value must be returned with `restore`.
+    pub(super) fn take(&mut self, id: BotId) -> Option<T> {
+        self.slots
+            .get_mut(id.index)
+            .filter(|s| s.generation == id.generation)
+            .and_then(|s| s.value.take())
+    }
+
+    pub(super) fn restore(&mut self, id: BotId, value: T) {
+        let slot = &mut self.slots[id.index];
+        debug_assert!(slot.generation == id.generation && slot.value.is_none());
+        slot.value = Some(value);
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = BotId> + '_ {
+        self.iter().map(|(id, _)| id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (BotId, &T)> {
+        self.slots.iter().enumerate().filter_map(|(index, s)| {
+            s.value.as_ref().map(|v| {
+                let id = BotId {
+                    index,
+                    generation: s.generation,
+                };
+                (id, v)
+            })
+        })
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (BotId, &mut T)> {
+        self.slots.iter_mut().enumerate().filter_map(|(index, s)| {
+            let generation = s.generation;
+            s.value.as_mut().map(|v| (BotId { index, generation }, v))
+        })
+    }
+
+    /// Makes an arena of the same layout with the converted values
+    pub(super) fn map<U, F>(&self, mut func: F) -> Arena<U>
+    where
+        F: FnMut(BotId, &T) -> U,
+    {
+        let slots = self
+            .slots
+            .iter()
+            .enumerate()
+            .map(|(index, s)| Slot {
+                generation: s.generation,
+                value: s.value.as_ref().map(|v| {
+                    func(
+                        BotId {
+                            index,
+                            generation: s.generation,
+                        },
+                        v,
+                    )
+                }),
+            })
+            .collect();
+        Arena {
+            slots,
+            free: self.free.clone(),
+            len: self.len,
+        }
+    }
+
+    /// Converts the values keeping their ids, stops on the first error
+    pub(super) fn try_map<U, E, F>(self, mut func: F) -> Result<Arena<U>, E>
+    where
+        F: FnMut(BotId, T) -> Result<U, E>,
+    {
+        let slots = self
+            .slots
+            .into_iter()
+            .enumerate()
+            .map(|(index, s)| {
+                let generation = s.generation;
+                Ok(Slot {
+                    generation,
+                    value: s
+                        .value
+                        .map(|v| func(BotId { index, generation }, v))
+                        .transpose()?,
+                })
+            })
+            .collect::<Result<Vec<_>, E>>()?;
+        Ok(Arena {
+            slots,
+            free: self.free,
+            len: self.len,
+        })
+    }
+
+    /// Checks the free list and the length, used after deserialization
+    pub(super) fn check(&self) -> Result<(), String> {
+        let mut is_free = vec![false; self.slots.len()];
+        for &index in self.free.iter() {
+            match is_free.get_mut(index) {
+                Some(f) if !*f && self.slots[index].value.is_none() => *f = true,
+                _ => return Err(format!("Invalid free slot {index}")),
+            }
+        }
+        if is_free.iter().filter(|f| !**f).count() != self.len
+            || self.slots.iter().filter(|s| s.value.is_some()).count() != self.len
+        {
+            return Err(format!("Invalid arena length {}", self.len));
+        }
+        Ok(())
+    }
+}
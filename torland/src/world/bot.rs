@@ -1,13 +1,20 @@
-use super::{Rules, WorldAccessor};
-use botc::code::{Command, Dir, Label, Mem, Reg, Val};
-use rand::{thread_rng, Rng};
-use std::{borrow::Borrow, isize, rc::Rc};
+use super::{
+    lineage::DeathCause,
+    mutation::{self, Mutation},
+    Rules, WorldAccessor,
+};
+use botc::code::{Command, Dir, Label, Reg, RwReg, Val, Word};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 
 #[derive(Debug)]
 pub struct Info {
+    pub id: usize,
+    pub parent_id: Option<usize>,
+    pub birth_tick: usize,
     pub colony_id: usize,
     pub genom_id: usize,
-    pub genom: Rc<Vec<Command>>,
+    pub genom: Arc<Vec<Command>>,
     pub reg_ax: Val,
     pub reg_bx: Val,
     pub reg_cx: Val,
@@ -35,8 +42,13 @@ impl std::fmt::Display for Info {
         writeln!(
             f,
             "genom: {}",
-            botc::code_packer::to_b32(self.genom.borrow()).unwrap()
+            botc::code_packer::to_b32(&self.genom).unwrap_or_else(|e| format!("<unpackable: {e}>"))
         )?;
+        writeln!(f, "id: {}", self.id)?;
+        if let Some(parent_id) = self.parent_id {
+            writeln!(f, "parent_id: {}", parent_id)?;
+        }
+        writeln!(f, "birth_tick: {}", self.birth_tick)?;
         writeln!(f, "colony_id: {}", self.colony_id)?;
         writeln!(f, "genom_id: {}", self.genom_id)?;
         writeln!(f, "Regs:")?;
@@ -65,7 +77,35 @@ impl std::fmt::Display for Info {
 }
 
 const REG_CNT: usize = 8;
-#[derive(Debug, Clone)]
+
+// clockwise starting from the front
+const DIRS: [Dir; 8] = [
+    Dir::Front,
+    Dir::FrontRight,
+    Dir::Right,
+    Dir::BackRight,
+    Dir::Back,
+    Dir::BackLeft,
+    Dir::Left,
+    Dir::FrontLeft,
+];
+
+/// What the bot does on an address out of its RAM, a label out of its genome or
+/// an unknown custom instruction
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Fault {
+    /// the address or label is taken modulo the size of the RAM or genome, unknown
+    /// instructions are handled as with `Flag`
+    #[default]
+    Wrap,
+    /// the command is skipped and `fo` is set
+    Flag,
+    /// the bot dies
+    Kill,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct State {
     // regs
     regs: [Val; REG_CNT],
@@ -104,6 +144,33 @@ impl State {
         state
     }
 
+    /// Checks the state restored from a snapshot against the rules, so that the VM
+    /// never indexes out of the RAM or stack
+    pub(super) fn check(&self, rules: &Rules) -> Result<(), String> {
+        if self.ram.len() != rules.ram_size {
+            return Err(format!(
+                "Invalid RAM size: {}, expect: {}",
+                self.ram.len(),
+                rules.ram_size
+            ));
+        }
+        if self.stack.len() != rules.stack_size {
+            return Err(format!(
+                "Invalid stack size: {}, expect: {}",
+                self.stack.len(),
+                rules.stack_size
+            ));
+        }
+        if self.sp > self.stack.len() {
+            return Err(format!(
+                "Stack pointer {} is out of the stack of {}",
+                self.sp,
+                self.stack.len()
+            ));
+        }
+        Ok(())
+    }
+
     fn set_reg(&mut self, reg: Reg, val: Val) {
         self.regs[reg as usize] = val;
     }
@@ -112,14 +179,6 @@ impl State {
         self.regs[reg as usize]
     }
 
-    fn get_ram(&self, addr: Mem) -> Val {
-        self.ram[addr as usize]
-    }
-
-    fn set_ram(&mut self, addr: Mem, val: Val) {
-        self.ram[addr as usize] = val;
-    }
-
     fn push(&mut self, val: Label) -> Result<(), ()> {
         if self.sp >= self.stack.len() {
             return Err(());
@@ -130,7 +189,7 @@ impl State {
     }
 
     fn pop(&mut self) -> Result<Label, ()> {
-        if self.sp <= 0 {
+        if self.sp == 0 {
             return Err(());
         }
         self.sp -= 1;
@@ -140,44 +199,59 @@ impl State {
 
 #[derive(Debug)]
 pub struct Bot {
-    colony_id: usize,
-    genom_id: usize,
-    is_live: bool,
-    state: State,
-    genom: Rc<Vec<Command>>,
+    // id and birth tick are given by the world when the bot is placed on the map
+    pub(super) id: usize,
+    pub(super) parent_id: Option<usize>,
+    pub(super) birth_tick: usize,
+    pub(super) colony_id: usize,
+    pub(super) genom_id: usize,
+    pub(super) death: Option<DeathCause>,
+    pub(super) state: State,
+    pub(super) genom: Arc<Vec<Command>>,
 }
 
 pub struct BotCfg {
     pub colony_id: usize,
     pub genom_id: usize,
-    pub genom: Rc<Vec<Command>>,
+    pub genom: Arc<Vec<Command>>,
     pub ram_size: usize,
     pub stack_size: usize,
     pub energy: Val,
 }
 
 impl Bot {
-    pub(super) fn new(cfg: BotCfg) -> Self {
+    pub fn new(cfg: BotCfg) -> Self {
         Self {
+            id: 0,
+            parent_id: None,
+            birth_tick: 0,
             colony_id: cfg.colony_id,
             genom_id: cfg.genom_id,
             state: State::new(cfg.ram_size, cfg.stack_size, cfg.energy),
             genom: cfg.genom,
-            is_live: true,
+            death: None,
         }
     }
 
-    pub(super) fn update(&mut self, wa: &mut WorldAccessor, rules: &Rules) -> Result<(), ()> {
-        if !self.is_live {
+    /// Executes one cycle of the bot
+    pub fn update<W: WorldAccessor>(&mut self, wa: &mut W, rules: &Rules) -> Result<(), ()> {
+        if !self.is_live() {
             return Ok(());
         }
 
-        for _ in 0..rules.max_commands_per_cycle {
+        let mut cycles = 0;
+        while cycles < rules.max_commands_per_cycle {
+            cycles += self.next_cycles(rules);
             if self.do_command(wa, rules) {
                 break;
             }
         }
+        self.finish_cycle(wa, rules);
+        Ok(())
+    }
 
+    /// Ages the bot and takes the energy for the cycle, called after the last command of it
+    pub fn finish_cycle<W: WorldAccessor>(&mut self, wa: &mut W, rules: &Rules) {
         let age = self.state.get_reg(Reg::Ag) + 1;
         self.state.set_reg(Reg::Ag, age);
 
@@ -187,19 +261,79 @@ impl Bot {
             .min(rules.max_energy);
         self.state.set_reg(Reg::En, energy);
 
-        self.is_live = self.is_live && energy > 0;
+        if self.is_live() && energy <= 0 {
+            self.death = Some(DeathCause::Starvation);
+        }
 
-        if self.is_live {
+        if self.is_live() {
             self.set_energy(wa.energy_diffusion(self.colony_id, self.get_energy()));
         }
+    }
+
+    /// Copy of the bot starting at the label with the energy for the split
+    fn child(&self, label: Label, rules: &Rules) -> Bot {
+        let mut new = Bot {
+            id: 0,
+            parent_id: Some(self.id),
+            birth_tick: 0,
+            colony_id: self.colony_id,
+            genom_id: self.genom_id,
+            death: None,
+            state: self.state.clone(),
+            genom: self.genom.clone(),
+        };
+        new.state.pc = label;
+        new.state.set_reg(Reg::Ag, 0);
+        new.state.set_reg(Reg::En, rules.energy_for_split);
+        new
+    }
+
+    /// Checks the address or label against the size of the RAM or genome, returns
+    /// None if the command is skipped by `Rules::on_fault`
+    fn check(&mut self, val: u64, size: usize, rules: &Rules) -> Option<usize> {
+        if val < size as u64 {
+            return Some(val as usize);
+        }
+        match rules.on_fault {
+            Fault::Wrap if size > 0 => return Some((val % size as u64) as usize),
+            Fault::Wrap | Fault::Flag => self.state.fo = true,
+            Fault::Kill => {
+                self.kill(DeathCause::Fault);
+            }
+        }
+        None
+    }
 
-        Ok(())
+    fn jump(&mut self, label: Label, rules: &Rules) {
+        if let Some(label) = self.check(label as u64, self.genom.len(), rules) {
+            self.state.pc = label;
+        }
     }
 
-    fn do_command(&mut self, wa: &mut WorldAccessor, rules: &Rules) -> bool {
-        let cmd = &self.genom[self.state.pc];
-        self.state.pc = (self.state.pc + 1).rem_euclid(self.genom.len());
-        match cmd {
+    /// Cycles the command at pc takes, the command is executed if the budget of
+    /// the cycle is not spent yet
+    pub fn next_cycles(&self, rules: &Rules) -> usize {
+        match self.genom.get(self.state.pc % self.genom.len().max(1)) {
+            Some(cmd) => rules.costs.get(cmd).cycles.max(1),
+            None => 1,
+        }
+    }
+
+    /// Executes the command at pc, returns true if the command ends the cycle
+    /// or the bot dies
+    pub fn do_command<W: WorldAccessor>(&mut self, wa: &mut W, rules: &Rules) -> bool {
+        let len = self.genom.len();
+        if len == 0 {
+            self.kill(DeathCause::Fault);
+            return true;
+        }
+        // the mutated genome of a child may be shorter than the label it starts from
+        let cmd = self.genom[self.state.pc % len].clone();
+        self.state.pc = (self.state.pc % len + 1) % len;
+        let energy = self.state.get_reg(Reg::En);
+        self.state
+            .set_reg(Reg::En, energy.saturating_sub(rules.costs.get(&cmd).energy));
+        match &cmd {
             Command::Nop => return true,
             Command::Mov(dir) => {
                 wa.mov(dir + self.state.dir).ok();
@@ -211,32 +345,32 @@ impl Bot {
             }
             Command::Jmf(label) => {
                 if self.state.ef {
-                    self.state.pc = *label
+                    self.jump(*label, rules);
                 }
             }
             Command::Jnf(label) => {
                 if !self.state.ef {
-                    self.state.pc = *label
+                    self.jump(*label, rules);
                 }
             }
             Command::Jmb(label) => {
                 if self.state.eb {
-                    self.state.pc = *label
+                    self.jump(*label, rules);
                 }
             }
             Command::Jnb(label) => {
                 if !self.state.eb {
-                    self.state.pc = *label
+                    self.jump(*label, rules);
                 }
             }
             Command::Jmc(label) => {
                 if self.state.ec {
-                    self.state.pc = *label
+                    self.jump(*label, rules);
                 }
             }
             Command::Jnc(label) => {
                 if !self.state.ec {
-                    self.state.pc = *label
+                    self.jump(*label, rules);
                 }
             }
             Command::Chk(dir) => {
@@ -264,59 +398,85 @@ impl Bot {
                 self.state.fs = reg >= *val;
             }
             Command::Split(dir, label) => {
+                let Some(label) = self.check(*label as u64, len, rules) else {
+                    return true;
+                };
                 let energy = self.state.get_reg(Reg::En);
                 self.state.set_reg(Reg::En, energy - rules.energy_for_split);
                 if energy > rules.energy_for_split {
-                    let mut new = Bot {
-                        colony_id: self.colony_id,
-                        genom_id: self.genom_id,
-                        is_live: true,
-                        state: self.state.clone(),
-                        genom: self.genom.clone(),
-                    };
-                    new.state.pc = *label;
-                    new.state.set_reg(Reg::Ag, 0);
-                    new.state.set_reg(Reg::En, rules.energy_for_split);
-                    if let Err(_) = wa.spawn(dir + self.state.dir, new) {
-                        self.kill();
+                    let new = self.child(label, rules);
+                    if wa.spawn(dir + self.state.dir, new).is_err() {
+                        self.kill(DeathCause::NoRoom);
                     }
                 }
                 return true;
             }
             Command::Fork(dir, label) => {
+                let Some(label) = self.check(*label as u64, len, rules) else {
+                    return true;
+                };
                 let energy = self.state.get_reg(Reg::En);
                 self.state.set_reg(Reg::En, energy - rules.energy_for_split);
                 if energy > rules.energy_for_split {
-                    let mut new = Bot {
-                        colony_id: self.colony_id,
-                        genom_id: self.genom_id,
-                        is_live: true,
-                        state: self.state.clone(),
-                        genom: self.genom.clone(),
-                    };
-                    new.state.pc = *label;
-                    new.state.set_reg(Reg::Ag, 0);
-                    new.state.set_reg(Reg::En, rules.energy_for_split);
+                    let mut new = self.child(label, rules);
                     new.colony_id = wa.get_new_colony_id();
-                    if thread_rng().gen_bool(rules.mutation_ver) {
-                        let mut genom = Vec::clone(self.genom.borrow());
-                        let index = thread_rng().gen_range(0..new.genom.len());
-                        let command = Command::rand(
-                            &mut thread_rng(),
-                            new.genom.len(),
-                            rules.max_random_value,
-                            rules.ram_size as isize,
-                        );
-                        genom[index] = command;
-                        new.genom = Rc::new(genom);
-                        new.genom_id = wa.get_new_genom_id();
+                    let mutations = mutation::mutate(&mut new.genom, wa.get_rng(), rules);
+                    if !mutations.is_empty() {
+                        new.genom_id = wa.get_new_genom_id(self.genom_id, mutations);
                     }
-                    if let Err(_) = wa.spawn(dir + self.state.dir, new) {
-                        self.kill();
+                    if wa.spawn(dir + self.state.dir, new).is_err() {
+                        self.kill(DeathCause::NoRoom);
                     }
                 }
                 return true;
             }
+            Command::Mate(dir, label) => {
+                let Some(label) = self.check(*label as u64, len, rules) else {
+                    return true;
+                };
+                // the energy is taken only when the child is born
+                let energy = self.state.get_reg(Reg::En);
+                let cost = rules.energy_for_split + rules.mating.cost;
+                let dir = *dir + self.state.dir;
+                if energy <= cost {
+                    return true;
+                }
+                let Some((partner_id, partner)) = wa.get_genom(dir) else {
+                    return true;
+                };
+                // the free cell around the bot is searched clockwise, starting
+                // from the direction of the partner
+                let Some(free) = DIRS
+                    .iter()
+                    .map(|d| dir + *d)
+                    .find(|d| wa.is_some_colony(*d, self.colony_id).is_none())
+                else {
+                    return true;
+                };
+                let mut new = self.child(label, rules);
+                new.colony_id = wa.get_new_colony_id();
+                let mut mutations = Vec::new();
+                if partner_id != self.genom_id {
+                    new.genom = Arc::new(mutation::crossover(
+                        &self.genom,
+                        &partner,
+                        wa.get_rng(),
+                        rules,
+                    ));
+                    mutations.push(Mutation::Crossover {
+                        partner: partner_id,
+                    });
+                }
+                mutations.extend(mutation::mutate(&mut new.genom, wa.get_rng(), rules));
+                if !mutations.is_empty() {
+                    new.genom_id = wa.get_new_genom_id(self.genom_id, mutations);
+                }
+                self.state.set_reg(Reg::En, energy - cost);
+                if wa.spawn(free, new).is_err() {
+                    self.kill(DeathCause::NoRoom);
+                }
+                return true;
+            }
             Command::Bite(dir) => {
                 let energy = self.state.get_reg(Reg::En);
                 self.state.set_reg(
@@ -328,16 +488,6 @@ impl Bot {
                 return true;
             }
             Command::Eatsun => {
-                const DIRS: [Dir; 8] = [
-                    Dir::Front,
-                    Dir::FrontRight,
-                    Dir::Right,
-                    Dir::BackRight,
-                    Dir::Back,
-                    Dir::BackLeft,
-                    Dir::Left,
-                    Dir::FrontLeft,
-                ];
                 let mut free_cnt = 0;
                 let mut bro_cnt = 0;
 
@@ -377,58 +527,63 @@ impl Bot {
                 self.state.set_reg((*rw_reg).into(), *val);
             }
             Command::Jmp(label) => {
-                self.state.pc = *label;
+                self.jump(*label, rules);
             }
             Command::Jme(label) => {
                 if self.state.fz {
-                    self.state.pc = *label;
+                    self.jump(*label, rules);
                 }
             }
             Command::Jne(label) => {
                 if !self.state.fz {
-                    self.state.pc = *label;
+                    self.jump(*label, rules);
                 }
             }
             Command::Jmg(label) => {
                 if self.state.fs && !self.state.fz {
-                    self.state.pc = *label;
+                    self.jump(*label, rules);
                 }
             }
             Command::Jml(label) => {
                 if !self.state.fs && !self.state.fz {
-                    self.state.pc = *label;
+                    self.jump(*label, rules);
                 }
             }
             Command::Jle(label) => {
                 if !self.state.fs || self.state.fz {
-                    self.state.pc = *label;
+                    self.jump(*label, rules);
                 }
             }
             Command::Jge(label) => {
                 if self.state.fs || self.state.fz {
-                    self.state.pc = *label;
+                    self.jump(*label, rules);
                 }
             }
             Command::Jmo(label) => {
                 if self.state.fo {
-                    self.state.pc = *label;
+                    self.jump(*label, rules);
                 }
             }
             Command::Jno(label) => {
                 if !self.state.fo {
-                    self.state.pc = *label;
+                    self.jump(*label, rules);
                 }
             }
             Command::Ldr(mem, reg) => {
-                self.state.set_ram(*mem, self.state.get_reg(*reg));
+                if let Some(addr) = self.check(*mem, self.state.ram.len(), rules) {
+                    self.state.ram[addr] = self.state.get_reg(*reg);
+                }
             }
             Command::Ldm(rw_reg, mem) => {
-                self.state
-                    .set_reg((*rw_reg).into(), self.state.get_ram(*mem));
+                if let Some(addr) = self.check(*mem, self.state.ram.len(), rules) {
+                    self.state.set_reg((*rw_reg).into(), self.state.ram[addr]);
+                }
             }
             Command::Neg(rw_reg) => {
-                let reg = (*rw_reg).into();
-                self.state.set_reg(reg, -self.state.get_reg(reg));
+                let res = self.state.get_reg((*rw_reg).into()).checked_neg();
+                self.state.fo = res.is_none();
+                self.state
+                    .set_reg((*rw_reg).into(), res.unwrap_or(rules.max_random_value));
             }
             Command::Add(rw_reg, reg) => {
                 let res = self
@@ -524,29 +679,56 @@ impl Bot {
                     .set_reg((*rw_reg).into(), res.unwrap_or(rules.max_random_value));
             }
             Command::Call(label) => {
+                let Some(label) = self.check(*label as u64, len, rules) else {
+                    return !self.is_live();
+                };
                 if self.state.push(self.state.pc).is_ok() {
-                    self.state.pc = *label;
+                    self.state.pc = label;
                 } else {
                     self.state.fo = true;
                 }
             }
             Command::Ret => {
                 if let Ok(label) = self.state.pop() {
-                    self.state.pc = label;
+                    self.jump(label, rules);
                 } else {
                     self.state.fo = true;
                 }
             }
+            Command::Ext(opcode, args) => {
+                let spec = rules.instructions.set().spec(Word::Custom(*opcode));
+                let handler = rules.instructions.handler(*opcode);
+                match (spec, handler) {
+                    (Some(spec), Some(handler))
+                        if args.iter().map(|a| a.kind()).eq(spec.args.iter().copied()) =>
+                    {
+                        if handler(self, args, wa, rules) {
+                            return true;
+                        }
+                    }
+                    _ if rules.on_fault == Fault::Kill => {
+                        self.kill(DeathCause::Fault);
+                    }
+                    _ => self.state.fo = true,
+                }
+            }
         }
-        return false;
+        !self.is_live()
+    }
+
+    pub fn is_live(&self) -> bool {
+        self.death.is_none()
     }
 
-    pub(super) fn is_live(&self) -> bool {
-        self.is_live
+    /// Why the bot died, None if it is alive
+    pub fn death_cause(&self) -> Option<DeathCause> {
+        self.death
     }
 
-    pub(super) fn kill(&mut self) -> isize {
-        self.is_live = false;
+    pub(super) fn kill(&mut self, cause: DeathCause) -> isize {
+        if self.is_live() {
+            self.death = Some(cause);
+        }
         self.state.get_reg(Reg::En)
     }
 
@@ -554,16 +736,38 @@ impl Bot {
         self.colony_id
     }
 
-    pub(super) fn get_energy(&self) -> isize {
+    pub fn get_energy(&self) -> isize {
         self.state.get_reg(Reg::En)
     }
 
-    pub(super) fn set_energy(&mut self, en: isize) {
+    pub fn set_energy(&mut self, en: isize) {
         self.state.set_reg(Reg::En, en);
     }
 
+    /// Register value, for the handlers of custom instructions
+    pub fn get_reg(&self, reg: Reg) -> Val {
+        self.state.get_reg(reg)
+    }
+
+    pub fn set_reg(&mut self, reg: RwReg, val: Val) {
+        self.state.set_reg(reg.into(), val);
+    }
+
+    /// Direction the bot is looking in, relative directions are added to it
+    pub fn get_dir(&self) -> Dir {
+        self.state.dir
+    }
+
+    /// Sets `fo`, the handlers report the failures of custom instructions with it
+    pub fn set_overflow(&mut self, fo: bool) {
+        self.state.fo = fo;
+    }
+
     pub fn get_info(&self) -> Info {
         Info {
+            id: self.id,
+            parent_id: self.parent_id,
+            birth_tick: self.birth_tick,
             colony_id: self.colony_id,
             genom_id: self.genom_id,
             genom: self.genom.clone(),
@@ -0,0 +1,66 @@
+use std::collections::BTreeMap;
+
+use botc::code::{Command, Word};
+use serde::{Deserialize, Serialize};
+
+/// Price of one command
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct Cost {
+    /// energy taken from the bot when the command is executed, negative gives it
+    pub energy: isize,
+    /// part of `Rules::max_commands_per_cycle` the command takes, at least 1
+    pub cycles: usize,
+}
+
+impl Default for Cost {
+    fn default() -> Self {
+        Self {
+            energy: 0,
+            cycles: 1,
+        }
+    }
+}
+
+/// Costs of the commands keyed by their botlang names, custom instructions are
+/// keyed as `op<opcode>`. The missing ones cost `Cost::default()`
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(try_from = "BTreeMap<String, Cost>", into = "BTreeMap<String, Cost>")]
+pub struct CostTable {
+    costs: BTreeMap<Word, Cost>,
+}
+
+impl CostTable {
+    pub fn get(&self, cmd: &Command) -> Cost {
+        if self.costs.is_empty() {
+            return Cost::default();
+        }
+        self.costs.get(&cmd.word()).copied().unwrap_or_default()
+    }
+
+    pub fn set<T: Into<Word>>(&mut self, word: T, cost: Cost) {
+        self.costs.insert(word.into(), cost);
+    }
+}
+
+impl TryFrom<BTreeMap<String, Cost>> for CostTable {
+    type Error = String;
+
+    fn try_from(value: BTreeMap<String, Cost>) -> Result<Self, Self::Error> {
+        let costs = value
+            .into_iter()
+            .map(|(name, cost)| Ok((name.parse::<Word>()?, cost)))
+            .collect::<Result<_, String>>()?;
+        Ok(Self { costs })
+    }
+}
+
+impl From<CostTable> for BTreeMap<String, Cost> {
+    fn from(value: CostTable) -> Self {
+        value
+            .costs
+            .into_iter()
+            .map(|(word, cost)| (word.to_string(), cost))
+            .collect()
+    }
+}
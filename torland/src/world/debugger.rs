@@ -0,0 +1,306 @@
+use std::collections::BTreeSet;
+
+use botc::{code::Command, compiler, symbols::SymbolMap};
+
+use super::{arena::BotId, bot, Rules, World, WorldConfig};
+use crate::vec2::Vec2u;
+
+/// Size of the world created around the debugged bot by `Debugger::standalone`
+const STANDALONE_WORLD_SIZE: usize = 5;
+
+/// Why the execution stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// the requested number of instructions was executed
+    Step,
+    /// pc reached the breakpoint
+    Breakpoint(usize),
+    /// the bot died
+    Died,
+}
+
+/// Step debugger of one bot. The bot is placed into a world, only it is executed:
+/// other bots, including its children, stay frozen.
+pub struct Debugger {
+    world: World,
+    // the world is never updated, so the bot stays in it even after death
+    bot: BotId,
+    symbols: SymbolMap,
+    breakpoints: BTreeSet<usize>,
+    watches: Vec<String>,
+    // cycles taken by the commands executed in the current cycle
+    cycles: usize,
+    tick: usize,
+}
+
+impl Debugger {
+    /// Places the bot with the genome into the world at the position
+    pub fn new(
+        mut world: World,
+        pos: Vec2u,
+        genom: Vec<Command>,
+        symbols: SymbolMap,
+    ) -> Result<Self, String> {
+        if let Some(err) = world.analyze_genom(&genom).iter().find(|i| i.is_error()) {
+            return Err(format!("Invalid genom: {}", err));
+        }
+        let bot = world
+            .spawn_genom(pos, genom)
+            .map_err(|_| format!("Failed to spawn bot at {},{}", pos.x, pos.y))?;
+        Ok(Self {
+            world,
+            bot,
+            symbols,
+            breakpoints: BTreeSet::new(),
+            watches: Vec::new(),
+            cycles: 0,
+            tick: 0,
+        })
+    }
+
+    /// Places the bot into the center of a small empty world with the same sun and
+    /// mineral level everywhere
+    pub fn standalone(
+        rules: Rules,
+        sun: usize,
+        mineral: usize,
+        genom: Vec<Command>,
+        symbols: SymbolMap,
+    ) -> Result<Self, String> {
+        let world = World::new(WorldConfig {
+            h: STANDALONE_WORLD_SIZE,
+            w: STANDALONE_WORLD_SIZE,
+            rules,
+            seed: 0,
+            sun: |_, _| sun,
+            mineral: |_, _| mineral,
+        });
+        let center = STANDALONE_WORLD_SIZE / 2;
+        Self::new(world, (center, center).into(), genom, symbols)
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    fn bot(&self) -> &(Vec2u, bot::Bot) {
+        self.world
+            .bots
+            .get(self.bot)
+            .expect("debugged bot is removed")
+    }
+
+    pub fn info(&self) -> bot::Info {
+        self.bot().1.get_info()
+    }
+
+    pub fn pos(&self) -> Vec2u {
+        self.bot().0
+    }
+
+    /// Number of finished cycles
+    pub fn tick(&self) -> usize {
+        self.tick
+    }
+
+    pub fn is_live(&self) -> bool {
+        self.bot().1.is_live()
+    }
+
+    /// Executes one instruction, finishing the cycle if the instruction ends it
+    pub fn step(&mut self) -> Stop {
+        if !self.is_live() {
+            return Stop::Died;
+        }
+        let max_cycles = self.world.rules.max_commands_per_cycle;
+        let (cycles, end_of_cycle) = self
+            .world
+            .with_bot(self.bot, |b, wa, rules| {
+                (b.next_cycles(rules), b.do_command(wa, rules))
+            })
+            .unwrap_or((1, true));
+        self.cycles += cycles;
+        if end_of_cycle || self.cycles >= max_cycles {
+            self.world
+                .with_bot(self.bot, |b, wa, rules| b.finish_cycle(wa, rules));
+            self.cycles = 0;
+            self.tick += 1;
+        }
+        if !self.is_live() {
+            return Stop::Died;
+        }
+        match self.info().pc {
+            pc if self.breakpoints.contains(&pc) => Stop::Breakpoint(pc),
+            _ => Stop::Step,
+        }
+    }
+
+    /// Executes up to `limit` instructions, stops earlier on a breakpoint or death
+    pub fn run(&mut self, limit: usize) -> Stop {
+        for _ in 0..limit {
+            match self.step() {
+                Stop::Step => {}
+                stop => return stop,
+            }
+        }
+        Stop::Step
+    }
+
+    /// Executes instructions until the end of the current cycle
+    pub fn finish_tick(&mut self) -> Stop {
+        let tick = self.tick;
+        while self.tick == tick {
+            match self.step() {
+                Stop::Step => {}
+                stop => return stop,
+            }
+        }
+        Stop::Step
+    }
+
+    /// Resolves an instruction index or a label name
+    pub fn resolve(&self, location: &str) -> Result<usize, String> {
+        let len = self.info().genom.len();
+        let index = match location.parse::<usize>() {
+            Ok(index) => index,
+            Err(_) => *self
+                .symbols
+                .labels
+                .get(&location.to_lowercase())
+                .ok_or_else(|| format!("Label \"{location}\" not found"))?,
+        };
+        if index >= len {
+            return Err(format!("Instruction {index} not in 0..{len}"));
+        }
+        Ok(index)
+    }
+
+    /// Sets a breakpoint on an instruction index or a label, returns the index
+    pub fn add_breakpoint(&mut self, location: &str) -> Result<usize, String> {
+        let index = self.resolve(location)?;
+        self.breakpoints.insert(index);
+        Ok(index)
+    }
+
+    pub fn remove_breakpoint(&mut self, location: &str) -> Result<usize, String> {
+        let index = self.resolve(location)?;
+        if !self.breakpoints.remove(&index) {
+            return Err(format!("No breakpoint at {index}"));
+        }
+        Ok(index)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = &usize> {
+        self.breakpoints.iter()
+    }
+
+    /// Adds an expression whose value is shown after every stop
+    pub fn add_watch(&mut self, expr: &str) -> Result<(), String> {
+        self.eval(expr)?;
+        self.watches.push(expr.to_lowercase());
+        Ok(())
+    }
+
+    pub fn remove_watch(&mut self, expr: &str) -> Result<(), String> {
+        let len = self.watches.len();
+        self.watches.retain(|w| *w != expr.to_lowercase());
+        if self.watches.len() == len {
+            return Err(format!("No watch \"{expr}\""));
+        }
+        Ok(())
+    }
+
+    /// Values of the watched expressions
+    pub fn watches(&self) -> Vec<(String, String)> {
+        self.watches
+            .iter()
+            .map(|w| (w.clone(), self.eval(w).unwrap_or_else(|e| e)))
+            .collect()
+    }
+
+    /// Evaluates a register, a flag, `pc`, `sp`, `dir`, `ram`, `stack` or a memory cell
+    /// `[N]`, where N is an address or a `#var` name
+    pub fn eval(&self, expr: &str) -> Result<String, String> {
+        let i = self.info();
+        let expr = expr.to_lowercase();
+        if let Some(addr) = expr.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            let addr = match addr.parse::<u64>() {
+                Ok(addr) => addr,
+                Err(_) => {
+                    *self
+                        .symbols
+                        .vars
+                        .iter()
+                        .find(|(name, _)| name.to_lowercase() == addr)
+                        .ok_or_else(|| format!("Variable \"{addr}\" not found"))?
+                        .1
+                }
+            };
+            return i
+                .ram
+                .get(addr as usize)
+                .map(|v| v.to_string())
+                .ok_or_else(|| format!("Address [{addr}] not in 0..{}", i.ram.len()));
+        }
+        Ok(match expr.as_str() {
+            "ax" => i.reg_ax.to_string(),
+            "bx" => i.reg_bx.to_string(),
+            "cx" => i.reg_cx.to_string(),
+            "dx" => i.reg_dx.to_string(),
+            "en" => i.reg_en.to_string(),
+            "ag" => i.reg_ag.to_string(),
+            "sd" => i.reg_sd.to_string(),
+            "md" => i.reg_md.to_string(),
+            "fs" => i.flag_fs.to_string(),
+            "fz" => i.flag_fz.to_string(),
+            "fo" => i.flag_fo.to_string(),
+            "ef" => i.flag_ef.to_string(),
+            "eb" => i.flag_eb.to_string(),
+            "ec" => i.flag_ec.to_string(),
+            "pc" => i.pc.to_string(),
+            "sp" => i.sp.to_string(),
+            "dir" => i.dir.to_string(),
+            "ram" => format!("{:?}", i.ram),
+            "stack" => format!("{:?}", &i.stack[..i.sp]),
+            _ => return Err(format!("Unknown expression \"{expr}\"")),
+        })
+    }
+
+    /// Listing of the genome with the current instruction and breakpoints marked
+    pub fn listing(&self) -> Vec<String> {
+        let i = self.info();
+        compiler::decompile_lines(Vec::clone(&i.genom), &self.symbols)
+            .into_iter()
+            .map(|(ix, line)| match ix {
+                Some(ix) => self.format_line(ix, &line, i.pc),
+                None => format!("        {line}"),
+            })
+            .collect()
+    }
+
+    /// Instruction at pc
+    pub fn current(&self) -> String {
+        let i = self.info();
+        compiler::decompile_lines(Vec::clone(&i.genom), &self.symbols)
+            .into_iter()
+            .find(|(ix, _)| *ix == Some(i.pc))
+            .map(|(_, line)| self.format_line(i.pc, &line, i.pc))
+            .unwrap_or_default()
+    }
+
+    fn format_line(&self, ix: usize, line: &str, pc: usize) -> String {
+        let marker = if ix == pc { '>' } else { ' ' };
+        let bp = if self.breakpoints.contains(&ix) {
+            '*'
+        } else {
+            ' '
+        };
+        match self.symbols.source_line(ix) {
+            Some(src) => format!(
+                "{bp}{marker}{ix:04}   {line:<30} // {}: {}",
+                src.line, src.text
+            ),
+            None => format!("{bp}{marker}{ix:04}   {line}"),
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use std::collections::BTreeMap;
+
+use botc::{
+    code::{CommandArg, Opcode},
+    isa::{InstructionSet, InstructionSpec},
+};
+
+use super::{bot::Bot, Rules, WorldAccessor};
+
+/// Executes the custom instruction with its arguments, returns true if the instruction
+/// ends the cycle of the bot. The arguments match `InstructionSpec::args`.
+pub type Handler = fn(&mut Bot, &[CommandArg], &mut dyn WorldAccessor, &Rules) -> bool;
+
+/// Custom instructions of the world with their handlers. Handlers are code, so they
+/// are not saved with the rules and must be registered again after `World::load`.
+#[derive(Clone, Default)]
+pub struct Instructions {
+    set: InstructionSet,
+    handlers: BTreeMap<Opcode, Handler>,
+}
+
+impl Instructions {
+    pub fn register(&mut self, spec: InstructionSpec, handler: Handler) -> Result<(), String> {
+        let opcode = spec.opcode;
+        self.set.register(spec)?;
+        self.handlers.insert(opcode, handler);
+        Ok(())
+    }
+
+    /// Builtin commands with the registered instructions, genomes using them are
+    /// compiled with it
+    pub fn set(&self) -> &InstructionSet {
+        &self.set
+    }
+
+    pub fn handler(&self, opcode: Opcode) -> Option<Handler> {
+        self.handlers.get(&opcode).copied()
+    }
+}
@@ -0,0 +1,154 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use super::bot::Bot;
+
+const LINEAGE_CSV_HEADER: &str = "id,parent_id,colony_id,genom_id,birth_tick,death_tick,cause";
+
+/// Why the bot died
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeathCause {
+    /// the energy dropped to zero
+    Starvation,
+    /// eaten by the bot with the id
+    Killed { by: usize },
+    /// tried to give birth into an occupied cell
+    NoRoom,
+    /// the genome went out of the RAM or genome with `Fault::Kill`, or is empty
+    Fault,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Death {
+    pub tick: usize,
+    pub cause: DeathCause,
+}
+
+/// Life of one bot
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LineageRecord {
+    pub id: usize,
+    /// None for the bots placed by `World::spawn`
+    pub parent_id: Option<usize>,
+    pub colony_id: usize,
+    pub genom_id: usize,
+    pub birth_tick: usize,
+    /// None while the bot is alive
+    pub death: Option<Death>,
+}
+
+/// Gives ids to the bots and, if tracking is on, records their births and deaths
+/// to build the family tree of a run
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lineage {
+    next_id: usize,
+    tracking: bool,
+    records: BTreeMap<usize, LineageRecord>,
+}
+
+impl Lineage {
+    /// Gives the id to the bot placed on the map
+    pub(super) fn birth(&mut self, b: &mut Bot, tick: usize) {
+        b.id = self.next_id;
+        b.birth_tick = tick;
+        self.next_id += 1;
+        if self.tracking {
+            self.add(b);
+        }
+    }
+
+    pub(super) fn death(&mut self, b: &Bot, tick: usize) {
+        if let (Some(r), Some(cause)) = (self.records.get_mut(&b.id), b.death_cause()) {
+            r.death = Some(Death { tick, cause });
+        }
+    }
+
+    /// Starts or stops recording, the bots alive at the start are recorded at once
+    pub(super) fn set_tracking<'a>(&mut self, on: bool, alive: impl Iterator<Item = &'a Bot>) {
+        if on && !self.tracking {
+            alive.for_each(|b| self.add(b));
+        }
+        self.tracking = on;
+    }
+
+    pub fn is_tracking(&self) -> bool {
+        self.tracking
+    }
+
+    fn add(&mut self, b: &Bot) {
+        self.records.entry(b.id).or_insert(LineageRecord {
+            id: b.id,
+            parent_id: b.parent_id,
+            colony_id: b.colony_id,
+            genom_id: b.genom_id,
+            birth_tick: b.birth_tick,
+            death: None,
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn get(&self, id: usize) -> Option<&LineageRecord> {
+        self.records.get(&id)
+    }
+
+    /// Records in the order of birth
+    pub fn iter(&self) -> impl Iterator<Item = &LineageRecord> {
+        self.records.values()
+    }
+
+    pub fn children(&self, id: usize) -> impl Iterator<Item = &LineageRecord> {
+        self.iter().filter(move |r| r.parent_id == Some(id))
+    }
+
+    /// Parent, grandparent and so on up to the first recorded ancestor
+    pub fn ancestors(&self, id: usize) -> Vec<usize> {
+        let mut res = Vec::new();
+        let mut cur = self.get(id).and_then(|r| r.parent_id);
+        while let Some(id) = cur {
+            res.push(id);
+            cur = self.get(id).and_then(|r| r.parent_id);
+        }
+        res
+    }
+
+    pub fn to_json(&self) -> Result<String, String> {
+        let records: Vec<&LineageRecord> = self.iter().collect();
+        serde_json::to_string_pretty(&records)
+            .map_err(|e| format!("Failed to serialize lineage: {e}"))
+    }
+
+    /// One bot per line: id, parent id, colony, genom, birth tick, death tick and cause,
+    /// the fields of the alive bots are empty
+    pub fn to_csv(&self) -> String {
+        let mut res = String::from(LINEAGE_CSV_HEADER);
+        res.push('\n');
+        for r in self.iter() {
+            let opt = |v: Option<usize>| v.map(|v| v.to_string()).unwrap_or_default();
+            let cause = match r.death.map(|d| d.cause) {
+                Some(DeathCause::Starvation) => "starvation".to_string(),
+                Some(DeathCause::Killed { by }) => format!("killed by {by}"),
+                Some(DeathCause::NoRoom) => "no room".to_string(),
+                Some(DeathCause::Fault) => "fault".to_string(),
+                None => String::new(),
+            };
+            res.push_str(&format!(
+                "{},{},{},{},{},{},{}\n",
+                r.id,
+                opt(r.parent_id),
+                r.colony_id,
+                r.genom_id,
+                r.birth_tick,
+                opt(r.death.map(|d| d.tick)),
+                cause
+            ));
+        }
+        res
+    }
+}
@@ -1,28 +1,41 @@
-use botc::code::Dir;
-use serde::Deserialize;
-use std::{
-    borrow::Borrow,
-    cell::{Ref, RefCell},
-    mem::swap,
-    rc::Rc,
-    usize,
-};
+use botc::code::{Command, Dir};
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, mem::swap, sync::Arc};
 
+pub mod arena;
 pub mod bot;
+pub mod cost;
+pub mod debugger;
+pub mod isa;
+pub mod lineage;
+pub mod mutation;
+#[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+mod parallel;
+pub mod phylogeny;
+mod snapshot;
 
 use crate::vec2::Vec2u;
-use bot::{Bot, BotCfg};
+use arena::{Arena, BotId};
+use bot::{Bot, BotCfg, Fault};
+use cost::CostTable;
+use isa::{Handler, Instructions};
+use lineage::{DeathCause, Lineage};
+use mutation::{MatingRules, Mutation, MutationRules};
+use phylogeny::GenomRegistry;
 
-type BotRef = Rc<RefCell<Bot>>;
+/// Random generator of the world. All randomness of the simulation must be taken from it,
+/// so that runs with the same seed are reproducible.
+pub type WorldRng = rand_pcg::Pcg64;
 
 #[derive(Debug)]
 pub struct Cell {
     pub sun: usize,
     pub mineral: usize,
-    pub bot: Option<BotRef>,
+    pub bot: Option<BotId>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize, Clone)]
 pub struct Rules {
     pub max_commands_per_cycle: usize,
     pub energy_for_split: isize,
@@ -40,9 +53,53 @@ pub struct Rules {
     pub energy_per_sun_oth_boost: isize,
     pub ram_size: usize,
     pub stack_size: usize,
+    /// Mutations of the forked genome besides the command replacement,
+    /// all of them are off if not set
+    #[serde(default)]
+    pub mutations: MutationRules,
+    /// Crossover and cost of the `mate` command
+    #[serde(default)]
+    pub mating: MatingRules,
+    /// What the bot does on an invalid address or label, they are wrapped if not set
+    #[serde(default)]
+    pub on_fault: Fault,
+    /// Energy and cycles taken by the commands, by default every command takes
+    /// one cycle and no energy
+    #[serde(default)]
+    pub costs: CostTable,
+    /// Custom instructions, see `World::register_instruction`
+    #[serde(skip)]
+    pub instructions: Instructions,
 }
 
-#[derive(Clone, Copy)]
+impl Rules {
+    /// Checks the values the simulation can not run with, the probabilities must be
+    /// in `0.0..=1.0` and the divisors positive
+    pub fn check(&self) -> Result<(), String> {
+        probability("mutation_ver", self.mutation_ver)?;
+        self.mutations.check()?;
+        for (name, val) in [
+            ("max_random_value", self.max_random_value),
+            ("on_bite_energy_delimiter", self.on_bite_energy_delimiter),
+            ("age_per_energy_penalty", self.age_per_energy_penalty),
+        ] {
+            if val <= 0 {
+                return Err(format!("Rule {name} must be positive, got {val}"));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn probability(name: &str, p: f64) -> Result<(), String> {
+    if (0.0..=1.0).contains(&p) {
+        Ok(())
+    } else {
+        Err(format!("Rule {name} must be in 0.0..=1.0, got {p}"))
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
 pub struct Info {
     pub h: usize,
     pub w: usize,
@@ -58,6 +115,7 @@ pub struct WorldConfig<T: FnMut(usize, usize) -> usize, U: FnMut(usize, usize) -
     pub h: usize,
     pub w: usize,
     pub rules: Rules,
+    pub seed: u64,
     pub sun: T,
     pub mineral: U,
 }
@@ -65,26 +123,67 @@ pub struct WorldConfig<T: FnMut(usize, usize) -> usize, U: FnMut(usize, usize) -
 pub struct World {
     size: Vec2u,
     map: Vec<Vec<Cell>>,
-    bots: Vec<(Vec2u, BotRef)>,
+    bots: Arena<(Vec2u, Bot)>,
     colony_cnt: usize,
-    genom_cnt: usize,
+    genoms: GenomRegistry,
+    lineage: Lineage,
+    // number of finished updates
+    tick: usize,
+    // bots died in this tick, they are removed at its end
+    dead: Vec<BotId>,
     rules: Rules,
     info: Info,
+    seed: u64,
+    rng: WorldRng,
 }
 
-struct WorldAccessor<'a> {
+/// The world as seen by a bot: the cell it stands on and its neighbours.
+/// Directions are absolute, the bot turns them itself.
+pub trait WorldAccessor {
+    /// Moves the bot to the neighbour cell if it is free
+    fn mov(&mut self, dir: Dir) -> Result<(), ()>;
+    fn get_new_colony_id(&mut self) -> usize;
+    /// Registers the mutant of the parent genome, returns its id
+    fn get_new_genom_id(&mut self, parent: usize, mutations: Vec<Mutation>) -> usize;
+    fn get_rng(&mut self) -> &mut WorldRng;
+    /// Places the new bot into the neighbour cell if it is free
+    fn spawn(&mut self, dir: Dir, b: Bot) -> Result<(), ()>;
+    /// Kills the neighbour bot, returns its energy
+    fn kill(&mut self, dir: Dir) -> Option<isize>;
+    fn get_sun(&self) -> isize;
+    /// Difference between the sun level of the bot cell and of the neighbour cell
+    fn get_sun_diff(&self, dir: Dir) -> isize;
+    fn get_mineral(&self) -> isize;
+    /// Difference between the mineral level of the bot cell and of the neighbour cell
+    fn get_mineral_diff(&self, dir: Dir) -> isize;
+    /// None if the neighbour cell is free, otherwise whether the neighbour is from the colony
+    fn is_some_colony(&self, dir: Dir, colony: usize) -> Option<bool>;
+    /// Genome id and genome of the neighbour bot
+    fn get_genom(&self, dir: Dir) -> Option<(usize, Arc<Vec<Command>>)>;
+    /// Shares the energy with the neighbours from the colony, returns the bot's share
+    fn energy_diffusion(&mut self, colony: usize, energy: isize) -> isize;
+}
+
+/// Accessor of the world map for the bot at `pos`
+struct MapAccessor<'a> {
     pos: &'a mut Vec2u,
     map_size: Vec2u,
     map: &'a mut Vec<Vec<Cell>>,
-    newborn: &'a mut Vec<(Vec2u, BotRef)>,
+    bots: &'a mut Arena<(Vec2u, Bot)>,
     colony_cnt: &'a mut usize,
-    genom_cnt: &'a mut usize,
+    genoms: &'a mut GenomRegistry,
+    lineage: &'a mut Lineage,
+    dead: &'a mut Vec<BotId>,
+    tick: usize,
+    // id of the bot the accessor is given to
+    bot_id: usize,
+    rng: &'a mut WorldRng,
 }
 
-impl<'a> WorldAccessor<'_> {
+impl WorldAccessor for MapAccessor<'_> {
     fn mov(&mut self, dir: Dir) -> Result<(), ()> {
         let pos = self.pos.mod_add(dir.into(), self.map_size);
-        if let None = self.map[pos.y][pos.x].bot {
+        if self.map[pos.y][pos.x].bot.is_none() {
             let mut bot = None;
             swap(&mut self.map[self.pos.y][self.pos.x].bot, &mut bot);
             self.map[pos.y][pos.x].bot = bot;
@@ -95,24 +194,25 @@ impl<'a> WorldAccessor<'_> {
         }
     }
 
-    pub fn get_new_colony_id(&mut self) -> usize {
+    fn get_new_colony_id(&mut self) -> usize {
         let rc = *self.colony_cnt;
-        *self.colony_cnt = *self.colony_cnt + 1;
+        *self.colony_cnt += 1;
         rc
     }
 
-    pub fn get_new_genom_id(&mut self) -> usize {
-        let rc = *self.genom_cnt;
-        *self.genom_cnt = *self.genom_cnt + 1;
-        rc
+    fn get_new_genom_id(&mut self, parent: usize, mutations: Vec<Mutation>) -> usize {
+        self.genoms.new_genom(Some(parent), mutations)
     }
 
-    fn spawn(&mut self, dir: Dir, b: Bot) -> Result<(), ()> {
+    fn get_rng(&mut self) -> &mut WorldRng {
+        self.rng
+    }
+
+    fn spawn(&mut self, dir: Dir, mut b: Bot) -> Result<(), ()> {
         let pos = self.pos.mod_add(dir.into(), self.map_size);
-        if let None = self.map[pos.y][pos.x].bot {
-            let b = Rc::new(RefCell::new(b));
-            self.map[pos.y][pos.x].bot = Some(b.clone());
-            self.newborn.push((pos, b));
+        if self.map[pos.y][pos.x].bot.is_none() {
+            self.lineage.birth(&mut b, self.tick);
+            self.map[pos.y][pos.x].bot = Some(self.bots.insert((pos, b)));
             Ok(())
         } else {
             Err(())
@@ -121,10 +221,15 @@ impl<'a> WorldAccessor<'_> {
 
     fn kill(&mut self, dir: Dir) -> Option<isize> {
         let pos = self.pos.mod_add(dir.into(), self.map_size);
-        self.map[pos.y][pos.x]
-            .bot
-            .clone()
-            .map(|b| RefCell::borrow_mut(Rc::borrow(&b)).kill())
+        // the victim stays in its cell until the end of the tick
+        let id = self.map[pos.y][pos.x].bot?;
+        let (_, b) = self.bots.get_mut(id)?;
+        // a dead bot has no energy to give
+        if !b.is_live() {
+            return None;
+        }
+        self.dead.push(id);
+        Some(b.kill(DeathCause::Killed { by: self.bot_id }))
     }
 
     fn get_sun(&self) -> isize {
@@ -147,26 +252,35 @@ impl<'a> WorldAccessor<'_> {
 
     fn is_some_colony(&self, dir: Dir, colony: usize) -> Option<bool> {
         let pos = self.pos.mod_add(dir.into(), self.map_size);
-        let Some(bot) = &self.map[pos.y][pos.x].bot else {
-            return None;
-        };
-        Some(RefCell::borrow(Rc::borrow(bot)).get_colony() == colony)
+        let (_, bot) = self.bots.get(self.map[pos.y][pos.x].bot?)?;
+        Some(bot.get_colony() == colony)
     }
 
-    fn energy_diffusion(&self, colony: usize, mut energy: isize) -> isize {
+    fn get_genom(&self, dir: Dir) -> Option<(usize, Arc<Vec<Command>>)> {
+        let pos = self.pos.mod_add(dir.into(), self.map_size);
+        let (_, bot) = self.bots.get(self.map[pos.y][pos.x].bot?)?;
+        Some((bot.genom_id, bot.genom.clone()))
+    }
+
+    fn energy_diffusion(&mut self, colony: usize, mut energy: isize) -> isize {
         let mut bro = Vec::new();
         for d in [Dir::Front, Dir::Right, Dir::Back, Dir::Left] {
             let pos = self.pos.mod_add(d.into(), self.map_size);
-            if let Some(b) = &self.map[pos.y][pos.x].bot {
-                if RefCell::borrow(Rc::borrow(b)).get_colony() == colony {
-                    bro.push(b);
-                    energy += RefCell::borrow(Rc::borrow(b)).get_energy();
+            let Some(id) = self.map[pos.y][pos.x].bot else {
+                continue;
+            };
+            if let Some((_, b)) = self.bots.get(id) {
+                if b.get_colony() == colony {
+                    bro.push(id);
+                    energy += b.get_energy();
                 }
             }
         }
-        energy = energy / (bro.len() + 1) as isize;
-        for b in bro.into_iter() {
-            RefCell::borrow_mut(Rc::borrow(b)).set_energy(energy);
+        energy /= (bro.len() + 1) as isize;
+        for id in bro.into_iter() {
+            if let Some((_, b)) = self.bots.get_mut(id) {
+                b.set_energy(energy);
+            }
         }
         energy
     }
@@ -196,9 +310,12 @@ impl World {
         Self {
             size: (cfg.w, cfg.h).into(),
             map,
-            bots: Vec::new(),
+            bots: Arena::new(),
             colony_cnt: 0,
-            genom_cnt: 1,
+            genoms: GenomRegistry::default(),
+            lineage: Lineage::default(),
+            tick: 0,
+            dead: Vec::new(),
             info: Info {
                 h: cfg.h,
                 w: cfg.w,
@@ -210,51 +327,65 @@ impl World {
                 min_energy: 0,
             },
             rules: cfg.rules,
+            seed: cfg.seed,
+            rng: WorldRng::seed_from_u64(cfg.seed),
         }
     }
 
     pub fn update(&mut self) -> Result<(), ()> {
+        #[cfg(not(all(feature = "parallel", not(target_arch = "wasm32"))))]
+        self.update_sequential()?;
+        #[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+        self.update_parallel()?;
+
+        self.remove_dead();
+        self.update_info();
+        self.tick += 1;
+        Ok(())
+    }
+
+    /// Updates the bots one by one, every bot sees the changes made by the previous ones
+    #[cfg(not(all(feature = "parallel", not(target_arch = "wasm32"))))]
+    fn update_sequential(&mut self) -> Result<(), ()> {
+        // bots born in this tick are not updated
+        let ids: Vec<BotId> = self.bots.ids().collect();
+        for id in ids {
+            if let Some(res) = self.with_bot(id, |b, wa, rules| b.update(wa, rules)) {
+                res?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Frees the cells and the slots of the bots died in this tick
+    fn remove_dead(&mut self) {
+        for id in std::mem::take(&mut self.dead) {
+            if let Some((pos, b)) = self.bots.remove(id) {
+                self.lineage.death(&b, self.tick);
+                self.map[pos.y][pos.x].bot = None;
+            }
+        }
+    }
+
+    fn update_info(&mut self) {
         self.info.max_age = 0;
         self.info.max_energy = 0;
         self.info.min_age = usize::MAX;
         self.info.min_energy = usize::MAX;
-
-        let mut newborn: Vec<(Vec2u, BotRef)> = Vec::new();
-        for (pos, b) in self.bots.iter_mut() {
-            let mut wa = WorldAccessor {
-                newborn: &mut newborn,
-                pos: pos,
-                map_size: self.size,
-                map: &mut self.map,
-                colony_cnt: &mut self.colony_cnt,
-                genom_cnt: &mut self.genom_cnt,
-            };
-            RefCell::borrow_mut(Rc::borrow(b)).update(&mut wa, &self.rules)?;
-            let info = RefCell::borrow(Rc::borrow(b)).get_info();
+        let mut genoms: HashMap<usize, usize> = HashMap::new();
+        for (_, (_, b)) in self.bots.iter() {
+            if self.genoms.is_tracking() {
+                *genoms.entry(b.genom_id).or_default() += 1;
+            }
+            let info = b.get_info();
             self.info.max_age = self.info.max_age.max(info.reg_ag as usize);
-            self.info.max_energy = self.info.max_age.max(info.reg_en as usize);
+            self.info.max_energy = self.info.max_energy.max(info.reg_en as usize);
             self.info.min_age = self.info.min_age.min(info.reg_ag as usize);
             self.info.min_energy = self.info.min_energy.min(info.reg_en as usize);
         }
-
-        for (_, b) in newborn.iter_mut() {
-            let info = RefCell::borrow(Rc::borrow(b)).get_info();
-            self.info.max_age = self.info.max_age.max(info.reg_ag as usize);
-            self.info.max_energy = self.info.max_age.max(info.reg_en as usize);
-            self.info.min_age = self.info.min_age.min(info.reg_ag as usize);
-            self.info.min_energy = self.info.min_energy.min(info.reg_en as usize);
+        if self.genoms.is_tracking() {
+            self.genoms.census(self.tick, &genoms);
         }
-
-        self.bots.append(&mut newborn);
-        self.bots.retain(|(pos, bot)| {
-            if RefCell::borrow_mut(Rc::borrow(bot)).is_live() {
-                true
-            } else {
-                self.map[pos.y][pos.x].bot = None;
-                false
-            }
-        });
-        Ok(())
     }
 
     pub fn foreach_cell<F>(&self, mut func: F)
@@ -270,54 +401,135 @@ impl World {
 
     pub fn foreach_bot<F>(&self, mut func: F)
     where
-        F: FnMut(usize, usize, Ref<Bot>),
+        F: FnMut(usize, usize, &Bot),
     {
-        self.bots.iter().for_each(|(pos, c)| {
-            func(pos.x, pos.y, RefCell::borrow(Rc::borrow(c)));
+        self.bots.iter().for_each(|(_, (pos, b))| {
+            func(pos.x, pos.y, b);
         });
     }
 
-    pub fn spawn(&mut self, pos: Vec2u, gen_b32: &str) -> Result<(), ()> {
-        let genom = botc::code_packer::from_b32(gen_b32).map_err(|_| ())?;
+    /// Spawns a bot with the genome in any encoding of `botc::code_packer`
+    pub fn spawn<T: AsRef<[u8]>>(&mut self, pos: Vec2u, genom: T) -> Result<(), ()> {
+        let genom = botc::code_packer::unpack(genom.as_ref()).map_err(|_| ())?;
+        self.spawn_genom(pos, genom.commands).map(|_| ())
+    }
+
+    /// Checks the genome for the bots of the world, see `botc::analysis`
+    pub fn analyze_genom(&self, genom: &[botc::code::Command]) -> Vec<botc::analysis::Issue> {
+        let limits = botc::analysis::Limits {
+            ram_size: Some(self.rules.ram_size),
+            stack_size: Some(self.rules.stack_size),
+        };
+        botc::analysis::analyze_with_isa(genom, &limits, self.rules.instructions.set())
+    }
+
+    /// Adds the custom instruction executed by the handler, its opcode must not change
+    /// between runs so that the saved genomes keep their meaning
+    pub fn register_instruction(
+        &mut self,
+        spec: botc::isa::InstructionSpec,
+        handler: Handler,
+    ) -> Result<(), String> {
+        self.rules.instructions.register(spec, handler)
+    }
+
+    /// Spawns a bot with the decoded genome, returns its id. Genomes with errors
+    /// found by the analysis are rejected
+    fn spawn_genom(&mut self, pos: Vec2u, genom: Vec<botc::code::Command>) -> Result<BotId, ()> {
+        if self.analyze_genom(&genom).iter().any(|i| i.is_error()) {
+            return Err(());
+        }
         let cell = self
             .map
             .get_mut(pos.y)
             .ok_or(())?
             .get_mut(pos.x)
             .ok_or(())?;
-        if let None = cell.bot {
-            let b = Bot::new(BotCfg {
+        if cell.bot.is_none() {
+            let mut b = Bot::new(BotCfg {
                 colony_id: self.colony_cnt,
-                genom_id: 0,
-                genom: Rc::new(genom),
+                genom_id: self.genoms.new_genom(None, Vec::new()),
+                genom: Arc::new(genom),
                 ram_size: self.rules.ram_size,
                 stack_size: self.rules.stack_size,
                 energy: self.rules.start_energy,
             });
-            let b = Rc::new(RefCell::new(b));
-            self.colony_cnt = self.colony_cnt + 1;
-            self.map[pos.y][pos.x].bot = Some(b.clone());
-            self.bots.push((pos, b));
-            Ok(())
+            self.colony_cnt += 1;
+            self.lineage.birth(&mut b, self.tick);
+            let id = self.bots.insert((pos, b));
+            self.map[pos.y][pos.x].bot = Some(id);
+            Ok(id)
         } else {
             Err(())
         }
     }
 
+    /// Runs the function for one bot with access to the world around it, returns None
+    /// if there is no such bot. The bots it gives birth to are placed into the world
+    /// but not updated, dead bots are left in place.
+    fn with_bot<R, F>(&mut self, id: BotId, func: F) -> Option<R>
+    where
+        F: FnOnce(&mut Bot, &mut MapAccessor, &Rules) -> R,
+    {
+        let (mut pos, mut b) = self.bots.take(id)?;
+        let was_live = b.is_live();
+        let mut wa = MapAccessor {
+            pos: &mut pos,
+            map_size: self.size,
+            map: &mut self.map,
+            bots: &mut self.bots,
+            colony_cnt: &mut self.colony_cnt,
+            genoms: &mut self.genoms,
+            lineage: &mut self.lineage,
+            dead: &mut self.dead,
+            tick: self.tick,
+            bot_id: b.id,
+            rng: &mut self.rng,
+        };
+        let res = func(&mut b, &mut wa, &self.rules);
+        if was_live && !b.is_live() {
+            self.dead.push(id);
+        }
+        self.bots.restore(id, (pos, b));
+        Some(res)
+    }
+
     pub fn get_info(&self) -> Info {
         self.info
     }
 
-    pub fn get_bot_info(&self, pos: Vec2u) ->  Result<bot::Info, ()> {
-        let cell = self
-        .map
-        .get(pos.y)
-        .ok_or(())?
-        .get(pos.x)
-        .ok_or(())?;
-        let Some(b) = &cell.bot else {
-            return Err(());
-        };
-        Ok(RefCell::borrow(Rc::borrow(b)).get_info())
+    /// Number of finished updates
+    pub fn get_tick(&self) -> usize {
+        self.tick
+    }
+
+    pub fn lineage(&self) -> &Lineage {
+        &self.lineage
+    }
+
+    /// Starts or stops recording births and deaths, off by default
+    pub fn set_lineage_tracking(&mut self, on: bool) {
+        self.lineage
+            .set_tracking(on, self.bots.iter().map(|(_, (_, b))| b));
+    }
+
+    pub fn genoms(&self) -> &GenomRegistry {
+        &self.genoms
+    }
+
+    /// Starts or stops recording the genome tree and populations, off by default
+    pub fn set_genom_tracking(&mut self, on: bool) {
+        self.genoms
+            .set_tracking(on, self.bots.iter().map(|(_, (_, b))| b.genom_id));
+    }
+
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn get_bot_info(&self, pos: Vec2u) -> Result<bot::Info, ()> {
+        let cell = self.map.get(pos.y).ok_or(())?.get(pos.x).ok_or(())?;
+        let (_, b) = cell.bot.and_then(|id| self.bots.get(id)).ok_or(())?;
+        Ok(b.get_info())
     }
 }
@@ -0,0 +1,321 @@
+use botc::code::{Command, CommandArg, Label};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use super::Rules;
+
+/// Probabilities of the mutations of a forked genome, in addition to the replacement
+/// of a command with the probability `Rules::mutation_ver`. Every mutation is tried
+/// independently, so one birth may apply several of them.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct MutationRules {
+    /// change one argument of a command
+    pub tweak: f64,
+    /// insert a random command
+    pub insert: f64,
+    /// delete a command
+    pub delete: f64,
+    /// copy a segment right after itself
+    pub duplicate: f64,
+    /// swap two adjacent segments
+    pub swap: f64,
+    /// max change of a number by the tweak
+    pub max_val_step: isize,
+    /// max length of a duplicated or swapped segment
+    pub max_segment_len: usize,
+    /// insertion and duplication do not make the genome longer
+    pub max_genom_len: usize,
+}
+
+impl Default for MutationRules {
+    fn default() -> Self {
+        Self {
+            tweak: 0.0,
+            insert: 0.0,
+            delete: 0.0,
+            duplicate: 0.0,
+            swap: 0.0,
+            max_val_step: 10,
+            max_segment_len: 8,
+            max_genom_len: 1024,
+        }
+    }
+}
+
+impl MutationRules {
+    pub(super) fn check(&self) -> Result<(), String> {
+        for (name, p) in [
+            ("tweak", self.tweak),
+            ("insert", self.insert),
+            ("delete", self.delete),
+            ("duplicate", self.duplicate),
+            ("swap", self.swap),
+        ] {
+            super::probability(&format!("mutations.{name}"), p)?;
+        }
+        Ok(())
+    }
+}
+
+/// How the genomes of two parents are combined by `mate`
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Crossover {
+    /// the head of the bot genome up to a random point and the tail of the partner one
+    OnePoint,
+    /// every command is taken from a random parent
+    Uniform,
+}
+
+/// Rules of the `mate` command. The bot pays only when the child is born: without
+/// a partner, a free cell around the bot or enough energy the command does nothing.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct MatingRules {
+    pub crossover: Crossover,
+    /// energy lost by the bot in addition to `Rules::energy_for_split` given to the child
+    pub cost: isize,
+}
+
+impl Default for MatingRules {
+    fn default() -> Self {
+        Self {
+            crossover: Crossover::OnePoint,
+            cost: 0,
+        }
+    }
+}
+
+/// Change of the parent genome that made the new one. Labels of the genome are
+/// fixed up by the mutations that move commands, so jumps keep their targets.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Mutation {
+    /// the command at the index was replaced with a random one
+    Replace {
+        index: usize,
+        command: Command,
+    },
+    /// an argument of the command at the index was changed
+    Tweak {
+        index: usize,
+        command: Command,
+    },
+    /// the random command was inserted at the index
+    Insert {
+        index: usize,
+        command: Command,
+    },
+    Delete {
+        index: usize,
+    },
+    /// the segment was copied right after itself
+    Duplicate {
+        start: usize,
+        len: usize,
+    },
+    /// segments `start..mid` and `mid..end` were swapped
+    Swap {
+        start: usize,
+        mid: usize,
+        end: usize,
+    },
+    /// the genome was combined with the genome of the partner
+    Crossover {
+        partner: usize,
+    },
+}
+
+/// Mutates the genome of a forked bot, returns the applied mutations.
+/// The genome is copied only if it is changed.
+pub fn mutate<R: Rng + ?Sized>(
+    genom: &mut Arc<Vec<Command>>,
+    rng: &mut R,
+    rules: &Rules,
+) -> Vec<Mutation> {
+    let mut res = Vec::new();
+    let m = &rules.mutations;
+    if rng.gen_bool(rules.mutation_ver) {
+        res.extend(replace(Arc::make_mut(genom).as_mut_slice(), rng, rules));
+    }
+    // probabilities are checked only when set, so the old configs give the same runs
+    let ops: [(f64, Op<R>); 5] = [
+        (m.tweak, |g, rng, rules| tweak(g, rng, rules)),
+        (m.insert, insert),
+        (m.delete, delete),
+        (m.duplicate, duplicate),
+        (m.swap, |g, rng, rules| swap(g, rng, rules)),
+    ];
+    for (p, op) in ops {
+        if p > 0.0 && rng.gen_bool(p) {
+            res.extend(op(Arc::make_mut(genom), rng, rules));
+        }
+    }
+    res
+}
+
+/// Combines the genome of the bot with the genome of its partner. The labels keep
+/// their values, the ones out of the new genome are wrapped.
+pub fn crossover<R: Rng + ?Sized>(
+    genom: &[Command],
+    partner: &[Command],
+    rng: &mut R,
+    rules: &Rules,
+) -> Vec<Command> {
+    let mut res: Vec<Command> = match rules.mating.crossover {
+        Crossover::OnePoint => {
+            let point = rng.gen_range(0..=genom.len().min(partner.len()));
+            genom[..point]
+                .iter()
+                .chain(partner[point..].iter())
+                .cloned()
+                .collect()
+        }
+        Crossover::Uniform => genom
+            .iter()
+            .enumerate()
+            .map(|(i, c)| match partner.get(i) {
+                Some(p) if rng.gen_bool(0.5) => p.clone(),
+                _ => c.clone(),
+            })
+            .collect(),
+    };
+    res.truncate(rules.mutations.max_genom_len.max(1));
+    let len = res.len();
+    remap_labels(&mut res, |l| l % len);
+    res
+}
+
+type Op<R> = fn(&mut Vec<Command>, &mut R, &Rules) -> Option<Mutation>;
+
+fn replace<R: Rng + ?Sized>(genom: &mut [Command], rng: &mut R, rules: &Rules) -> Option<Mutation> {
+    let index = rng.gen_range(0..genom.len());
+    let command = Command::rand(
+        rng,
+        genom.len(),
+        rules.max_random_value,
+        rules.ram_size as isize,
+    );
+    genom[index] = command.clone();
+    Some(Mutation::Replace { index, command })
+}
+
+fn tweak<R: Rng + ?Sized>(genom: &mut [Command], rng: &mut R, rules: &Rules) -> Option<Mutation> {
+    let index = rng.gen_range(0..genom.len());
+    let mut args = genom[index].args();
+    if args.is_empty() {
+        return None;
+    }
+    let i = rng.gen_range(0..args.len());
+    let step = rules.mutations.max_val_step.max(1);
+    args[i] = match args[i] {
+        CommandArg::Val(v) => CommandArg::Val(
+            v.saturating_add(rng.gen_range(-step..=step))
+                .clamp(-rules.max_random_value, rules.max_random_value),
+        ),
+        CommandArg::Label(_) => CommandArg::Label(rng.gen_range(0..genom.len())),
+        CommandArg::Mem(_) => CommandArg::Mem(rng.gen_range(0..rules.ram_size.max(1) as u64)),
+        CommandArg::Dir(_) => CommandArg::Dir(rng.gen()),
+        CommandArg::Reg(_) => CommandArg::Reg(rng.gen()),
+        CommandArg::RwReg(_) => CommandArg::RwReg(rng.gen()),
+    };
+    let command = genom[index].with_args(args)?;
+    genom[index] = command.clone();
+    Some(Mutation::Tweak { index, command })
+}
+
+fn insert<R: Rng + ?Sized>(
+    genom: &mut Vec<Command>,
+    rng: &mut R,
+    rules: &Rules,
+) -> Option<Mutation> {
+    if genom.len() >= rules.mutations.max_genom_len {
+        return None;
+    }
+    let index = rng.gen_range(0..=genom.len());
+    remap_labels(genom, |l| if l >= index { l + 1 } else { l });
+    let command = Command::rand(
+        rng,
+        genom.len() + 1,
+        rules.max_random_value,
+        rules.ram_size as isize,
+    );
+    genom.insert(index, command.clone());
+    Some(Mutation::Insert { index, command })
+}
+
+fn delete<R: Rng + ?Sized>(genom: &mut Vec<Command>, rng: &mut R, _: &Rules) -> Option<Mutation> {
+    if genom.len() < 2 {
+        return None;
+    }
+    let index = rng.gen_range(0..genom.len());
+    genom.remove(index);
+    // labels of the deleted command point to the next one
+    let len = genom.len();
+    remap_labels(genom, |l| if l > index { l - 1 } else { l % len });
+    Some(Mutation::Delete { index })
+}
+
+fn duplicate<R: Rng + ?Sized>(
+    genom: &mut Vec<Command>,
+    rng: &mut R,
+    rules: &Rules,
+) -> Option<Mutation> {
+    let max_len = rules
+        .mutations
+        .max_segment_len
+        .min(genom.len())
+        .min(rules.mutations.max_genom_len.saturating_sub(genom.len()));
+    if max_len == 0 {
+        return None;
+    }
+    let len = rng.gen_range(1..=max_len);
+    let start = rng.gen_range(0..=genom.len() - len);
+    let end = start + len;
+    // jumps of the copy inside the segment go to the copy
+    let mut copy = genom[start..end].to_vec();
+    remap_labels(&mut copy, |l| if l >= start { l + len } else { l });
+    remap_labels(genom, |l| if l >= end { l + len } else { l });
+    genom.splice(end..end, copy);
+    Some(Mutation::Duplicate { start, len })
+}
+
+fn swap<R: Rng + ?Sized>(genom: &mut [Command], rng: &mut R, rules: &Rules) -> Option<Mutation> {
+    if genom.len() < 2 {
+        return None;
+    }
+    let max_len = rules.mutations.max_segment_len.max(1);
+    let first = rng.gen_range(1..=max_len.min(genom.len() - 1));
+    let second = rng.gen_range(1..=max_len.min(genom.len() - first));
+    let start = rng.gen_range(0..=genom.len() - first - second);
+    let mid = start + first;
+    let end = mid + second;
+    genom[start..end].rotate_left(first);
+    remap_labels(genom, |l| match l {
+        l if (start..mid).contains(&l) => l + second,
+        l if (mid..end).contains(&l) => l - first,
+        l => l,
+    });
+    Some(Mutation::Swap { start, mid, end })
+}
+
+/// Changes every label argument of the genome
+fn remap_labels<F: Fn(Label) -> Label>(genom: &mut [Command], func: F) {
+    for cmd in genom.iter_mut() {
+        let args = cmd.args();
+        if !args.iter().any(|a| matches!(a, CommandArg::Label(_))) {
+            continue;
+        }
+        let args = args
+            .into_iter()
+            .map(|a| match a {
+                CommandArg::Label(l) => CommandArg::Label(func(l)),
+                a => a,
+            })
+            .collect();
+        if let Some(c) = cmd.with_args(args) {
+            *cmd = c;
+        }
+    }
+}
@@ -0,0 +1,254 @@
+use botc::code::{Command, Dir};
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use std::sync::Arc;
+
+use super::{
+    arena::BotId, bot::Bot, lineage::DeathCause, mutation::Mutation, Rules, World, WorldAccessor,
+    WorldRng,
+};
+use crate::vec2::Vec2u;
+
+// colony id of the bots born in the intent phase, real ids are given in the resolve phase
+const PROVISIONAL_ID: usize = usize::MAX;
+
+struct FrameBot {
+    colony_id: usize,
+    genom_id: usize,
+    genom: Arc<Vec<Command>>,
+}
+
+struct FrameCell {
+    sun: isize,
+    mineral: isize,
+    bot: Option<FrameBot>,
+}
+
+/// The map as it was at the start of the tick
+struct Frame {
+    size: Vec2u,
+    cells: Vec<FrameCell>,
+}
+
+impl Frame {
+    fn new(world: &World) -> Self {
+        let cells = world
+            .map
+            .iter()
+            .flatten()
+            .map(|c| FrameCell {
+                sun: c.sun as isize,
+                mineral: c.mineral as isize,
+                bot: c
+                    .bot
+                    .and_then(|id| world.bots.get(id))
+                    .map(|(_, b)| FrameBot {
+                        colony_id: b.get_colony(),
+                        genom_id: b.genom_id,
+                        genom: b.genom.clone(),
+                    }),
+            })
+            .collect();
+        Self {
+            size: world.size,
+            cells,
+        }
+    }
+
+    fn get(&self, pos: Vec2u) -> &FrameCell {
+        &self.cells[pos.y * self.size.x + pos.x]
+    }
+}
+
+/// Change of the map requested by a bot
+enum Intent {
+    Move(Dir),
+    /// the bot with the mutation of the parent genome if it is a mutant
+    Spawn(Dir, Box<Bot>, Option<(usize, Vec<Mutation>)>),
+    Kill(Dir),
+}
+
+/// Accessor of the intent phase: the bot sees the frame, its actions are recorded
+struct IntentAccessor<'a> {
+    frame: &'a Frame,
+    pos: Vec2u,
+    intents: Vec<Intent>,
+    seed: u64,
+    // mutation of the child that is going to be spawned
+    mutation: Option<(usize, Vec<Mutation>)>,
+    // created on demand, most bots never use it
+    rng: Option<WorldRng>,
+}
+
+impl IntentAccessor<'_> {
+    fn neighbour(&self, dir: Dir) -> &FrameCell {
+        self.frame
+            .get(self.pos.mod_add(dir.into(), self.frame.size))
+    }
+}
+
+impl WorldAccessor for IntentAccessor<'_> {
+    fn mov(&mut self, dir: Dir) -> Result<(), ()> {
+        if self.neighbour(dir).bot.is_some() {
+            return Err(());
+        }
+        self.intents.push(Intent::Move(dir));
+        Ok(())
+    }
+
+    fn get_new_colony_id(&mut self) -> usize {
+        PROVISIONAL_ID
+    }
+
+    fn get_new_genom_id(&mut self, parent: usize, mutations: Vec<Mutation>) -> usize {
+        self.mutation = Some((parent, mutations));
+        PROVISIONAL_ID
+    }
+
+    fn get_rng(&mut self) -> &mut WorldRng {
+        let seed = self.seed;
+        self.rng
+            .get_or_insert_with(|| WorldRng::seed_from_u64(seed))
+    }
+
+    fn spawn(&mut self, dir: Dir, b: Bot) -> Result<(), ()> {
+        if self.neighbour(dir).bot.is_some() {
+            return Err(());
+        }
+        let mutation = self.mutation.take();
+        self.intents.push(Intent::Spawn(dir, Box::new(b), mutation));
+        Ok(())
+    }
+
+    fn kill(&mut self, dir: Dir) -> Option<isize> {
+        // the victim may be gone by the resolve phase, its energy is credited there
+        self.neighbour(dir).bot.as_ref()?;
+        self.intents.push(Intent::Kill(dir));
+        None
+    }
+
+    fn get_sun(&self) -> isize {
+        self.frame.get(self.pos).sun
+    }
+
+    fn get_sun_diff(&self, dir: Dir) -> isize {
+        self.get_sun() - self.neighbour(dir).sun
+    }
+
+    fn get_mineral(&self) -> isize {
+        self.frame.get(self.pos).mineral
+    }
+
+    fn get_mineral_diff(&self, dir: Dir) -> isize {
+        self.get_mineral() - self.neighbour(dir).mineral
+    }
+
+    fn is_some_colony(&self, dir: Dir, colony: usize) -> Option<bool> {
+        self.neighbour(dir)
+            .bot
+            .as_ref()
+            .map(|b| b.colony_id == colony)
+    }
+
+    fn get_genom(&self, dir: Dir) -> Option<(usize, Arc<Vec<Command>>)> {
+        let b = self.neighbour(dir).bot.as_ref()?;
+        Some((b.genom_id, b.genom.clone()))
+    }
+
+    fn energy_diffusion(&mut self, _colony: usize, energy: isize) -> isize {
+        // done in the resolve phase, when the neighbours' energy is known
+        energy
+    }
+}
+
+impl World {
+    /// Updates the bots in two phases. In the intent phase the bot VMs run in parallel,
+    /// every bot sees the map as it was at the start of the tick and its actions on
+    /// the map are recorded. In the resolve phase the actions are applied in the order
+    /// of the bots list, the first bot wins a conflict. A biting bot gets the energy
+    /// of the bot it kills in the resolve phase, so a dead bot is never eaten twice.
+    /// Bots killed in the resolve phase before their turn lose their actions, the energy
+    /// diffusion of a bot happens after its actions are applied. Every bot has its own
+    /// random generator seeded from the world one, so the result does not depend on
+    /// the number of threads.
+    pub(super) fn update_parallel(&mut self) -> Result<(), ()> {
+        let frame = Frame::new(self);
+        let seed: u64 = self.rng.gen();
+
+        // bots born in this tick are not updated
+        let ids: Vec<BotId> = self.bots.ids().collect();
+        let bots: Vec<(usize, Vec2u, &mut Bot)> = self
+            .bots
+            .iter_mut()
+            .enumerate()
+            .map(|(i, (_, (pos, b)))| (i, *pos, b))
+            .collect();
+        let rules = &self.rules;
+        let results = bots
+            .into_par_iter()
+            .map(|(i, pos, b)| {
+                let mut wa = IntentAccessor {
+                    frame: &frame,
+                    pos,
+                    intents: Vec::new(),
+                    seed: seed.wrapping_add(i as u64),
+                    mutation: None,
+                    rng: None,
+                };
+                b.update(&mut wa, rules)?;
+                Ok((b.is_live(), wa.intents))
+            })
+            .collect::<Result<Vec<(bool, Vec<Intent>)>, ()>>()?;
+
+        for (id, (was_live, intents)) in ids.into_iter().zip(results) {
+            if !was_live {
+                self.dead.push(id);
+            }
+            self.with_bot(id, |b, wa, rules| resolve(b, wa, rules, was_live, intents));
+        }
+        Ok(())
+    }
+}
+
+/// Applies the recorded actions of the bot to the map
+fn resolve<W: WorldAccessor>(
+    b: &mut Bot,
+    wa: &mut W,
+    rules: &Rules,
+    was_live: bool,
+    intents: Vec<Intent>,
+) {
+    // a bot killed by one resolved earlier does nothing, as in the sequential update
+    if was_live && !b.is_live() {
+        return;
+    }
+    for intent in intents {
+        match intent {
+            Intent::Move(dir) => {
+                wa.mov(dir).ok();
+            }
+            Intent::Spawn(dir, mut child, mutation) => {
+                if child.colony_id == PROVISIONAL_ID {
+                    child.colony_id = wa.get_new_colony_id();
+                }
+                if let Some((parent, mutations)) = mutation {
+                    child.genom_id = wa.get_new_genom_id(parent, mutations);
+                }
+                if wa.spawn(dir, *child).is_err() {
+                    b.kill(DeathCause::NoRoom);
+                }
+            }
+            Intent::Kill(dir) => {
+                // the bot eats the one that is in the cell now, if it is still alive
+                if let Some(energy) = wa.kill(dir) {
+                    let energy = b.get_energy() + energy / rules.on_bite_energy_delimiter;
+                    b.set_energy(energy.min(rules.max_energy));
+                }
+            }
+        }
+    }
+    // the energy is shared with the neighbours as they are at the bot's turn
+    if was_live && b.is_live() {
+        b.set_energy(wa.energy_diffusion(b.get_colony(), b.get_energy()));
+    }
+}
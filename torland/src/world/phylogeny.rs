@@ -0,0 +1,209 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use super::mutation::Mutation;
+
+/// History of one genome
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GenomRecord {
+    pub id: usize,
+    /// None for the genomes placed by `World::spawn`
+    pub parent_id: Option<usize>,
+    /// Changes of the parent genome, empty for the roots
+    pub mutations: Vec<Mutation>,
+    /// Tick the first bot with the genome was seen, None if it was never born
+    pub first_tick: Option<usize>,
+    /// Tick the last bot with the genome was gone
+    pub extinction_tick: Option<usize>,
+    pub population: usize,
+    /// (tick, population) on every change of the population
+    pub history: Vec<(usize, usize)>,
+}
+
+/// Gives ids to the genomes and, if tracking is on, records which genome every mutant
+/// came from and how many bots carry it
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GenomRegistry {
+    next_id: usize,
+    tracking: bool,
+    records: BTreeMap<usize, GenomRecord>,
+    // genomes with a non zero population
+    alive: BTreeSet<usize>,
+}
+
+impl GenomRegistry {
+    /// Gives the id to the new genome
+    pub(super) fn new_genom(
+        &mut self,
+        parent_id: Option<usize>,
+        mutations: Vec<Mutation>,
+    ) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        if self.tracking {
+            self.add(id, parent_id, mutations);
+        }
+        id
+    }
+
+    /// Updates the populations, `counts` has the number of bots of every genome in the world
+    pub(super) fn census(&mut self, tick: usize, counts: &HashMap<usize, usize>) {
+        let gone: Vec<usize> = self
+            .alive
+            .iter()
+            .filter(|id| !counts.contains_key(id))
+            .copied()
+            .collect();
+        for id in gone {
+            self.set_population(tick, id, 0);
+        }
+        for (&id, &n) in counts.iter() {
+            self.set_population(tick, id, n);
+        }
+    }
+
+    /// Starts or stops recording, the genomes alive at the start are recorded as roots
+    pub(super) fn set_tracking(&mut self, on: bool, alive: impl Iterator<Item = usize>) {
+        if on && !self.tracking {
+            alive.for_each(|id| self.add(id, None, Vec::new()));
+        }
+        self.tracking = on;
+    }
+
+    pub fn is_tracking(&self) -> bool {
+        self.tracking
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn get(&self, id: usize) -> Option<&GenomRecord> {
+        self.records.get(&id)
+    }
+
+    /// Records in the order of creation
+    pub fn iter(&self) -> impl Iterator<Item = &GenomRecord> {
+        self.records.values()
+    }
+
+    pub fn children(&self, id: usize) -> impl Iterator<Item = &GenomRecord> {
+        self.iter().filter(move |r| r.parent_id == Some(id))
+    }
+
+    /// Genomes that were seen in the world
+    fn seen(&self) -> impl Iterator<Item = &GenomRecord> {
+        self.iter().filter(|r| r.first_tick.is_some())
+    }
+
+    pub fn to_json(&self) -> Result<String, String> {
+        let records: Vec<&GenomRecord> = self.seen().collect();
+        serde_json::to_string_pretty(&records)
+            .map_err(|e| format!("Failed to serialize genom registry: {e}"))
+    }
+
+    /// Tree of the genomes in Newick format. Nodes are named `g<id>`, branch lengths are
+    /// the ticks between the appearance of the parent and the child. Several trees are
+    /// joined with an unnamed root.
+    pub fn to_newick(&self) -> String {
+        enum Step {
+            Visit(usize),
+            Comma,
+            Close(usize),
+        }
+
+        let mut children: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        let mut roots = Vec::new();
+        for r in self.seen() {
+            match r
+                .parent_id
+                .filter(|p| self.get(*p).is_some_and(|p| p.first_tick.is_some()))
+            {
+                Some(p) => children.entry(p).or_default().push(r.id),
+                None => roots.push(r.id),
+            }
+        }
+
+        let mut res = String::new();
+        let mut stack = Vec::new();
+        for (i, root) in roots.iter().enumerate().rev() {
+            stack.push(Step::Visit(*root));
+            if i > 0 {
+                stack.push(Step::Comma);
+            }
+        }
+        while let Some(step) = stack.pop() {
+            match step {
+                Step::Visit(id) => match children.get(&id) {
+                    Some(ch) => {
+                        res.push('(');
+                        stack.push(Step::Close(id));
+                        for (i, c) in ch.iter().enumerate().rev() {
+                            stack.push(Step::Visit(*c));
+                            if i > 0 {
+                                stack.push(Step::Comma);
+                            }
+                        }
+                    }
+                    None => self.write_node(&mut res, id),
+                },
+                Step::Comma => res.push(','),
+                Step::Close(id) => {
+                    res.push(')');
+                    self.write_node(&mut res, id);
+                }
+            }
+        }
+        if roots.len() > 1 {
+            res = format!("({res})");
+        }
+        res.push(';');
+        res
+    }
+
+    fn write_node(&self, res: &mut String, id: usize) {
+        res.push_str(&format!("g{id}"));
+        let r = &self.records[&id];
+        let parent_tick = r
+            .parent_id
+            .and_then(|p| self.get(p))
+            .and_then(|p| p.first_tick);
+        if let (Some(parent_tick), Some(tick)) = (parent_tick, r.first_tick) {
+            res.push_str(&format!(":{}", tick - parent_tick));
+        }
+    }
+
+    fn add(&mut self, id: usize, parent_id: Option<usize>, mutations: Vec<Mutation>) {
+        self.records.entry(id).or_insert(GenomRecord {
+            id,
+            parent_id,
+            mutations,
+            first_tick: None,
+            extinction_tick: None,
+            population: 0,
+            history: Vec::new(),
+        });
+    }
+
+    fn set_population(&mut self, tick: usize, id: usize, n: usize) {
+        let Some(r) = self.records.get_mut(&id) else {
+            return;
+        };
+        if r.population == n {
+            return;
+        }
+        r.first_tick.get_or_insert(tick);
+        r.extinction_tick = if n == 0 { Some(tick) } else { None };
+        r.population = n;
+        r.history.push((tick, n));
+        if n == 0 {
+            self.alive.remove(&id);
+        } else {
+            self.alive.insert(id);
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use botc::code::{Command, CommandArg};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    sync::Arc,
+};
+
+use super::{
+    arena::Arena,
+    bot::{Bot, State},
+    lineage::{DeathCause, Lineage},
+    phylogeny::GenomRegistry,
+    Cell, Info, Rules, World, WorldRng,
+};
+use crate::vec2::Vec2u;
+
+// must be increased on every incompatible change of the snapshot layout
+const SNAPSHOT_VERSION: u32 = 5;
+
+#[derive(Serialize, Deserialize)]
+struct CellSnapshot {
+    sun: usize,
+    mineral: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BotSnapshot {
+    x: usize,
+    y: usize,
+    id: usize,
+    parent_id: Option<usize>,
+    birth_tick: usize,
+    // the dead bots stay in the world until it is updated
+    death: Option<DeathCause>,
+    colony_id: usize,
+    genom_id: usize,
+    // index in the snapshot genom table, bots with a common genom share one entry
+    genom: usize,
+    state: State,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    version: u32,
+    h: usize,
+    w: usize,
+    // row by row, h * w cells
+    cells: Vec<CellSnapshot>,
+    genoms: Vec<Vec<Command>>,
+    // the whole arena with free slots, so that the ids of new bots are the same after loading
+    bots: Arena<BotSnapshot>,
+    colony_cnt: usize,
+    genom_registry: GenomRegistry,
+    lineage: Lineage,
+    tick: usize,
+    rules: Rules,
+    info: Info,
+    seed: u64,
+    rng: WorldRng,
+}
+
+impl World {
+    /// Writes the full world state: map, bots with their VM state and genomes, counters and rules.
+    pub fn save<W: Write>(&self, writer: W) -> Result<(), String> {
+        let mut genom_index: HashMap<*const Vec<Command>, usize> = HashMap::new();
+        let mut genoms = Vec::new();
+        let bots = self.bots.map(|_, (pos, b)| {
+            let genom = *genom_index.entry(Arc::as_ptr(&b.genom)).or_insert_with(|| {
+                genoms.push(Vec::clone(&b.genom));
+                genoms.len() - 1
+            });
+            BotSnapshot {
+                x: pos.x,
+                y: pos.y,
+                id: b.id,
+                parent_id: b.parent_id,
+                birth_tick: b.birth_tick,
+                death: b.death,
+                colony_id: b.colony_id,
+                genom_id: b.genom_id,
+                genom,
+                state: b.state.clone(),
+            }
+        });
+
+        let snapshot = Snapshot {
+            version: SNAPSHOT_VERSION,
+            h: self.size.y,
+            w: self.size.x,
+            cells: self
+                .map
+                .iter()
+                .flatten()
+                .map(|c| CellSnapshot {
+                    sun: c.sun,
+                    mineral: c.mineral,
+                })
+                .collect(),
+            genoms,
+            bots,
+            colony_cnt: self.colony_cnt,
+            genom_registry: self.genoms.clone(),
+            lineage: self.lineage.clone(),
+            tick: self.tick,
+            rules: self.rules.clone(),
+            info: self.info,
+            seed: self.seed,
+            rng: self.rng.clone(),
+        };
+        serde_json::to_writer(writer, &snapshot)
+            .map_err(|e| format!("Failed to write snapshot: {e}"))
+    }
+
+    /// Restores a world previously written by [`World::save`].
+    pub fn load<R: Read>(reader: R) -> Result<World, String> {
+        let snapshot: Snapshot =
+            serde_json::from_reader(reader).map_err(|e| format!("Failed to read snapshot: {e}"))?;
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(format!(
+                "Unsupported snapshot version: {}, expect: {}",
+                snapshot.version, SNAPSHOT_VERSION
+            ));
+        }
+        snapshot.rules.check()?;
+        let Some(size) = snapshot.h.checked_mul(snapshot.w) else {
+            return Err(format!("Invalid world size: {}x{}", snapshot.h, snapshot.w));
+        };
+        if snapshot.cells.len() != size {
+            return Err(format!(
+                "Invalid cells count: {}, expect: {}",
+                snapshot.cells.len(),
+                size
+            ));
+        }
+
+        let mut map: Vec<Vec<Cell>> = Vec::new();
+        let mut cells = snapshot.cells.into_iter();
+        for _ in 0..snapshot.h {
+            map.push(
+                cells
+                    .by_ref()
+                    .take(snapshot.w)
+                    .map(|c| Cell {
+                        sun: c.sun,
+                        mineral: c.mineral,
+                        bot: None,
+                    })
+                    .collect(),
+            );
+        }
+
+        // the genomes can be packed again, their labels are in them
+        for (i, g) in snapshot.genoms.iter().enumerate() {
+            let out = g.iter().flat_map(|c| c.args()).find_map(|a| match a {
+                CommandArg::Label(l) if l >= g.len() => Some(l),
+                _ => None,
+            });
+            if let Some(l) = out {
+                return Err(format!("Genom {} has label {} out of it", i, l));
+            }
+        }
+        let genoms: Vec<Arc<Vec<Command>>> = snapshot.genoms.into_iter().map(Arc::new).collect();
+        snapshot.bots.check()?;
+        let bots = snapshot.bots.try_map(|id, b| {
+            let pos: Vec2u = (b.x, b.y).into();
+            let genom = genoms.get(b.genom).ok_or(format!(
+                "Bot at {} refers to unknown genom {}",
+                pos, b.genom
+            ))?;
+            b.state
+                .check(&snapshot.rules)
+                .map_err(|e| format!("Bot at {}: {}", pos, e))?;
+            let cell = map
+                .get_mut(pos.y)
+                .and_then(|r| r.get_mut(pos.x))
+                .ok_or(format!("Bot position {} is out of the map", pos))?;
+            if cell.bot.is_some() {
+                return Err(format!("Several bots at {}", pos));
+            }
+            cell.bot = Some(id);
+            Ok((
+                pos,
+                Bot {
+                    id: b.id,
+                    parent_id: b.parent_id,
+                    birth_tick: b.birth_tick,
+                    colony_id: b.colony_id,
+                    genom_id: b.genom_id,
+                    death: b.death,
+                    state: b.state,
+                    genom: genom.clone(),
+                },
+            ))
+        })?;
+
+        // a world saved in the middle of a tick by the debugger may have dead bots
+        let dead = bots
+            .iter()
+            .filter(|(_, (_, b))| !b.is_live())
+            .map(|(id, _)| id)
+            .collect();
+        Ok(World {
+            size: (snapshot.w, snapshot.h).into(),
+            map,
+            bots,
+            colony_cnt: snapshot.colony_cnt,
+            genoms: snapshot.genom_registry,
+            lineage: snapshot.lineage,
+            tick: snapshot.tick,
+            dead,
+            rules: snapshot.rules,
+            info: snapshot.info,
+            seed: snapshot.seed,
+            rng: snapshot.rng,
+        })
+    }
+}
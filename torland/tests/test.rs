@@ -0,0 +1,918 @@
+use botc::{
+    code::{ArgKind, Command, CommandArg, Dir, Reg, RwReg, Word},
+    code_packer::{self, Encoding},
+    isa::InstructionSpec,
+};
+use rand::SeedableRng;
+use std::sync::Arc;
+use torland::{
+    testing::{self, Event},
+    util,
+    world::{
+        arena::Arena,
+        bot::{Bot, Fault},
+        cost::{Cost, CostTable},
+        debugger::{Debugger, Stop},
+        lineage::DeathCause,
+        mutation::{self, Crossover, Mutation},
+        Rules, World, WorldAccessor, WorldRng,
+    },
+};
+
+const CONFIG: &str = r#"
+{
+    "sun_max_lvl": 10,
+    "mineral_max_lvl": 10,
+    "height": 20,
+    "width": 20,
+    "word_type": "Clustered",
+    "cluster_cnt": 5,
+    "seed": 42,
+    "rules": {
+        "max_commands_per_cycle": 10,
+        "energy_for_split": 1000,
+        "energy_per_sun": 10,
+        "energy_per_mineral": 10,
+        "energy_per_step": 50,
+        "age_per_energy_penalty": 100,
+        "start_energy": 100,
+        "on_bite_energy_delimiter": 10,
+        "max_energy": 10000,
+        "max_random_value": 10000,
+        "mutation_ver": 0.2,
+        "energy_per_sun_free_boost": 10,
+        "energy_per_sun_bro_boost": 5,
+        "energy_per_sun_oth_boost": -2,
+        "ram_size": 100,
+        "stack_size": 100
+    }
+}
+"#;
+const GENOM: &str = "5XB4CCIAAAEAEQDJRBUNYRRP6DQRA6TQBONLJXN7EADQ";
+
+/// Loads the snapshot changed by `f`, the rng state is kept as is
+/// because its numbers do not fit `serde_json::Value`
+fn load_tampered(snapshot: &[u8], f: impl FnOnce(&mut serde_json::Value)) -> Result<World, String> {
+    let text = std::str::from_utf8(snapshot).unwrap();
+    let start = text.find("\"rng\":").unwrap();
+    let rng = &text[start..=start + text[start..].find('}').unwrap()];
+    let mut json: serde_json::Value = serde_json::from_str(text).unwrap();
+    json.as_object_mut().unwrap().remove("rng");
+    f(&mut json);
+    let json = json.to_string();
+    World::load(format!("{},{}}}", &json[..json.len() - 1], rng).as_bytes())
+}
+
+#[test]
+fn snapshot_test() {
+    let mut world = util::make_world(CONFIG).unwrap();
+    world.spawn((5usize, 5).into(), GENOM).unwrap();
+    world.spawn((10usize, 10).into(), GENOM).unwrap();
+    for _ in 0..50 {
+        world.update().unwrap();
+    }
+
+    let mut snapshot = Vec::new();
+    world.save(&mut snapshot).unwrap();
+    let mut loaded = World::load(snapshot.as_slice()).unwrap();
+    let mut loaded_snapshot = Vec::new();
+    loaded.save(&mut loaded_snapshot).unwrap();
+    assert_eq!(snapshot, loaded_snapshot);
+
+    // the snapshot is validated before use
+    assert!(World::load(&snapshot[..snapshot.len() / 2]).is_err());
+    // the VM state of the bots must fit the rules, the stack pointer is in the stack
+    let tampered = String::from_utf8(snapshot.clone()).unwrap();
+    let (start, _) = tampered.match_indices("\"sp\":").next().unwrap();
+    let end = start + tampered[start..].find(',').unwrap();
+    let tampered = format!("{}\"sp\":1000{}", &tampered[..start], &tampered[end..]);
+    assert!(World::load(tampered.as_bytes()).is_err());
+    assert!(load_tampered(&snapshot, |_| {}).is_ok());
+    let err = load_tampered(&snapshot, |json| json["rules"]["ram_size"] = 5.into());
+    assert!(err.err().unwrap().starts_with("Bot at"));
+    let err = load_tampered(&snapshot, |json| json["rules"]["mutation_ver"] = 2.0.into());
+    assert!(err.err().unwrap().contains("mutation_ver"));
+    // the labels of the genomes must be in them
+    let err = load_tampered(&snapshot, |json| {
+        let genom = json["genoms"][0].as_array_mut().unwrap();
+        genom.push(serde_json::json!({ "Jmp": 1000 }));
+    });
+    assert!(err.err().unwrap().contains("label 1000"));
+    // the size must not overflow
+    let err = load_tampered(&snapshot, |json| {
+        json["h"] = usize::MAX.into();
+        json["w"] = 2.into();
+    });
+    assert!(err.err().unwrap().contains("world size"));
+
+    loaded.update().unwrap();
+}
+
+#[test]
+fn arena_test() {
+    let mut arena = Arena::new();
+    let a = arena.insert("a");
+    let b = arena.insert("b");
+    assert_eq!(arena.len(), 2);
+    assert_eq!(arena.remove(a), Some("a"));
+    assert_eq!(arena.remove(a), None);
+
+    // the slot is reused, but the old id does not refer to the new value
+    let c = arena.insert("c");
+    assert_ne!(a, c);
+    assert_eq!(arena.get(a), None);
+    assert_eq!(arena.get(c), Some(&"c"));
+    assert_eq!(
+        arena.iter().map(|(_, v)| *v).collect::<Vec<_>>(),
+        ["c", "b"]
+    );
+    assert!(arena.contains(b));
+}
+
+#[test]
+fn world_send_test() {
+    let mut world = util::make_world(CONFIG).unwrap();
+    world.spawn((5usize, 5).into(), GENOM).unwrap();
+    let mut world = std::thread::spawn(move || {
+        for _ in 0..50 {
+            world.update().unwrap();
+        }
+        world
+    })
+    .join()
+    .unwrap();
+
+    // the loaded world continues the same way, including the ids of new bots
+    let mut snapshot = Vec::new();
+    world.save(&mut snapshot).unwrap();
+    let mut loaded = World::load(snapshot.as_slice()).unwrap();
+    let run = |world: &mut World| {
+        for _ in 0..50 {
+            world.update().unwrap();
+        }
+        let mut snapshot = Vec::new();
+        world.save(&mut snapshot).unwrap();
+        snapshot
+    };
+    assert_eq!(run(&mut world), run(&mut loaded));
+}
+
+#[test]
+fn determinism_test() {
+    let genom = botc::code_packer::to_b32(
+        &botc::compiler::compile(
+            r#"
+            start:
+            eatsun
+            cmpv en 1500
+            jle start
+            fork front start
+            rot frontleft
+            "#
+            .into(),
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    let run = || {
+        let mut world = util::make_world(CONFIG).unwrap();
+        world.spawn((5usize, 5).into(), &genom).unwrap();
+        for _ in 0..300 {
+            world.update().unwrap();
+        }
+        let mut snapshot = Vec::new();
+        world.save(&mut snapshot).unwrap();
+        snapshot
+    };
+    assert_eq!(run(), run());
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn parallel_determinism_test() {
+    let genom = botc::code_packer::to_b32(
+        &botc::compiler::compile(
+            r#"
+            start:
+            eatsun
+            cmpv en 1500
+            jle start
+            fork front start
+            rot frontleft
+            "#
+            .into(),
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    let run = |threads: usize| {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+        pool.install(|| {
+            let mut world = util::make_world(CONFIG).unwrap();
+            world.spawn((5usize, 5).into(), &genom).unwrap();
+            world.spawn((6usize, 5).into(), &genom).unwrap();
+            world.spawn((10usize, 10).into(), GENOM).unwrap();
+            for _ in 0..300 {
+                world.update().unwrap();
+            }
+            let mut snapshot = Vec::new();
+            world.save(&mut snapshot).unwrap();
+            snapshot
+        })
+    };
+    // conflicts are resolved in the order of the bots list, not of the threads
+    assert_eq!(run(1), run(4));
+}
+
+#[test]
+fn double_bite_test() {
+    let compile = |code: &str| {
+        botc::code_packer::to_b32(&botc::compiler::compile(code.into()).unwrap()).unwrap()
+    };
+    let victim = compile("start:\neatsun\njmp start\n");
+    let front = compile("start:\nbite front\njmp start\n");
+    let right = compile("start:\nbite right\njmp start\n");
+    let mut world = util::make_world(CONFIG).unwrap();
+    world.set_lineage_tracking(true);
+    world.spawn((5usize, 5).into(), &victim).unwrap();
+    world.spawn((5usize, 6).into(), &front).unwrap();
+    world.spawn((4usize, 5).into(), &right).unwrap();
+    world.update().unwrap();
+
+    // only the first bot gets the energy of the victim, with `parallel` too
+    let energy = |pos: (usize, usize)| world.get_bot_info(pos.into()).unwrap().reg_en;
+    assert!(energy((5, 6)) > 50);
+    assert_eq!(energy((4, 5)), 50);
+    let death = world.lineage().get(0).and_then(|r| r.death).unwrap();
+    assert_eq!(death.cause, DeathCause::Killed { by: 1 });
+}
+
+#[test]
+fn lineage_test() {
+    let genom = botc::code_packer::to_b32(
+        &botc::compiler::compile(
+            r#"
+            start:
+            eatsun
+            cmpv en 1500
+            jle start
+            fork front start
+            bite left
+            rot frontleft
+            "#
+            .into(),
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    let mut world = util::make_world(CONFIG).unwrap();
+    world.set_lineage_tracking(true);
+    world.spawn((5usize, 5).into(), &genom).unwrap();
+    for _ in 0..300 {
+        world.update().unwrap();
+    }
+
+    let lineage = world.lineage();
+    assert!(lineage.len() > 1);
+    for r in lineage.iter() {
+        let root = lineage.ancestors(r.id).last().copied().unwrap_or(r.id);
+        assert_eq!(root, 0);
+        if let Some(parent) = r.parent_id.and_then(|id| lineage.get(id)) {
+            assert!(parent.id < r.id);
+            assert!(parent.birth_tick <= r.birth_tick);
+        }
+        if let Some(death) = r.death {
+            assert!(death.tick >= r.birth_tick);
+            if let DeathCause::Killed { by } = death.cause {
+                assert!(lineage.get(by).is_some());
+            }
+        }
+    }
+    assert!(lineage.iter().any(|r| r.death.is_some()));
+    assert_eq!(
+        lineage.children(0).count(),
+        lineage.iter().filter(|r| r.parent_id == Some(0)).count()
+    );
+
+    // alive bots are the ones without death
+    let mut alive = Vec::new();
+    world.foreach_bot(|_, _, b| alive.push(b.get_info().id));
+    alive.sort();
+    let not_dead: Vec<usize> = lineage
+        .iter()
+        .filter(|r| r.death.is_none())
+        .map(|r| r.id)
+        .collect();
+    assert_eq!(alive, not_dead);
+    assert_eq!(lineage.to_csv().lines().count(), lineage.len() + 1);
+}
+
+#[test]
+fn bite_after_turn_test() {
+    let compile = |code: &str| {
+        botc::code_packer::to_b32(&botc::compiler::compile(code.into()).unwrap()).unwrap()
+    };
+    let victim = compile("start:\neatsun\njmp start\n");
+    let biter = compile("start:\nbite front\njmp start\n");
+    let mut world = util::make_world(CONFIG).unwrap();
+    world.set_lineage_tracking(true);
+    // the victim is updated first and is bitten after its turn
+    world.spawn((5usize, 4).into(), &victim).unwrap();
+    world.spawn((5usize, 5).into(), &biter).unwrap();
+    world.update().unwrap();
+
+    // the dead bot is removed at the end of the tick it was killed in
+    assert!(world.get_bot_info((5usize, 4).into()).is_err());
+    let mut alive = Vec::new();
+    world.foreach_bot(|_, _, b| alive.push(b.get_info().id));
+    assert_eq!(alive, vec![1]);
+    let death = world.lineage().get(0).and_then(|r| r.death).unwrap();
+    assert_eq!(death.tick, 0);
+    assert_eq!(death.cause, DeathCause::Killed { by: 1 });
+
+    // the free cell can be taken in the next tick
+    world.spawn((5usize, 4).into(), &victim).unwrap();
+}
+
+#[test]
+fn phylogeny_test() {
+    let genom = botc::code_packer::to_b32(
+        &botc::compiler::compile(
+            r#"
+            start:
+            eatsun
+            cmpv en 1500
+            jle start
+            fork front start
+            rot frontleft
+            "#
+            .into(),
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    let mut world = util::make_world(CONFIG).unwrap();
+    world.set_genom_tracking(true);
+    world.spawn((5usize, 5).into(), &genom).unwrap();
+    world.spawn((15usize, 15).into(), &genom).unwrap();
+    for _ in 0..300 {
+        world.update().unwrap();
+    }
+
+    let genoms = world.genoms();
+    let seen: Vec<_> = genoms.iter().filter(|r| r.first_tick.is_some()).collect();
+    assert!(seen.len() > 2);
+    for r in seen.iter() {
+        match r.parent_id {
+            Some(p) => {
+                let p = genoms.get(p).unwrap();
+                assert!(p.first_tick <= r.first_tick);
+                assert!(!r.mutations.is_empty());
+            }
+            None => assert!(r.mutations.is_empty()),
+        }
+        assert_eq!(r.history.last().unwrap().1, r.population);
+        assert_eq!(r.extinction_tick.is_some(), r.population == 0);
+    }
+
+    let mut population = std::collections::HashMap::new();
+    world.foreach_bot(|_, _, b| *population.entry(b.get_info().genom_id).or_insert(0) += 1);
+    for (id, n) in population {
+        assert_eq!(genoms.get(id).unwrap().population, n);
+    }
+
+    // two spawned genomes are two trees under a common root
+    let newick = genoms.to_newick();
+    assert!(newick.starts_with('(') && newick.ends_with(");"));
+    assert_eq!(newick.matches('(').count(), newick.matches(')').count());
+    for r in seen.iter() {
+        assert!(newick.contains(&format!("g{}", r.id)));
+    }
+    let json: serde_json::Value = serde_json::from_str(&genoms.to_json().unwrap()).unwrap();
+    assert_eq!(json.as_array().unwrap().len(), seen.len());
+}
+
+#[test]
+fn mutation_test() {
+    // markers at even indexes, jumps to the markers at odd ones
+    let genom: Vec<Command> = (0..20)
+        .map(|i| match i % 2 {
+            0 => Command::Ldv(RwReg::Ax, i as isize),
+            _ => Command::Jmp((i * 7) % 10 * 2),
+        })
+        .collect();
+    let targets = |g: &[Command], skip: Option<usize>| {
+        let mut res: Vec<isize> = g
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| Some(*i) != skip)
+            .filter_map(|(_, c)| match c {
+                Command::Jmp(l) => match g[*l] {
+                    Command::Ldv(RwReg::Ax, v) => Some(v),
+                    _ => Some(-1),
+                },
+                _ => None,
+            })
+            .collect();
+        res.sort();
+        res
+    };
+    let before = targets(&genom, None);
+
+    let mut rng = WorldRng::seed_from_u64(7);
+    for op in 0..5 {
+        let mut rules = rules();
+        rules.mutation_ver = 0.0;
+        let m = &mut rules.mutations;
+        *[
+            &mut m.tweak,
+            &mut m.insert,
+            &mut m.delete,
+            &mut m.duplicate,
+            &mut m.swap,
+        ][op] = 1.0;
+        for _ in 0..100 {
+            let mut g = Arc::new(genom.clone());
+            let res = mutation::mutate(&mut g, &mut rng, &rules);
+            for c in g.iter() {
+                for a in c.args() {
+                    if let CommandArg::Label(l) = a {
+                        assert!(l < g.len());
+                    }
+                }
+            }
+            // so the mutant can be packed
+            assert!(code_packer::to_b32(&g).is_ok());
+            match res.as_slice() {
+                [Mutation::Tweak { index, command }] => {
+                    assert_eq!((g.len(), &g[*index]), (20, command));
+                }
+                [Mutation::Insert { index, .. }] => {
+                    assert_eq!(g.len(), 21);
+                    assert_eq!(targets(&g, Some(*index)), before);
+                }
+                [Mutation::Delete { index }] => {
+                    assert_eq!(g.len(), 19);
+                    let mut rest = genom.clone();
+                    rest.remove(*index);
+                    let markers = |g: &[Command]| {
+                        let res: Vec<Command> = g
+                            .iter()
+                            .filter(|c| matches!(c, Command::Ldv(..)))
+                            .cloned()
+                            .collect();
+                        res
+                    };
+                    assert_eq!(markers(&g), markers(&rest));
+                }
+                [Mutation::Duplicate { start, len }] => {
+                    assert_eq!(g.len(), 20 + len);
+                    // jumps of the copy go to the copy, the markers are the same
+                    for k in *start..start + len {
+                        if let Command::Ldv(..) = g[k] {
+                            assert_eq!(g[k], g[k + len]);
+                        }
+                    }
+                    assert!(!targets(&g, None).contains(&-1));
+                }
+                [Mutation::Swap { .. }] => {
+                    assert_eq!(g.len(), 20);
+                    assert_eq!(targets(&g, None), before);
+                }
+                // a tweak of a command without arguments does nothing
+                [] => assert_eq!(op, 0),
+                res => panic!("unexpected mutations {res:?}"),
+            }
+        }
+    }
+
+    // without the new probabilities only the replacement happens
+    let mut g = Arc::new(genom.clone());
+    let res = mutation::mutate(&mut g, &mut WorldRng::seed_from_u64(1), &rules());
+    assert!(res.iter().all(|m| matches!(m, Mutation::Replace { .. })));
+
+    // numbers at the ends of the range are tweaked without overflow
+    let mut rules = rules();
+    rules.mutations.tweak = 1.0;
+    rules.mutations.max_val_step = isize::MAX;
+    for v in [isize::MAX, isize::MIN] {
+        let mut g = Arc::new(vec![Command::Ldv(RwReg::Ax, v)]);
+        for _ in 0..20 {
+            mutation::mutate(&mut g, &mut rng, &rules);
+        }
+    }
+
+    // the probabilities out of range are rejected before the world is made
+    let with = |key: &str, val: serde_json::Value| {
+        let mut cfg: serde_json::Value = serde_json::from_str(CONFIG).unwrap();
+        cfg["rules"][key] = val;
+        util::make_world(&cfg.to_string())
+    };
+    assert!(with("mutation_ver", 1.5.into()).is_err());
+    assert!(with("mutations", serde_json::json!({ "tweak": -0.1 })).is_err());
+    assert!(with("max_random_value", (-1).into()).is_err());
+    assert!(with("mutation_ver", 1.0.into()).is_ok());
+}
+
+#[test]
+fn mating_test() {
+    let mut rules = rules();
+    rules.start_energy = 5000;
+    rules.mutation_ver = 0.0;
+    let mut h = testing::harness("start:\nmate front start\n", rules.clone(), 0, 0);
+    // nobody to mate with, the energy is kept
+    h.tick();
+    assert!(h.world.events.is_empty());
+    assert_eq!(h.info().reg_en, 5000 - 50);
+
+    h.world.neighbour(Dir::Front).bot = Some(testing::MockBot {
+        colony_id: 7,
+        energy: 500,
+    });
+    h.world.genoms[Dir::Front as usize] = Some((5, Arc::new(vec![Command::Eatsun])));
+    h.world.neighbour(Dir::FrontRight).bot = Some(testing::MockBot {
+        colony_id: 7,
+        energy: 500,
+    });
+    h.tick();
+    // the child is born in the first free cell around the bot, clockwise from the partner
+    assert_eq!(h.world.events, vec![Event::Spawn(Dir::Right, 1)]);
+    assert_eq!(h.info().reg_en, 5000 - 50 - 1000 - 50);
+
+    // no free cell around the bot, nothing is born and the energy is kept
+    for cell in h.world.neighbours.iter_mut() {
+        cell.bot = Some(testing::MockBot {
+            colony_id: 7,
+            energy: 500,
+        });
+    }
+    h.world.events.clear();
+    h.tick();
+    assert!(h.world.events.is_empty());
+    assert!(h.is_live());
+    assert_eq!(h.info().reg_en, 5000 - 50 - 1000 - 50 - 50);
+
+    // the child genome is made of the parents' commands, labels stay in it
+    let a: Vec<Command> = (0..10).map(|i| Command::Jmp(9 - i)).collect();
+    let b: Vec<Command> = (0..6).map(|_| Command::Eatsun).collect();
+    let mut rng = WorldRng::seed_from_u64(3);
+    for crossover in [Crossover::OnePoint, Crossover::Uniform] {
+        rules.mating.crossover = crossover;
+        for _ in 0..50 {
+            let c = mutation::crossover(&a, &b, &mut rng, &rules);
+            match crossover {
+                Crossover::OnePoint => assert_eq!(c.len(), 6),
+                Crossover::Uniform => assert_eq!(c.len(), 10),
+            }
+            for (i, cmd) in c.iter().enumerate() {
+                match cmd {
+                    Command::Jmp(l) => assert!(*l < c.len() && (9 - i) % c.len() == *l),
+                    cmd => assert_eq!(*cmd, Command::Eatsun),
+                }
+            }
+            if crossover == Crossover::Uniform {
+                assert_eq!(c[6..], a[6..]);
+            }
+        }
+    }
+
+    // mates of two genomes in the world are registered as crossovers
+    let compile = |code: &str| {
+        botc::code_packer::to_b32(&botc::compiler::compile(code.into()).unwrap()).unwrap()
+    };
+    let seeker = compile(
+        r#"
+        start:
+        eatsun
+        cmpv en 1500
+        jle start
+        mate front start
+        rot right
+        "#,
+    );
+    let grower = compile("start:\neatsun\njmp start\n");
+    let mut world = util::make_world(CONFIG).unwrap();
+    world.set_genom_tracking(true);
+    world.spawn((5usize, 5).into(), &seeker).unwrap();
+    world.spawn((6usize, 5).into(), &grower).unwrap();
+    for _ in 0..100 {
+        world.update().unwrap();
+    }
+    assert!(world.genoms().iter().any(|r| r.parent_id == Some(0)
+        && r.mutations.first() == Some(&Mutation::Crossover { partner: 1 })));
+}
+
+#[test]
+fn spawn_encodings_test() {
+    let code = "start:\neatsun\njmp start\n";
+    let genom = botc::compiler::compile(code.into()).unwrap();
+    let mut world = util::make_world(CONFIG).unwrap();
+    for (i, encoding) in Encoding::ALL.into_iter().enumerate() {
+        let packed = code_packer::pack(&genom, None, encoding).unwrap();
+        world.spawn((i, 0).into(), packed).unwrap();
+    }
+    world.spawn((0usize, 1).into(), code).unwrap();
+    assert!(world.spawn((1usize, 1).into(), "jmp nowhere").is_err());
+    let mut cnt = 0;
+    world.foreach_bot(|_, _, b| {
+        assert_eq!(*b.get_info().genom, genom);
+        cnt += 1;
+    });
+    assert_eq!(cnt, Encoding::ALL.len() + 1);
+
+    // the address is out of the RAM of the bots, the warnings are allowed
+    assert!(world
+        .spawn((2usize, 1).into(), "ldr [100] ax\neatsun")
+        .is_err());
+    assert!(world
+        .spawn((2usize, 1).into(), "ldr [99] ax\neatsun\nret")
+        .is_ok());
+}
+
+#[test]
+fn debugger_test() {
+    let code = r#"
+        #var counter
+        start:
+            ldm ax [counter]
+            addv ax 1
+            ldr [counter] ax
+            cmpv ax 3
+            jme done
+            eatsun
+            jmp start
+        done:
+            call sub
+            jmp done
+        sub:
+            ret
+    "#;
+    let (genom, symbols) = botc::compiler::compile_with_symbols(code.into()).unwrap();
+    let mut dbg = Debugger::standalone(rules(), 10, 10, genom, symbols).unwrap();
+
+    assert_eq!(dbg.add_breakpoint("done").unwrap(), 7);
+    assert!(dbg.add_breakpoint("nowhere").is_err());
+    assert!(dbg.add_watch("[counter]").is_ok());
+    assert!(dbg.add_watch("xx").is_err());
+
+    assert_eq!(dbg.step(), Stop::Step);
+    assert_eq!(dbg.eval("pc").unwrap(), "1");
+    assert_eq!(dbg.eval("ax").unwrap(), "0");
+    // eatsun ends the cycle
+    assert_eq!(dbg.finish_tick(), Stop::Step);
+    assert_eq!(dbg.tick(), 1);
+    assert_eq!(
+        dbg.watches(),
+        vec![("[counter]".to_string(), "1".to_string())]
+    );
+
+    assert_eq!(dbg.run(1000), Stop::Breakpoint(7));
+    assert_eq!(dbg.eval("[0]").unwrap(), "3");
+    assert_eq!(dbg.eval("fz").unwrap(), "true");
+    assert!(dbg.current().contains("call sub"));
+    dbg.step();
+    assert_eq!(dbg.eval("stack").unwrap(), "[8]");
+    assert!(dbg.remove_breakpoint("7").is_ok());
+    assert_eq!((dbg.tick(), dbg.info().reg_ag), (2, 2));
+}
+
+fn rules() -> Rules {
+    let cfg: serde_json::Value = serde_json::from_str(CONFIG).unwrap();
+    serde_json::from_value(cfg["rules"].clone()).unwrap()
+}
+
+#[test]
+fn mock_world_test() {
+    // eats where the sun is brighter than in front, otherwise moves forward
+    let code = r#"
+        start:
+            chk front
+            cmpv sd 0
+            jml go
+            eatsun
+            jmp start
+        go:
+            mov front
+            jmp start
+    "#;
+
+    // chk ends the cycle, so every decision takes two cycles
+    let mut h = testing::harness(code, rules(), 10, 0);
+    h.run(6);
+    assert_eq!(
+        h.take_executed()
+            .iter()
+            .filter(|c| **c == Command::Eatsun)
+            .count(),
+        3
+    );
+    assert!(h.world.events.is_empty());
+    assert_eq!(h.info().reg_ag, 6);
+
+    let mut h = testing::harness(code, rules(), 0, 0);
+    h.world.neighbour(Dir::Front).sun = 10;
+    h.run(4);
+    assert!(!h.executed().contains(&Command::Eatsun));
+    assert_eq!(h.world.events, vec![Event::Move(Dir::Front)]);
+    // starved without sun
+    assert!(!h.is_live());
+
+    let mut h = testing::harness("start:\nbite front\njmp start\n", rules(), 0, 0);
+    h.world.neighbour(Dir::Front).bot = Some(testing::MockBot {
+        colony_id: 7,
+        energy: 500,
+    });
+    h.run(2);
+    assert_eq!(h.world.events, vec![Event::Kill(Dir::Front)]);
+    // the second bite finds nobody
+    assert_eq!(h.info().reg_en, 100 + 500 / 10 - 50 - 50);
+}
+
+#[test]
+fn vm_fault_test() {
+    // the address is out of the RAM of 100 values, the label is out of the genome
+    let genom = vec![
+        Command::Ldv(RwReg::Ax, 5),
+        Command::Ldr(105, Reg::Ax),
+        Command::Ldm(RwReg::Bx, 105),
+        Command::Jmp(6),
+        Command::Eatsun,
+    ];
+    let run = |on_fault| {
+        let rules = Rules {
+            on_fault,
+            ..rules()
+        };
+        let mut h = testing::Harness::new(testing::MockWorld::new(10, 0), rules, genom.clone());
+        h.tick();
+        h
+    };
+
+    let h = run(Fault::Wrap);
+    let i = h.info();
+    assert_eq!((i.ram[5], i.reg_bx, i.flag_fo), (5, 5, false));
+    assert_eq!(h.executed().len(), 10);
+    assert!(!h.executed().contains(&Command::Eatsun));
+
+    // the faulty commands are skipped, including the jump
+    let h = run(Fault::Flag);
+    let i = h.info();
+    assert_eq!((i.ram[5], i.reg_bx, i.flag_fo), (0, 0, true));
+    assert_eq!(h.executed().last(), Some(&Command::Eatsun));
+
+    let h = run(Fault::Kill);
+    assert!(!h.is_live());
+    assert_eq!(h.executed(), &genom[..2]);
+    // the genome can not be packed, the info is still shown
+    let info = h.info().to_string();
+    assert!(info.contains("genom: <unpackable: Label 6"), "{info}");
+
+    // an empty genome can not run at all
+    let mut h = testing::Harness::new(testing::MockWorld::new(10, 0), rules(), Vec::new());
+    h.tick();
+    assert!(!h.is_live());
+
+    // random genomes with labels and addresses out of range do not crash the VM
+    let mut rng = WorldRng::seed_from_u64(1);
+    for on_fault in [Fault::Wrap, Fault::Flag, Fault::Kill] {
+        for _ in 0..200 {
+            let len = rand::Rng::gen_range(&mut rng, 1..20);
+            let genom = (0..len)
+                .map(|_| Command::rand(&mut rng, 40, 1000, 300))
+                .collect();
+            let rules = Rules {
+                on_fault,
+                ..rules()
+            };
+            let mut h = testing::Harness::new(testing::MockWorld::new(10, 10), rules, genom);
+            h.run(20);
+        }
+    }
+}
+
+#[test]
+fn cost_test() {
+    let code = "start:\npowv ax 2\njmp start\n";
+    // by default every command takes one cycle and no energy
+    let mut h = testing::harness(code, rules(), 0, 0);
+    h.tick();
+    assert_eq!((h.executed().len(), h.info().reg_en), (10, 50));
+
+    let costs: CostTable = serde_json::from_str(
+        r#"{ "powv": { "energy": 5, "cycles": 4 }, "nop": { "energy": -1 } }"#,
+    )
+    .unwrap();
+    assert_eq!(
+        costs.get(&Command::Powv(RwReg::Ax, 2)),
+        Cost {
+            energy: 5,
+            cycles: 4
+        }
+    );
+    assert_eq!(
+        costs.get(&Command::Nop),
+        Cost {
+            energy: -1,
+            cycles: 1
+        }
+    );
+    assert_eq!(costs.get(&Command::Eatsun), Cost::default());
+    let json = serde_json::to_string(&costs).unwrap();
+    assert!(json.contains("\"powv\""), "{json}");
+    assert_eq!(serde_json::from_str::<CostTable>(&json).unwrap(), costs);
+    assert!(serde_json::from_str::<CostTable>(r#"{ "fly": {} }"#).is_err());
+
+    // the command that starts within the budget is executed even if it exceeds it
+    let mut h = testing::harness(code, Rules { costs, ..rules() }, 0, 0);
+    h.tick();
+    assert_eq!(h.executed().len(), 4);
+    assert_eq!(h.info().reg_en, 100 - 2 * 5 - 50);
+}
+
+// exchanges two registers
+fn swap(bot: &mut Bot, args: &[CommandArg], _: &mut dyn WorldAccessor, _: &Rules) -> bool {
+    let [CommandArg::RwReg(a), CommandArg::RwReg(b)] = args else {
+        unreachable!()
+    };
+    let (va, vb) = (bot.get_reg((*a).into()), bot.get_reg((*b).into()));
+    bot.set_reg(*a, vb);
+    bot.set_reg(*b, va);
+    false
+}
+
+// loads the sun difference with the neighbour cell and ends the cycle
+fn sense(bot: &mut Bot, args: &[CommandArg], wa: &mut dyn WorldAccessor, _: &Rules) -> bool {
+    let [CommandArg::Dir(dir), CommandArg::RwReg(reg)] = args else {
+        unreachable!()
+    };
+    bot.set_reg(*reg, wa.get_sun_diff(*dir + bot.get_dir()));
+    true
+}
+
+fn swap_spec() -> InstructionSpec {
+    InstructionSpec::new("swap", 256, vec![ArgKind::RwReg, ArgKind::RwReg], false)
+}
+
+#[test]
+fn custom_instruction_test() {
+    let mut rules = rules();
+    rules.instructions.register(swap_spec(), swap).unwrap();
+    rules
+        .instructions
+        .register(
+            InstructionSpec::new("sense", 257, vec![ArgKind::Dir, ArgKind::RwReg], true),
+            sense,
+        )
+        .unwrap();
+    assert!(rules.instructions.register(swap_spec(), swap).is_err());
+    rules.costs.set(
+        Word::Custom(256),
+        Cost {
+            energy: 3,
+            cycles: 2,
+        },
+    );
+
+    let code = "start:\nldv ax 1\nldv bx 2\nswap ax bx\nsense front cx\njmp start\n";
+    let mut h = testing::harness(code, rules.clone(), 4, 0);
+    h.world.neighbour(Dir::Front).sun = 10;
+    h.tick();
+    let i = h.info();
+    assert_eq!((i.reg_ax, i.reg_bx, i.reg_cx), (2, 1, -6));
+    assert_eq!(h.executed().len(), 4);
+    assert_eq!(i.reg_en, 100 - 3 - 50);
+    let json = serde_json::to_string(&rules.costs).unwrap();
+    assert!(json.contains("\"op256\""), "{json}");
+
+    // unknown instructions are skipped with fo set or kill the bot
+    let genom = vec![Command::Ext(300, Vec::new()), Command::Eatsun];
+    let mut h = testing::Harness::new(testing::MockWorld::new(10, 0), rules.clone(), genom.clone());
+    h.tick();
+    assert!(h.info().flag_fo);
+    assert_eq!(h.executed(), &genom[..]);
+    let rules = Rules {
+        on_fault: Fault::Kill,
+        ..rules
+    };
+    let mut h = testing::Harness::new(testing::MockWorld::new(10, 0), rules, genom);
+    h.tick();
+    assert!(!h.is_live());
+
+    // the world accepts the genomes with the registered instructions only
+    let genom = vec![
+        Command::Ext(
+            256,
+            vec![CommandArg::RwReg(RwReg::Ax), CommandArg::RwReg(RwReg::Bx)],
+        ),
+        Command::Eatsun,
+    ];
+    let packed = code_packer::to_b32(&genom).unwrap();
+    let mut world = util::make_world(CONFIG).unwrap();
+    assert!(world.spawn((0usize, 0).into(), &packed).is_err());
+    world.register_instruction(swap_spec(), swap).unwrap();
+    world.spawn((0usize, 0).into(), &packed).unwrap();
+    world.update().unwrap();
+}